@@ -1,74 +1,1767 @@
 use crate::board::*;
+use crate::dtm::{Dtm, DtmTables};
 use crate::evaluate::*;
 use crate::move_generator::*;
 use crate::player::Player;
+use crate::score::Score;
+use crate::see::*;
+use crate::syzygy::{Tablebases, Wdl, MAX_PIECES};
+use crate::tt::*;
 use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::fmt;
+use std::thread;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+const MAX_PLY: usize = 64;
+const DEFAULT_DEPTH: i32 = 4;
+// initial half-width of the aspiration window, doubled on each fail-high/fail-low
+const ASPIRATION_WINDOW: i32 = 10;
+// iterations running longer than this start reporting the root move being searched
+const CURRMOVE_DELAY: Duration = Duration::from_millis(1000);
+// kept on the clock for the GUI/engine communication
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+// the stop signal and the clock are only looked at every that many nodes
+const CHECK_INTERVAL: u64 = 1024;
+// singular extensions are only tried that far from the horizon
+const SINGULAR_DEPTH: i32 = 6;
+// how much the other moves must fall short of the TT move, per ply of depth
+const SINGULAR_MARGIN: i32 = 2;
+// lines searched by a strength limited engine to pick its move from
+const WEAK_CANDIDATES: usize = 4;
+
+/// Flags raised by the GUI while a search is running.
+#[derive(Debug, Default)]
+pub struct Signals {
+    /// Abort the search, the best move of the last completed iteration being returned.
+    pub stop: AtomicBool,
+    /// Cleared on `ponderhit`: the predicted move was played, the clock is running.
+    pub ponder: AtomicBool,
+}
+
+/// Principal variation starting with one of the root moves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PvLine {
+    pub score: Score,
+    pub pv: Vec<GenMove>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    /// None when checkmated or stalemated.
+    pub best: Option<GenMove>,
+    /// Expected reply, to think about on the opponent's time.
+    pub ponder: Option<GenMove>,
+    pub score: Score,
+    /// Best lines of the last completed iteration, as many as the MultiPV setting.
+    pub lines: Vec<PvLine>,
+}
+
+/// Constraints of a search, as given by the UCI `go` command.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchLimits {
+    /// Search until stopped.
+    pub infinite: bool,
+    /// Search until `ponderhit`, then switch to the time control.
+    pub ponder: bool,
+    pub movetime: Option<Duration>,
+    pub wtime: Option<Duration>,
+    pub btime: Option<Duration>,
+    pub winc: Option<Duration>,
+    pub binc: Option<Duration>,
+    pub movestogo: Option<u32>,
+    /// Search exactly that many plies, unless stopped or out of time before.
+    pub depth: Option<i32>,
+    /// Stop after searching that many nodes, using the main thread only.
+    pub nodes: Option<u64>,
+    /// Look for a mate in that many moves, stopping once found.
+    pub mate: Option<u32>,
+    /// Only search these root moves, all of them when empty.
+    pub searchmoves: Vec<GenMove>,
+}
+
+impl SearchLimits {
+    /// Time to spend on the move for `c`, `None` if the search isn't timed.
+    fn time_budget(&self, c: Color) -> Option<Duration> {
+        if self.infinite {
+            return None;
+        }
+        if self.movetime.is_some() {
+            return self.movetime;
+        }
+        let (time, inc) = c.map((self.wtime, self.winc), (self.btime, self.binc));
+        let time = time?;
+        let moves_to_go = std::cmp::max(self.movestogo.unwrap_or(30), 1);
+        let budget = time / moves_to_go + inc.unwrap_or_default() * 3 / 4;
+        Some(std::cmp::min(budget, time.saturating_sub(MOVE_OVERHEAD)))
+    }
+}
+
+/// Playing strength limit, as set by `UCI_LimitStrength` and `UCI_Elo`.
+///
+/// The engine searches shallower and fewer nodes the lower the Elo, then
+/// picks its move at random among the lines close to the best one, or
+/// sometimes among clearly worse ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Strength {
+    pub elo: u32,
+}
+
+impl Strength {
+    pub const MIN_ELO: u32 = 800;
+    pub const MAX_ELO: u32 = 2400;
+
+    // 0 at MIN_ELO, MAX_ELO - MIN_ELO at full strength
+    fn level(&self) -> u32 {
+        self.elo.clamp(Self::MIN_ELO, Self::MAX_ELO) - Self::MIN_ELO
+    }
+
+    fn max_depth(&self) -> i32 {
+        1 + self.level() as i32 / 200
+    }
+
+    fn max_nodes(&self) -> u64 {
+        200 << (self.level() / 160)
+    }
+
+    /// Lines scoring at most that much below the best one are as good a choice.
+    fn margin(&self) -> i32 {
+        (Self::MAX_ELO - Self::MIN_ELO - self.level()) as i32 / 50
+    }
+
+    /// Probability of picking any of the candidate lines.
+    fn mistake_rate(&self) -> f64 {
+        f64::from(Self::MAX_ELO - Self::MIN_ELO - self.level()) / 8000.0
+    }
+}
+
+/// Progress report of a running search, displayed as a UCI `info` line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchInfo {
+    Iteration {
+        depth: i32,
+        seldepth: usize,
+        /// Rank of the line, from 1.
+        multipv: usize,
+        score: Score,
+        nodes: u64,
+        /// Positions found in the tablebases.
+        tbhits: u64,
+        time: Duration,
+        pv: Vec<GenMove>,
+    },
+    CurrMove {
+        depth: i32,
+        mov: GenMove,
+        number: usize,
+    },
+}
+
+impl fmt::Display for SearchInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SearchInfo::Iteration {
+                depth,
+                seldepth,
+                multipv,
+                score,
+                nodes,
+                tbhits,
+                time,
+                pv,
+            } => {
+                let ms = time.as_millis() as u64;
+                let nps = nodes * 1000 / std::cmp::max(ms, 1);
+                write!(
+                    f,
+                    "info depth {} seldepth {} multipv {} score {} nodes {} nps {} tbhits {} time {} pv",
+                    depth, seldepth, multipv, score, nodes, nps, tbhits, ms
+                )?;
+                for m in pv {
+                    write!(f, " {}", m)?;
+                }
+                Ok(())
+            }
+            SearchInfo::CurrMove { depth, mov, number } => write!(
+                f,
+                "info depth {} currmove {} currmovenumber {}",
+                depth, mov, number
+            ),
+        }
+    }
+}
+
+type InfoHandler = dyn FnMut(&SearchInfo) + Send;
+
+/// Outcome of the iterative deepening of one search thread.
+struct ThreadResult {
+    /// Deepest completed iteration, 0 if none completed.
+    depth: i32,
+    seldepth: usize,
+    lines: Vec<PvLine>,
+    /// Root moves, best first.
+    moves: Vec<GenMove>,
+}
+
+/// State of one search thread. The transposition table and the signals are
+/// shared by all the threads, the move ordering tables are not.
+struct Searcher {
+    // 0 for the main thread, which handles the clock and the reports
+    id: usize,
+    eval: Box<dyn Evaluator>,
+    tt: Arc<TranspositionTable>,
+    killers: [[Option<GenMove>; 2]; MAX_PLY],
+    history: Box<[[[i32; 64]; 64]; 2]>,
+    // triangular PV table: pv[ply] holds the best line found from ply
+    pv: Vec<Vec<GenMove>>,
+    // number of root moves searched for their own line
+    multipv: usize,
+    // number of those lines reported, as set by the MultiPV option
+    shown_lines: usize,
+    // line being searched, the moves of the previous lines being excluded
+    pv_index: usize,
+    // keys of the positions before the current node: the game, then the search path
+    keys: Vec<u64>,
+    // moves from the root to the current node
+    path: Vec<GenMove>,
+    // move skipped at each ply, while checking that the TT move is singular
+    excluded: [Option<GenMove>; MAX_PLY + 1],
+    // depth of the current iteration, extensions stop at twice that ply
+    root_depth: i32,
+    // positions of `keys` played before the root
+    game_len: usize,
+    // plies since the last capture or pawn move
+    rule50: usize,
+    // score of a draw for the side to move at the root
+    contempt: i32,
+    nodes: u64,
+    // nodes already added to `total_nodes`
+    flushed_nodes: u64,
+    total_nodes: Arc<AtomicU64>,
+    seldepth: usize,
+    start: Instant,
+    // when the clock started running, after `ponderhit` when pondering
+    clock_start: Instant,
+    budget: Option<Duration>,
+    deadline: Option<Instant>,
+    pondering: bool,
+    signals: Arc<Signals>,
+    // raised by the main thread to stop the helpers once it is done
+    abort: Arc<AtomicBool>,
+    stopped: bool,
+    node_limit: Option<u64>,
+    // moves of the mate to look for
+    mate_limit: Option<u32>,
+    tablebases: Option<Arc<Tablebases>>,
+    // most pieces of the tables probed, as set by the SyzygyProbeLimit option
+    tb_probe_limit: usize,
+    // most pieces of the positions probed in the current search, 0 for none
+    tb_cardinality: usize,
+    tb_hits: Arc<AtomicU64>,
+    // distance to mate tables, probed before the Syzygy ones
+    endgames: Option<Arc<DtmTables>>,
+    on_info: Option<Box<InfoHandler>>,
+}
+
+impl Searcher {
+    fn new(
+        id: usize,
+        eval: Box<dyn Evaluator>,
+        tt: Arc<TranspositionTable>,
+        signals: Arc<Signals>,
+        abort: Arc<AtomicBool>,
+        total_nodes: Arc<AtomicU64>,
+    ) -> Self {
+        Searcher {
+            id,
+            eval,
+            tt,
+            killers: [[None; 2]; MAX_PLY],
+            history: Box::new([[[0; 64]; 64]; 2]),
+            pv: vec![Vec::new(); MAX_PLY + 1],
+            multipv: 1,
+            shown_lines: 1,
+            pv_index: 0,
+            keys: Vec::new(),
+            path: Vec::new(),
+            excluded: [None; MAX_PLY + 1],
+            root_depth: 0,
+            game_len: 0,
+            rule50: 0,
+            contempt: 0,
+            nodes: 0,
+            flushed_nodes: 0,
+            total_nodes,
+            seldepth: 0,
+            start: Instant::now(),
+            clock_start: Instant::now(),
+            budget: None,
+            deadline: None,
+            pondering: false,
+            signals,
+            abort,
+            stopped: false,
+            node_limit: None,
+            mate_limit: None,
+            tablebases: None,
+            tb_probe_limit: MAX_PIECES,
+            tb_cardinality: 0,
+            tb_hits: Arc::new(AtomicU64::new(0)),
+            // set by the engine, which generates the built-in ones at startup
+            endgames: None,
+            on_info: None,
+        }
+    }
+
+    /// Starts the clock once the GUI confirmed the pondered move was played.
+    fn check_ponderhit(&mut self) {
+        if self.pondering && !self.signals.ponder.load(Ordering::Relaxed) {
+            self.pondering = false;
+            self.clock_start = Instant::now();
+            self.deadline = self.budget.map(|t| self.clock_start + t);
+        }
+    }
+
+    fn check_stop(&mut self) -> bool {
+        if self.node_limit.is_some_and(|n| self.nodes > n) {
+            self.stopped = true;
+        }
+        if !self.stopped && self.nodes.is_multiple_of(CHECK_INTERVAL) {
+            self.flush_nodes();
+            self.check_ponderhit();
+            self.stopped = self.signals.stop.load(Ordering::Relaxed)
+                || self.abort.load(Ordering::Relaxed)
+                || self.deadline.is_some_and(|d| Instant::now() >= d);
+        }
+        self.stopped
+    }
+
+    fn flush_nodes(&mut self) {
+        self.total_nodes.fetch_add(self.nodes - self.flushed_nodes, Ordering::Relaxed);
+        self.flushed_nodes = self.nodes;
+    }
+
+    fn report(&mut self, info: SearchInfo) {
+        if let Some(ref mut f) = self.on_info {
+            f(&info);
+        }
+    }
+
+    fn update_pv(&mut self, ply: usize, m: &GenMove) {
+        let (head, tail) = self.pv.split_at_mut(ply + 1);
+        head[ply].clear();
+        head[ply].push(*m);
+        head[ply].extend_from_slice(&tail[0]);
+    }
+
+    fn evaluate(&mut self, b: &Board, c: Color) -> Score {
+        Score::cp(self.eval.evaluate(b, c))
+    }
+
+    /// Draw score for the side to move at `ply`: the root side sees a draw
+    /// as `-contempt`, so that a positive contempt avoids draws.
+    fn draw_score(&self, ply: usize) -> Score {
+        if ply.is_multiple_of(2) {
+            Score(-self.contempt)
+        } else {
+            Score(self.contempt)
+        }
+    }
+
+    /// Whether the position `key` at the current node repeats: once is
+    /// enough inside the search tree, the game needs a threefold repetition.
+    fn is_repetition(&self, key: u64) -> bool {
+        let n = self.keys.len();
+        let mut count = 0;
+        // only the positions with the same side to move, since the last irreversible move
+        let mut i = n.checked_sub(2);
+        while let Some(j) = i {
+            if n - j > self.rule50 {
+                break;
+            }
+            if self.keys[j] == key {
+                if j >= self.game_len {
+                    return true;
+                }
+                count += 1;
+                if count == 2 {
+                    return true;
+                }
+            }
+            i = j.checked_sub(2);
+        }
+        false
+    }
+
+    /// Records the position `key` before searching the move `m` from it,
+    /// returning the state to give back to `undo_move`.
+    fn do_move(&mut self, key: u64, b: &Board, child: &Board, c: Color, m: &GenMove) -> usize {
+        let rule50 = self.rule50;
+        let pawn = b.get_pc_board(Piece::Pawn, c) & (1 << m.from.0) != 0;
+        self.rule50 = if pawn || m.flags.contains(Flags::CAPTURE) {
+            0
+        } else {
+            rule50 + 1
+        };
+        self.keys.push(key);
+        self.path.push(*m);
+        self.eval.make_move(b, child, m);
+        rule50
+    }
+
+    fn undo_move(&mut self, rule50: usize) {
+        self.keys.pop();
+        self.path.pop();
+        self.eval.unmake_move();
+        self.rule50 = rule50;
+    }
+
+    /// Exact score of `b` from the distance to mate tables.
+    fn probe_endgames(&mut self, b: &Board, c: Color, ply: usize) -> Option<Score> {
+        let tables = self.endgames.as_ref()?;
+        if b.all().count_ones() as usize > tables.max_pieces() {
+            return None;
+        }
+        let dtm = tables.probe(b, c)?;
+        self.tb_hits.fetch_add(1, Ordering::Relaxed);
+        Some(dtm.score(ply).unwrap_or_else(|| self.draw_score(ply)))
+    }
+
+    /// Tablebase score of `b` right after a capture or a pawn move, and the
+    /// bound it gives on the result of the search.
+    fn probe_tablebases(&mut self, b: &Board, c: Color, ply: usize) -> Option<(Score, Bound)> {
+        if self.rule50 != 0 || b.all().count_ones() as usize > self.tb_cardinality {
+            return None;
+        }
+        let wdl = self.tablebases.as_ref()?.probe_wdl(b, c)?;
+        self.tb_hits.fetch_add(1, Ordering::Relaxed);
+        Some(match wdl {
+            Wdl::Win => (Score::tb_win_in(ply), Bound::Lower),
+            Wdl::Loss => (Score::tb_loss_in(ply), Bound::Upper),
+            // draws under the 50-move rule, barely better than the others
+            wdl => (self.draw_score(ply) + wdl as i32, Bound::Exact),
+        })
+    }
+
+    /// Whether `m` is worth searching one ply deeper: a recapture on the
+    /// square of the previous capture, or a pawn push to the 7th rank (always
+    /// a passed pawn, as no enemy pawn can stand in front of it).
+    fn is_forcing(&self, b: &Board, c: Color, m: &GenMove) -> bool {
+        let recapture = m.flags.contains(Flags::CAPTURE)
+            && self
+                .path
+                .last()
+                .is_some_and(|p| p.flags.contains(Flags::CAPTURE) && p.to == m.to);
+        let pawn = b.get_pc_board(Piece::Pawn, c) & (1 << m.from.0) != 0;
+        recapture || (pawn && m.to.row() == c.map(6, 1))
+    }
+
+    /// The TT move if all the other moves fail low against a margin below its
+    /// score, with a reduced search skipping it.
+    fn singular_move(
+        &mut self,
+        b: &Board,
+        c: Color,
+        depth: i32,
+        ply: usize,
+        entry: Option<TtEntry>,
+    ) -> Option<GenMove> {
+        let entry = entry?;
+        let tt_move = entry.mov?;
+        let tt_score = Score::from_tt(entry.score, ply);
+        if depth < SINGULAR_DEPTH
+            || self.excluded[ply].is_some()
+            || entry.depth < depth - 3
+            || entry.bound == Bound::Upper
+            || tt_score.is_mate()
+        {
+            return None;
+        }
+        let singular_beta = tt_score - SINGULAR_MARGIN * depth;
+        self.excluded[ply] = Some(tt_move);
+        let score = self.search(b, c, (depth - 1) / 2, singular_beta - 1, singular_beta, ply);
+        self.excluded[ply] = None;
+        if score < singular_beta {
+            Some(tt_move)
+        } else {
+            None
+        }
+    }
+
+    fn move_order_key(
+        &self,
+        b: &Board,
+        c: Color,
+        ply: usize,
+        tt_move: Option<GenMove>,
+        m: &GenMove,
+    ) -> i32 {
+        if tt_move.is_some_and(|t| t.is_same(m)) {
+            2_000_000
+        } else if m.flags.contains(Flags::CAPTURE) {
+            // MVV-LVA: most valuable victim first, least valuable attacker as tie-break
+            let victim = b.at_pos(&m.to).map_or(0, |(p, _)| p as i32);
+            let attacker = b.at_pos(&m.from).map_or(0, |(p, _)| p as i32);
+            // captures losing material come after the quiet moves
+            if see_ge(b, m, 0) {
+                1_000_000 + victim * 10 - attacker
+            } else {
+                -1_000_000 + victim * 10 - attacker
+            }
+        } else if self.killers[ply].contains(&Some(*m)) {
+            900_000
+        } else {
+            self.history[c as usize][m.from.0 as usize][m.to.0 as usize]
+        }
+    }
+
+    fn order_moves(
+        &self,
+        b: &Board,
+        c: Color,
+        ply: usize,
+        tt_move: Option<GenMove>,
+        moves: &mut [GenMove],
+    ) {
+        moves.sort_by_cached_key(|m| -self.move_order_key(b, c, ply, tt_move, m));
+    }
+
+    fn update_quiet_stats(&mut self, c: Color, ply: usize, depth: i32, m: &GenMove) {
+        if m.flags.contains(Flags::CAPTURE) {
+            return;
+        }
+        if self.killers[ply][0] != Some(*m) {
+            self.killers[ply][1] = self.killers[ply][0];
+            self.killers[ply][0] = Some(*m);
+        }
+        self.history[c as usize][m.from.0 as usize][m.to.0 as usize] += depth * depth;
+    }
+
+    /// Principal variation search: the first move is searched with the full
+    /// window, the others with a null window and re-searched if they beat alpha.
+    fn search(
+        &mut self,
+        b: &Board,
+        c: Color,
+        depth: i32,
+        alpha: Score,
+        beta: Score,
+        ply: usize,
+    ) -> Score {
+        self.nodes += 1;
+        self.seldepth = std::cmp::max(self.seldepth, ply);
+        self.pv[ply].clear();
+        if self.check_stop() {
+            return Score::DRAW;
+        }
+        let key = b.hash(c);
+        if self.rule50 >= 100 || self.is_repetition(key) {
+            return self.draw_score(ply);
+        }
+        // extensions are bounded by the ply, or a long forcing line would never end
+        let can_extend = ply < 2 * self.root_depth as usize;
+        let checked = in_check(b, c);
+        let depth = if checked && can_extend { depth + 1 } else { depth };
+        if ply >= MAX_PLY {
+            return self.evaluate(b, c);
+        }
+        if depth <= 0 {
+            return self.quiesce(b, c, alpha, beta, ply);
+        }
+
+        // mate distance pruning: no line from here beats a shorter mate found elsewhere
+        let alpha = std::cmp::max(alpha, Score::mated_in(ply));
+        let beta = std::cmp::min(beta, Score::mate_in(ply + 1));
+        if alpha >= beta {
+            return alpha;
+        }
+
+        let pv_node = beta.0 - alpha.0 > 1;
+        let excluded = self.excluded[ply];
+        let tt_entry = self.tt.probe(key);
+        if let Some(entry) = tt_entry {
+            let score = Score::from_tt(entry.score, ply);
+            if !pv_node && excluded.is_none() && entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => {}
+                }
+            }
+        }
+
+        if excluded.is_none() {
+            if let Some(score) = self.probe_endgames(b, c, ply) {
+                return score;
+            }
+        }
+
+        // a tablebase result out of the window ends the search, else bounds its result
+        let (mut tb_floor, mut tb_ceiling) = (-Score::INFINITY, Score::INFINITY);
+        let probe = if excluded.is_none() {
+            self.probe_tablebases(b, c, ply)
+        } else {
+            None
+        };
+        if let Some((score, bound)) = probe {
+            let cutoff = match bound {
+                Bound::Exact => true,
+                Bound::Lower => score >= beta,
+                Bound::Upper => score <= alpha,
+            };
+            if cutoff {
+                self.tt.store(
+                    key,
+                    TtEntry {
+                        mov: None,
+                        score: score.to_tt(ply),
+                        depth: std::cmp::min(depth + 6, MAX_PLY as i32 - 1),
+                        bound,
+                    },
+                );
+                return score;
+            } else if bound == Bound::Lower {
+                tb_floor = score;
+            } else {
+                tb_ceiling = score;
+            }
+        }
+
+        let singular = if can_extend {
+            self.singular_move(b, c, depth, ply, tt_entry)
+        } else {
+            None
+        };
+
+        let mut moves = generate_moves(b, c);
+        self.order_moves(b, c, ply, tt_entry.and_then(|e| e.mov), &mut moves);
+
+        let original_alpha = alpha;
+        let mut alpha = alpha;
+        let mut best = -Score::INFINITY;
+        let mut best_move = None;
+        let mut legal = 0;
+        for m in moves.iter() {
+            if excluded.is_some_and(|e| e.is_same(m)) {
+                continue;
+            }
+            let child = b.apply(m).unwrap();
+            if in_check(&child, c) {
+                continue;
+            }
+            legal += 1;
+            // forcing moves are only extended on the PV, where they matter most
+            let extend = can_extend
+                && (singular.is_some_and(|s| s.is_same(m)) || (pv_node && self.is_forcing(b, c, m)));
+            let new_depth = if extend { depth } else { depth - 1 };
+            let undo = self.do_move(key, b, &child, c, m);
+            let score = if legal == 1 {
+                -self.search(&child, !c, new_depth, -beta, -alpha, ply + 1)
+            } else {
+                let scout = -self.search(&child, !c, new_depth, -alpha - 1, -alpha, ply + 1);
+                if scout > alpha && scout < beta {
+                    -self.search(&child, !c, new_depth, -beta, -alpha, ply + 1)
+                } else {
+                    scout
+                }
+            };
+            self.undo_move(undo);
+            if score > best {
+                best = score;
+                best_move = Some(*m);
+                if score > alpha {
+                    alpha = score;
+                    self.update_pv(ply, m);
+                    if alpha >= beta {
+                        self.update_quiet_stats(c, ply, depth, m);
+                        break;
+                    }
+                }
+            }
+        }
+
+        if self.stopped {
+            return Score::DRAW;
+        }
+        if excluded.is_some() {
+            // the result without the best move isn't worth storing
+            return if legal == 0 { alpha } else { best };
+        }
+        if legal == 0 {
+            return if checked {
+                Score::mated_in(ply)
+            } else {
+                self.draw_score(ply)
+            };
+        }
+
+        let best = best.clamp(tb_floor, tb_ceiling);
+        let bound = if best >= beta {
+            Bound::Lower
+        } else if best > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.tt.store(
+            key,
+            TtEntry {
+                mov: best_move,
+                score: best.to_tt(ply),
+                depth,
+                bound,
+            },
+        );
+        best
+    }
+
+    /// Searches the captures until the position is quiet, so that the static
+    /// evaluation isn't taken in the middle of an exchange. The side to move
+    /// may also stand pat on the evaluation, and captures losing material
+    /// by the exchange evaluation are skipped.
+    fn quiesce(&mut self, b: &Board, c: Color, mut alpha: Score, beta: Score, ply: usize) -> Score {
+        self.nodes += 1;
+        self.seldepth = std::cmp::max(self.seldepth, ply);
+        self.pv[ply].clear();
+        if self.check_stop() {
+            return Score::DRAW;
+        }
+        let stand_pat = self.evaluate(b, c);
+        if stand_pat >= beta || ply >= MAX_PLY {
+            return stand_pat;
+        }
+        alpha = std::cmp::max(alpha, stand_pat);
+
+        let mut moves: Vec<_> = generate_moves(b, c)
+            .into_iter()
+            .filter(|m| m.flags.contains(Flags::CAPTURE) && see_ge(b, m, 0))
+            .collect();
+        self.order_moves(b, c, ply, None, &mut moves);
+        let mut best = stand_pat;
+        for m in moves.iter() {
+            let child = b.apply(m).unwrap();
+            if in_check(&child, c) {
+                continue;
+            }
+            self.eval.make_move(b, &child, m);
+            let score = -self.quiesce(&child, !c, -beta, -alpha, ply + 1);
+            self.eval.unmake_move();
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    self.update_pv(ply, m);
+                    if alpha >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// Same as `search` for the root node, returning the index of the best move in `moves`.
+    fn search_root(
+        &mut self,
+        b: &Board,
+        c: Color,
+        moves: &[GenMove],
+        depth: i32,
+        mut alpha: Score,
+        beta: Score,
+    ) -> (Score, usize) {
+        let original_alpha = alpha;
+        let mut best = -Score::INFINITY;
+        let mut best_index = 0;
+        let key = b.hash(c);
+        self.nodes += 1;
+        self.pv[0].clear();
+        for (i, m) in moves.iter().enumerate() {
+            if self.start.elapsed() > CURRMOVE_DELAY {
+                self.report(SearchInfo::CurrMove {
+                    depth,
+                    mov: *m,
+                    number: self.pv_index + i + 1,
+                });
+            }
+            let child = b.apply(m).unwrap();
+            let undo = self.do_move(key, b, &child, c, m);
+            let score = if i == 0 {
+                -self.search(&child, !c, depth - 1, -beta, -alpha, 1)
+            } else {
+                let scout = -self.search(&child, !c, depth - 1, -alpha - 1, -alpha, 1);
+                if scout > alpha && scout < beta {
+                    -self.search(&child, !c, depth - 1, -beta, -alpha, 1)
+                } else {
+                    scout
+                }
+            };
+            self.undo_move(undo);
+            if self.stopped {
+                break;
+            }
+            if score > best {
+                best = score;
+                best_index = i;
+                if score > alpha {
+                    alpha = score;
+                    self.update_pv(0, m);
+                    if alpha >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+        // the best move of a later line isn't the best move of the position
+        if !self.stopped && self.pv_index == 0 && best > original_alpha && best < beta {
+            self.tt.store(
+                key,
+                TtEntry {
+                    mov: Some(moves[best_index]),
+                    score: best,
+                    depth,
+                    bound: Bound::Exact,
+                },
+            );
+        }
+        (best, best_index)
+    }
+
+    /// Searches the root with a window centered on the previous iteration's score,
+    /// widening it on the failing side until the score falls inside.
+    fn search_aspiration(
+        &mut self,
+        b: &Board,
+        c: Color,
+        moves: &[GenMove],
+        depth: i32,
+        prev: Score,
+    ) -> (Score, usize) {
+        let mut delta = ASPIRATION_WINDOW;
+        let (mut alpha, mut beta) = if depth > 1 {
+            (prev - delta, prev + delta)
+        } else {
+            (-Score::INFINITY, Score::INFINITY)
+        };
+        loop {
+            let (score, best) = self.search_root(b, c, moves, depth, alpha, beta);
+            if self.stopped {
+                return (score, best);
+            }
+            if score <= alpha && alpha > -Score::INFINITY {
+                alpha = std::cmp::max(alpha - delta, -Score::INFINITY);
+            } else if score >= beta && beta < Score::INFINITY {
+                beta = std::cmp::min(beta + delta, Score::INFINITY);
+            } else {
+                return (score, best);
+            }
+            delta *= 2;
+        }
+    }
+}
+
+impl Searcher {
+    /// Resets the per-search state and the clock for `limits`.
+    fn start(&mut self, c: Color, limits: &SearchLimits) {
+        self.killers = [[None; 2]; MAX_PLY];
+        self.keys.truncate(self.game_len);
+        self.path.clear();
+        self.nodes = 0;
+        self.flushed_nodes = 0;
+        self.seldepth = 0;
+        self.start = Instant::now();
+        self.clock_start = self.start;
+        self.stopped = false;
+        self.pondering = limits.ponder;
+        self.budget = limits.time_budget(c);
+        self.deadline = match self.budget {
+            Some(t) if !self.pondering => Some(self.start + t),
+            _ => None,
+        };
+    }
+
+    /// Iterative deepening over the root `moves` up to `max_depth`.
+    ///
+    /// Each iteration searches `multipv` lines, the moves of the lines already
+    /// found being excluded from the next one. Helpers start every other one
+    /// at depth 2, so that the threads don't all search the same depth at the
+    /// same time.
+    fn iterate(&mut self, c: Color, b: &Board, mut moves: Vec<GenMove>, max_depth: i32) -> ThreadResult {
+        let mut result = ThreadResult {
+            depth: 0,
+            seldepth: 0,
+            lines: Vec::new(),
+            moves: Vec::new(),
+        };
+        let multipv = std::cmp::min(self.multipv, moves.len());
+        'deepening: for depth in (1 + self.id as i32 % 2)..=max_depth {
+            let mut lines = Vec::with_capacity(multipv);
+            self.root_depth = depth;
+            for pv_index in 0..multipv {
+                self.pv_index = pv_index;
+                let prev = result.lines.get(pv_index).map_or(Score::DRAW, |l: &PvLine| l.score);
+                let (score, best) = self.search_aspiration(b, c, &moves[pv_index..], depth, prev);
+                if self.stopped {
+                    break 'deepening;
+                }
+                // search the best move first in the next iteration
+                let m = moves.remove(pv_index + best);
+                moves.insert(pv_index, m);
+                lines.push(PvLine {
+                    score,
+                    pv: self.pv[0].clone(),
+                });
+            }
+            result.depth = depth;
+            result.seldepth = self.seldepth;
+            result.lines = lines;
+            if self.id == 0 {
+                self.flush_nodes();
+                self.report_lines(&result);
+            }
+            let mate = result.lines.first().and_then(|l| l.score.mate_moves());
+            if self.mate_limit.is_some_and(|n| mate.is_some_and(|m| m > 0 && m <= n as i32)) {
+                break;
+            }
+            // the next iteration would most likely not finish in time
+            self.check_ponderhit();
+            if !self.pondering && self.budget.is_some_and(|t| self.clock_start.elapsed() > t / 2) {
+                break;
+            }
+        }
+        self.flush_nodes();
+        result.moves = moves;
+        result
+    }
+
+    fn report_lines(&mut self, result: &ThreadResult) {
+        for (i, line) in result.lines.iter().take(self.shown_lines).enumerate() {
+            let info = SearchInfo::Iteration {
+                depth: result.depth,
+                seldepth: result.seldepth,
+                multipv: i + 1,
+                score: line.score,
+                nodes: self.total_nodes.load(Ordering::Relaxed),
+                tbhits: self.tb_hits.load(Ordering::Relaxed),
+                time: self.start.elapsed(),
+                pv: line.pv.clone(),
+            };
+            self.report(info);
+        }
+    }
+}
+
+/// Lazy SMP search: the main thread and its helpers run the same iterative
+/// deepening, only sharing what they find through the transposition table.
 pub struct AiPlayer {
     rng: SmallRng,
-    eval: Box<Evaluator>,
+    pub depth: i32,
+    strength: Option<Strength>,
+    // threads[0] is the main thread
+    threads: Vec<Searcher>,
 }
 
 impl AiPlayer {
     pub fn new(seed: [u8; 16]) -> Self {
-        use rand::SeedableRng;
-        // Create small, cheap to initialize and fast RNG with a random seed.
-        // The randomness is supplied by the operating system.
+        // Create small, cheap to initialize and fast RNG from the given seed,
+        // only used to pick weaker moves when the strength is limited.
         let small_rng = SmallRng::from_seed(seed);
+        let main = Searcher::new(
+            0,
+            Box::new(BasicEvaluator::default()),
+            Arc::new(TranspositionTable::new(DEFAULT_SIZE_MB)),
+            Arc::new(Signals::default()),
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicU64::new(0)),
+        );
         AiPlayer {
             rng: small_rng,
-            eval: Box::new(BasicEvaluator),
+            depth: DEFAULT_DEPTH,
+            strength: None,
+            threads: vec![main],
         }
     }
 
-    fn search(&self, depth: usize, c: Color, b: &Board, maximizing: bool) -> i32 {
-        if depth == 0 {
-            return self.eval.evaluate(b, c);
+    fn main_thread(&mut self) -> &mut Searcher {
+        &mut self.threads[0]
+    }
+
+    /// Signals read by the running search. They are never cleared by the search.
+    pub fn signals(&self) -> Arc<Signals> {
+        self.threads[0].signals.clone()
+    }
+
+    pub fn set_hash_size(&mut self, size_mb: usize) {
+        let tt = Arc::new(TranspositionTable::new(size_mb));
+        for t in self.threads.iter_mut() {
+            t.tt = tt.clone();
         }
-        let mut best = if maximizing { -9999 } else { 9999 };
-        for m in generate_moves(b, c) {
-            best = if maximizing { 
-                std::cmp::max(best, self.search(depth -1, !c, &b.apply(&m.clone().into()).unwrap(), !maximizing))
-            } else {
-                std::cmp::min(best, self.search(depth -1, !c, &b.apply(&m.clone().into()).unwrap(), !maximizing))
+    }
+
+    pub fn clear_hash(&mut self) {
+        self.threads[0].tt.clear();
+    }
+
+    /// Sets the evaluation used by all the search threads.
+    pub fn set_evaluator(&mut self, eval: Box<dyn Evaluator>) {
+        for t in self.threads.iter_mut() {
+            t.eval = eval.box_clone();
+        }
+    }
+
+    /// Sets the number of search threads, the main one included.
+    pub fn set_threads(&mut self, count: usize) {
+        let count = std::cmp::max(count, 1);
+        self.threads.truncate(count);
+        for id in self.threads.len()..count {
+            let main = &self.threads[0];
+            let mut helper = Searcher::new(
+                id,
+                main.eval.box_clone(),
+                main.tt.clone(),
+                main.signals.clone(),
+                main.abort.clone(),
+                main.total_nodes.clone(),
+            );
+            helper.multipv = main.shown_lines;
+            helper.shown_lines = main.shown_lines;
+            helper.keys = main.keys.clone();
+            helper.game_len = main.game_len;
+            helper.rule50 = main.rule50;
+            helper.contempt = main.contempt;
+            helper.tablebases = main.tablebases.clone();
+            helper.tb_probe_limit = main.tb_probe_limit;
+            helper.tb_hits = main.tb_hits.clone();
+            helper.endgames = main.endgames.clone();
+            self.threads.push(helper);
+        }
+    }
+
+    /// Sets the number of best root moves searched, each reported with its own line.
+    pub fn set_multipv(&mut self, count: usize) {
+        for t in self.threads.iter_mut() {
+            t.multipv = std::cmp::max(count, 1);
+            t.shown_lines = t.multipv;
+        }
+    }
+
+    /// Sets the positions played before the one to search, oldest first,
+    /// and the number of plies since the last capture or pawn move.
+    pub fn set_game_history(&mut self, keys: &[u64], rule50: usize) {
+        for t in self.threads.iter_mut() {
+            t.keys = keys.to_vec();
+            t.game_len = keys.len();
+            t.rule50 = rule50;
+        }
+    }
+
+    /// Sets the score of a draw for the engine, in evaluation units. A
+    /// positive contempt makes it avoid draws against weaker opponents.
+    pub fn set_contempt(&mut self, contempt: i32) {
+        for t in self.threads.iter_mut() {
+            t.contempt = contempt;
+        }
+    }
+
+    /// Sets the Syzygy tablebases probed by the search, `None` for none.
+    pub fn set_tablebases(&mut self, tablebases: Option<Arc<Tablebases>>) {
+        for t in self.threads.iter_mut() {
+            t.tablebases = tablebases.clone();
+        }
+    }
+
+    /// Only probes the tablebases of that many pieces or less.
+    pub fn set_tb_probe_limit(&mut self, pieces: usize) {
+        for t in self.threads.iter_mut() {
+            t.tb_probe_limit = pieces;
+        }
+    }
+
+    /// Sets the distance to mate tables probed by the search, `None` for none.
+    pub fn set_endgames(&mut self, endgames: Option<Arc<DtmTables>>) {
+        for t in self.threads.iter_mut() {
+            t.endgames = endgames.clone();
+        }
+    }
+
+    /// Keeps the root moves of `b` leading to the quickest mate, if its
+    /// ending is won in the distance to mate tables, and returns whether it
+    /// is in them. The other results are left to the search, which sees the
+    /// draws by repetition the tables know nothing about.
+    fn probe_root_endgames(&mut self, c: Color, b: &Board, moves: &mut Vec<GenMove>) -> bool {
+        let main = &self.threads[0];
+        let tables = match main.endgames {
+            Some(ref tables) if b.all().count_ones() as usize <= tables.max_pieces() => tables.clone(),
+            _ => return false,
+        };
+        let (best, dtm) = match tables.best_moves(b, c) {
+            Some(found) => found,
+            None => return false,
+        };
+        main.tb_hits.fetch_add(moves.len() as u64, Ordering::Relaxed);
+        if let Dtm::Win(_) = dtm {
+            if moves.iter().any(|m| best.contains(m)) {
+                moves.retain(|m| best.contains(m));
+            }
+        }
+        true
+    }
+
+    /// Keeps the root moves preserving the tablebase result of `b`, and
+    /// returns the most pieces of the positions to probe in the search:
+    /// none once the DTZ tables picked the root moves, or when the root
+    /// isn't won anyway.
+    fn probe_root(&mut self, c: Color, b: &Board, moves: &mut Vec<GenMove>) -> usize {
+        let main = &self.threads[0];
+        let tb = match main.tablebases {
+            Some(ref tb) => tb.clone(),
+            None => return 0,
+        };
+        let cardinality = std::cmp::min(tb.max_pieces(), main.tb_probe_limit);
+        if b.all().count_ones() as usize > cardinality {
+            return cardinality;
+        }
+        let probed = moves.len() as u64;
+        match tb.filter_root_moves(b, c, main.rule50, moves) {
+            Some((wdl, dtz)) => {
+                main.tb_hits.fetch_add(probed, Ordering::Relaxed);
+                if dtz || wdl <= Wdl::Draw {
+                    0
+                } else {
+                    cardinality
+                }
+            }
+            None => cardinality,
+        }
+    }
+
+    /// Limits the playing strength, `None` playing at full strength. A limited
+    /// search only uses the main thread, so that it is reproducible.
+    pub fn set_strength(&mut self, strength: Option<Strength>) {
+        self.strength = strength;
+    }
+
+    /// Reseeds the random generator used by the strength limit.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = SmallRng::seed_from_u64(seed);
+    }
+
+    /// Index of the line played by a strength limited engine.
+    fn pick_weaker_line(&mut self, strength: Strength, lines: &[PvLine]) -> usize {
+        let best = lines[0].score;
+        if self.rng.gen_bool(strength.mistake_rate()) {
+            // a realistic mistake doesn't walk into a mate the best line avoids
+            let candidates = lines
+                .iter()
+                .take_while(|l| !l.score.is_mate() || l.score > Score::DRAW || best < Score::DRAW)
+                .count();
+            return self.rng.gen_range(0, std::cmp::max(candidates, 1));
+        }
+        let candidates = lines
+            .iter()
+            .take_while(|l| l.score >= best - strength.margin())
+            .count();
+        self.rng.gen_range(0, candidates)
+    }
+
+    /// Sets the callback receiving the search progress reports.
+    pub fn on_info<F: FnMut(&SearchInfo) + Send + 'static>(&mut self, f: F) {
+        self.main_thread().on_info = Some(Box::new(f));
+    }
+
+    /// Reply to `best` expected by the principal variation, or else by the
+    /// transposition table.
+    fn ponder_move(&self, c: Color, b: &Board, best: &GenMove, pv: &[GenMove]) -> Option<GenMove> {
+        if pv.len() > 1 && pv[0] == *best {
+            return Some(pv[1]);
+        }
+        let child = b.apply(best)?;
+        let reply = self.threads[0].tt.probe(child.hash(!c))?.mov?;
+        generate_legal_moves(&child, !c)
+            .into_iter()
+            .find(|m| m.is_same(&reply))
+    }
+
+    /// Iterative deepening search of the best move for `c`, up to `depth` plies
+    /// unless `limits` sets a time control.
+    ///
+    /// The helpers search until the main thread is done, as deep at most, the
+    /// deepest completed iteration of all the threads giving the move played.
+    /// Fixed depth, node and mate searches run on the main thread alone, to be
+    /// reproducible.
+    pub fn think(&mut self, c: Color, b: &Board, limits: &SearchLimits) -> SearchResult {
+        let mut moves = generate_legal_moves(b, c);
+        if moves.is_empty() {
+            // checkmate or stalemate, there is nothing to search
+            let score = if in_check(b, c) { Score::mated_in(0) } else { Score::DRAW };
+            return SearchResult {
+                best: None,
+                ponder: None,
+                score,
+                lines: Vec::new(),
             };
         }
-        best
+        if moves.iter().any(|m| limits.searchmoves.iter().any(|s| s.is_same(m))) {
+            moves.retain(|m| limits.searchmoves.iter().any(|s| s.is_same(m)));
+        }
+        self.threads[0].tb_hits.store(0, Ordering::Relaxed);
+        let tb_cardinality = if self.probe_root_endgames(c, b, &mut moves) {
+            0
+        } else {
+            self.probe_root(c, b, &mut moves)
+        };
+        let tt_move = self.threads[0].tt.probe(b.hash(c)).and_then(|e| e.mov);
+        self.threads[0].order_moves(b, c, 0, tt_move, &mut moves);
+        let helper_limits = SearchLimits {
+            infinite: true,
+            ..SearchLimits::default()
+        };
+        for t in self.threads.iter_mut() {
+            t.start(c, if t.id == 0 { limits } else { &helper_limits });
+            t.tb_cardinality = tb_cardinality;
+        }
+        self.threads[0].total_nodes.store(0, Ordering::Relaxed);
+        self.threads[0].abort.store(false, Ordering::Relaxed);
+        let unbounded = limits.infinite
+            || limits.ponder
+            || limits.nodes.is_some()
+            || self.threads[0].budget.is_some();
+        // the mated side must still be searched to see it has no move
+        let mate_depth = limits.mate.map(|mate| 2 * mate as i32);
+        let max_depth = match (limits.depth, mate_depth) {
+            (Some(depth), Some(mate_depth)) => std::cmp::min(depth, mate_depth),
+            (Some(depth), None) | (None, Some(depth)) => depth,
+            (None, None) if unbounded => MAX_PLY as i32 - 1,
+            (None, None) => self.depth,
+        };
+        let mut max_depth = max_depth.clamp(1, MAX_PLY as i32 - 1);
+        let strength = self.strength;
+        {
+            let main = &mut self.threads[0];
+            main.multipv = main.shown_lines;
+            main.node_limit = limits.nodes;
+            main.mate_limit = limits.mate;
+            if let Some(strength) = strength {
+                max_depth = std::cmp::min(max_depth, strength.max_depth());
+                let nodes = strength.max_nodes();
+                main.node_limit = Some(main.node_limit.map_or(nodes, |n| n.min(nodes)));
+                main.multipv = std::cmp::max(main.multipv, WEAK_CANDIDATES);
+            }
+        }
+
+        let (main, helpers) = self.threads.split_first_mut().unwrap();
+        // node counts and fixed depths are only reproducible on a single thread
+        let single_thread = strength.is_some()
+            || limits.nodes.is_some()
+            || limits.depth.is_some()
+            || limits.mate.is_some();
+        let helpers = if single_thread { &mut [][..] } else { helpers };
+        let mut results = thread::scope(|s| {
+            let handles: Vec<_> = helpers
+                .iter_mut()
+                .map(|h| {
+                    let moves = moves.clone();
+                    s.spawn(move || h.iterate(c, b, moves, max_depth))
+                })
+                .collect();
+            let mut results = vec![main.iterate(c, b, moves.clone(), max_depth)];
+            main.abort.store(true, Ordering::Relaxed);
+            results.extend(handles.into_iter().map(|h| h.join().unwrap()));
+            results
+        });
+
+        // deepest iteration first, the main thread winning ties
+        let mut chosen = 0;
+        for (i, r) in results.iter().enumerate().skip(1) {
+            let best = &results[chosen];
+            let score = |r: &ThreadResult| r.lines.first().map(|l| l.score);
+            if r.depth > best.depth || (r.depth == best.depth && score(r) > score(best)) {
+                chosen = i;
+            }
+        }
+        let mut result = results.swap_remove(chosen);
+        if chosen != 0 {
+            main.report_lines(&result);
+        }
+        let shown_lines = main.shown_lines;
+        let played = match strength {
+            Some(strength) if !result.lines.is_empty() => self.pick_weaker_line(strength, &result.lines),
+            _ => 0,
+        };
+        let (best, score, pv) = match result.lines.get(played) {
+            Some(line) => (line.pv[0], line.score, &line.pv[..]),
+            // stopped before the first iteration completed
+            None => (result.moves.first().cloned().unwrap_or(moves[0]), Score::DRAW, &[][..]),
+        };
+        let ponder = self.ponder_move(c, b, &best, pv);
+        result.lines.truncate(shown_lines);
+        SearchResult {
+            best: Some(best),
+            ponder,
+            score,
+            lines: result.lines,
+        }
     }
 }
 
 impl Player for AiPlayer {
     fn get_move(&mut self, c: Color, b: &Board) -> GenMove {
-        // use rand::prelude::SliceRandom;
-        let mut moves = generate_moves(b, c)
+        self.think(c, b, &SearchLimits::default())
+            .best
+            .expect("no legal move to play")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negamax(ai: &mut Searcher, b: &Board, c: Color, depth: i32, ply: usize) -> Score {
+        if depth == 0 {
+            let inf = Score::INFINITY;
+            return ai.quiesce(b, c, -inf, inf, ply);
+        }
+        let moves = generate_legal_moves(b, c);
+        if moves.is_empty() {
+            return if in_check(b, c) {
+                Score::mated_in(ply)
+            } else {
+                Score::DRAW
+            };
+        }
+        moves
             .iter()
-            .map(|m| {
-                (
-                    m.clone(),
-                    self.search(3, !c, &b.apply(&m.clone().into()).unwrap(), false),
-                )
+            .map(|m| -negamax(ai, &b.apply(m).unwrap(), !c, depth - 1, ply + 1))
+            .max()
+            .unwrap()
+    }
+
+    fn search_score(fen: &str, depth: i32) -> Score {
+        let (b, c) = parse_fen_color(&mut fen.chars()).unwrap();
+        let mut ai = AiPlayer::new([0; 16]);
+        ai.depth = depth;
+        let infos = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = infos.clone();
+        ai.on_info(move |info| sink.lock().unwrap().push(info.clone()));
+        ai.get_move(c, &b);
+        let last = infos.lock().unwrap().last().cloned();
+        match last {
+            Some(SearchInfo::Iteration { score, .. }) => score,
+            _ => panic!("no iteration reported"),
+        }
+    }
+
+    #[test]
+    fn pvs_matches_negamax() {
+        // few enough pieces for the full width negamax with quiescence leaves
+        let b = parse_fen("4r1k1/pp3ppp/2n5/3qp3/3P4/2N5/PP3PPP/3QR1K1 b - - 0 20").unwrap();
+        for depth in 1..=3 {
+            let mut ai = AiPlayer::new([0; 16]);
+            let ai = ai.main_thread();
+            let expected = negamax(ai, &b, Color::Black, depth, 0);
+            let mut moves = generate_legal_moves(&b, Color::Black);
+            ai.order_moves(&b, Color::Black, 0, None, &mut moves);
+            let (score, _) = ai.search_aspiration(&b, Color::Black, &moves, depth, Score::DRAW);
+            assert_eq!(expected, score, "depth {}", depth);
+        }
+    }
+
+    #[test]
+    fn mate_distance() {
+        assert_eq!(Score::mate_in(1), search_score("7k/8/5K2/8/8/8/8/6Q1 w", 2));
+        // Kh7 is forced, then Qg7#
+        assert_eq!(Score::mated_in(2), search_score("7k/8/5K2/8/8/8/8/6Q1 b", 3));
+    }
+
+    #[test]
+    fn no_legal_move() {
+        let mut ai = AiPlayer::new([0; 16]);
+        let ai = ai.main_thread();
+        let (inf, ply) = (Score::INFINITY, 3);
+        let mated = parse_fen("7k/6Q1/5K2/8/8/8/8/8 b").unwrap();
+        let mate = ai.search(&mated, Color::Black, 2, -inf, inf, ply);
+        assert_eq!(Score::mated_in(ply), mate);
+        let stalemate = parse_fen("k7/2Q5/1K6/8/8/8/8/8 b").unwrap();
+        assert_eq!(Score::DRAW, ai.search(&stalemate, Color::Black, 2, -inf, inf, ply));
+    }
+
+    #[test]
+    fn think_without_legal_move() {
+        let mut ai = AiPlayer::new([0; 16]);
+        let limits = SearchLimits::default();
+        let mated = parse_fen("7k/6Q1/5K2/8/8/8/8/8 b").unwrap();
+        let result = ai.think(Color::Black, &mated, &limits);
+        assert_eq!((None, Score::mated_in(0)), (result.best, result.score));
+        let stalemate = parse_fen("k7/2Q5/1K6/8/8/8/8/8 b").unwrap();
+        let result = ai.think(Color::Black, &stalemate, &limits);
+        assert_eq!((None, Score::DRAW), (result.best, result.score));
+    }
+
+    #[test]
+    fn reports_iterations_with_pv() {
+        use std::sync::{Arc, Mutex};
+
+        let b = parse_fen("4k3/8/8/3q4/8/8/3R4/4K3 w").unwrap();
+        let mut ai = AiPlayer::new([0; 16]);
+        let infos = Arc::new(Mutex::new(Vec::new()));
+        let sink = infos.clone();
+        ai.on_info(move |info| sink.lock().unwrap().push(info.clone()));
+        let best = ai.get_move(Color::White, &b);
+
+        let infos = infos.lock().unwrap();
+        assert_eq!(ai.depth as usize, infos.len());
+        for (i, info) in infos.iter().enumerate() {
+            match info {
+                SearchInfo::Iteration { depth, seldepth, pv, .. } => {
+                    assert_eq!(i as i32 + 1, *depth);
+                    assert!(*seldepth >= *depth as usize);
+                    assert!(!pv.is_empty());
+                }
+                _ => panic!("unexpected {:?}", info),
+            }
+        }
+        match infos.last() {
+            Some(SearchInfo::Iteration { pv, .. }) => assert_eq!(best, pv[0]),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn info_line() {
+        let info = SearchInfo::Iteration {
+            depth: 3,
+            seldepth: 5,
+            multipv: 1,
+            score: Score::cp(-20),
+            nodes: 5000,
+            tbhits: 12,
+            time: Duration::from_millis(250),
+            pv: vec!["e2e4".parse().unwrap(), "e7e5".parse().unwrap()],
+        };
+        assert_eq!(
+            "info depth 3 seldepth 5 multipv 1 score cp -20 nodes 5000 nps 20000 tbhits 12 time 250 pv e2e4 e7e5",
+            info.to_string()
+        );
+    }
+
+    #[test]
+    fn time_budget() {
+        let ms = Duration::from_millis;
+        let limits = SearchLimits {
+            wtime: Some(ms(60_000)),
+            btime: Some(ms(3_000)),
+            binc: Some(ms(1_000)),
+            movestogo: Some(20),
+            ..SearchLimits::default()
+        };
+        assert_eq!(Some(ms(3_000)), limits.time_budget(Color::White));
+        assert_eq!(Some(ms(900)), limits.time_budget(Color::Black));
+        let movetime = SearchLimits {
+            movetime: Some(ms(500)),
+            ..limits.clone()
+        };
+        assert_eq!(Some(ms(500)), movetime.time_budget(Color::White));
+        assert_eq!(None, SearchLimits::default().time_budget(Color::White));
+    }
+
+    #[test]
+    fn stops_infinite_search() {
+        let b = Board::new_start();
+        let mut ai = AiPlayer::new([0; 16]);
+        let signals = ai.signals();
+        let limits = SearchLimits {
+            infinite: true,
+            ..SearchLimits::default()
+        };
+        let search = std::thread::spawn(move || ai.think(Color::White, &b, &limits));
+        std::thread::sleep(Duration::from_millis(100));
+        signals.stop.store(true, Ordering::Relaxed);
+        let m = search.join().unwrap().best.unwrap();
+        assert!(generate_legal_moves(&Board::new_start(), Color::White).contains(&m));
+    }
+
+    #[test]
+    fn ponderhit_starts_the_clock() {
+        let b = Board::new_start();
+        let mut ai = AiPlayer::new([0; 16]);
+        let signals = ai.signals();
+        signals.ponder.store(true, Ordering::Relaxed);
+        let limits = SearchLimits {
+            ponder: true,
+            movetime: Some(Duration::from_millis(20)),
+            ..SearchLimits::default()
+        };
+        let search = std::thread::spawn(move || ai.think(Color::White, &b, &limits));
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(!search.is_finished());
+        signals.ponder.store(false, Ordering::Relaxed);
+        let result = search.join().unwrap();
+        let reply = result.ponder.unwrap();
+        let child = Board::new_start().apply(&result.best.unwrap()).unwrap();
+        assert!(generate_legal_moves(&child, Color::Black).contains(&reply));
+    }
+
+    #[test]
+    fn helpers_share_the_search() {
+        let b = parse_fen("4k3/8/8/3q4/8/8/3R4/4K3 w").unwrap();
+        let mut ai = AiPlayer::new([0; 16]);
+        ai.set_threads(4);
+        let nodes = std::sync::Arc::new(std::sync::Mutex::new(0));
+        let sink = nodes.clone();
+        ai.on_info(move |info| {
+            if let SearchInfo::Iteration { nodes, .. } = info {
+                *sink.lock().unwrap() = *nodes;
+            }
+        });
+        assert_eq!("d2d5", ai.get_move(Color::White, &b).to_string());
+        // every thread searched, and the report counts all of them
+        assert!(ai.threads.iter().all(|t| t.nodes > 0));
+        assert!(*nodes.lock().unwrap() >= ai.threads[0].nodes);
+
+        ai.set_threads(2);
+        assert_eq!(2, ai.threads.len());
+        assert_eq!(1, ai.threads[1].id);
+    }
+
+    #[test]
+    fn multipv_lines() {
+        use std::sync::{Arc, Mutex};
+
+        let b = parse_fen("4k3/8/8/3q4/8/8/3R4/4K3 w").unwrap();
+        let mut ai = AiPlayer::new([0; 16]);
+        ai.set_multipv(3);
+        let infos = Arc::new(Mutex::new(Vec::new()));
+        let sink = infos.clone();
+        ai.on_info(move |info| sink.lock().unwrap().push(info.clone()));
+        let result = ai.think(Color::White, &b, &SearchLimits::default());
+
+        assert_eq!(3, result.lines.len());
+        assert_eq!("d2d5", result.lines[0].pv[0].to_string());
+        assert_eq!(result.best.unwrap(), result.lines[0].pv[0]);
+        assert!(result.lines[1..].iter().all(|l| l.score < result.lines[0].score));
+        assert_ne!(result.lines[1].pv[0], result.lines[2].pv[0]);
+        assert!(result.lines[1..].iter().all(|l| l.pv[0] != result.best.unwrap()));
+
+        let infos = infos.lock().unwrap();
+        assert_eq!(3 * ai.depth as usize, infos.len());
+        let ranks: Vec<_> = infos[infos.len() - 3..]
+            .iter()
+            .map(|info| match info {
+                SearchInfo::Iteration { multipv, .. } => *multipv,
+                _ => panic!("unexpected {:?}", info),
             })
-            .collect::<Vec<(GenMove, i32)>>();
-        moves.sort_by_key(|x| x.1);
-        info!("all {} moves\r\n{:#?}", moves.len(), moves);
-        moves.iter().last().cloned().unwrap().0
-        // let m = moves.choose(&mut self.rng).cloned().unwrap();
-        // m
+            .collect();
+        assert_eq!(vec![1, 2, 3], ranks);
+    }
+
+    #[test]
+    fn fifty_move_rule() {
+        let b = parse_fen("4k3/8/8/8/8/8/8/R3K3 w").unwrap();
+        let mut ai = AiPlayer::new([0; 16]);
+        ai.set_game_history(&[], 99);
+        let result = ai.think(Color::White, &b, &SearchLimits::default());
+        assert_eq!(Score::DRAW, result.score);
+        ai.set_contempt(20);
+        let result = ai.think(Color::White, &b, &SearchLimits::default());
+        assert_eq!(Score(-20), result.score);
+        ai.set_game_history(&[], 0);
+        let result = ai.think(Color::White, &b, &SearchLimits::default());
+        assert!(result.score > Score::cp(40));
     }
-}
 
-// pub struct MoveBank {
+    #[test]
+    fn threefold_repetition() {
+        // black is a rook down, but Kd8 repeats a position for the third time
+        let b = parse_fen("4k3/8/8/8/8/8/8/R3K3 b").unwrap();
+        let kd8 = b.apply(&"e8d8".parse().unwrap()).unwrap().hash(Color::White);
+        let mut ai = AiPlayer::new([0; 16]);
+        ai.set_game_history(&[1, kd8, 2, kd8], 4);
+        let result = ai.think(Color::Black, &b, &SearchLimits::default());
+        assert_eq!("e8d8", result.best.unwrap().to_string());
+        assert_eq!(Score::DRAW, result.score);
 
-// }
+        // a single earlier occurrence isn't a draw yet
+        ai.set_game_history(&[1, kd8], 2);
+        let result = ai.think(Color::Black, &b, &SearchLimits::default());
+        assert!(result.score < Score::DRAW);
+    }
 
-// impl MoveBank {
-//     pub fn new() -> Self {
-//         MoveBank {
+    #[test]
+    fn tablebase_probes() {
+        use std::sync::{Arc, Mutex};
 
-//         }
-//     }
+        // the capture of the knight reaches a won KPvK, found in the tree
+        let b = parse_fen("8/8/8/3n4/3KP3/8/8/6k1 w").unwrap();
+        let mut ai = AiPlayer::new([0; 16]);
+        ai.depth = 3;
+        ai.set_endgames(None);
+        ai.set_tablebases(Some(::syzygy::tests::tablebases()));
+        let infos = Arc::new(Mutex::new(Vec::new()));
+        let sink = infos.clone();
+        ai.on_info(move |info| sink.lock().unwrap().push(info.clone()));
+        let result = ai.think(Color::White, &b, &SearchLimits::default());
+        assert!(result.score > Score::cp(100_000) && !result.score.is_mate());
+        match infos.lock().unwrap().last() {
+            Some(SearchInfo::Iteration { tbhits, .. }) => assert!(*tbhits > 0),
+            info => panic!("unexpected {:?}", info),
+        }
+        ai.set_tb_probe_limit(2);
+        let result = ai.think(Color::White, &b, &SearchLimits::default());
+        assert!(result.score < Score::cp(100_000));
 
-//     pub fn get_moves_for(&self, _c: Color, _p: Piece, _pos: Pos) {
+        // at the root, only the moves keeping the quickest win are searched
+        let b = parse_fen("4k3/8/8/8/8/8/4P3/4K3 w").unwrap();
+        ai.set_tb_probe_limit(MAX_PIECES);
+        let tb = ::syzygy::tests::tablebases();
+        let mut moves = generate_legal_moves(&b, Color::White);
+        tb.filter_root_moves(&b, Color::White, 0, &mut moves);
+        let result = ai.think(Color::White, &b, &SearchLimits::default());
+        assert!(moves.contains(&result.best.unwrap()), "{}", result.best.unwrap());
+    }
 
-//     }
-// }
+    #[test]
+    fn endgame_tables() {
+        // the tables find the quickest mate at any depth
+        let b = parse_fen("8/8/8/3k4/8/8/8/R5K1 w").unwrap();
+        let tables = DtmTables::builtin();
+        let (best, dtm) = tables.best_moves(&b, Color::White).unwrap();
+        let mut ai = AiPlayer::new([0; 16]);
+        ai.set_endgames(Some(tables));
+        ai.depth = 1;
+        let result = ai.think(Color::White, &b, &SearchLimits::default());
+        assert!(best.contains(&result.best.unwrap()), "{}", result.best.unwrap());
+        assert_eq!(dtm.score(0), Some(result.score));
+
+        ai.set_endgames(None);
+        let result = ai.think(Color::White, &b, &SearchLimits::default());
+        assert!(!result.score.is_mate());
+    }
+
+    #[test]
+    fn strength_limit() {
+        let b = Board::new_start();
+        let play = |elo, seed| {
+            let mut ai = AiPlayer::new([0; 16]);
+            ai.set_strength(Some(Strength { elo }));
+            ai.set_seed(seed);
+            ai.think(Color::White, &b, &SearchLimits::default()).best.unwrap()
+        };
+        // reproducible for a given seed, but not always the same move
+        assert_eq!(play(800, 7), play(800, 7));
+        let moves: Vec<_> = (0..10).map(|seed| play(800, seed)).collect();
+        assert!(moves.iter().any(|m| *m != moves[0]));
+
+        // never gives away the queen at full strength
+        let b = parse_fen("4k3/8/8/3q4/8/8/3R4/4K3 w").unwrap();
+        let mut ai = AiPlayer::new([0; 16]);
+        ai.set_strength(Some(Strength { elo: Strength::MAX_ELO }));
+        for seed in 0..5 {
+            ai.set_seed(seed);
+            let result = ai.think(Color::White, &b, &SearchLimits::default());
+            assert_eq!("d2d5", result.best.unwrap().to_string());
+            assert_eq!(1, result.lines.len());
+        }
+        assert!(Strength { elo: 1000 }.max_nodes() < Strength { elo: 2000 }.max_nodes());
+        assert_eq!(0.0, Strength { elo: 3000 }.mistake_rate());
+    }
+
+    #[test]
+    fn search_limits() {
+        use std::sync::{Arc, Mutex};
+
+        let search_threads = |fen: &str, limits: SearchLimits, threads: usize| {
+            let (b, c) = parse_fen_color(&mut fen.chars()).unwrap();
+            let mut ai = AiPlayer::new([0; 16]);
+            ai.set_threads(threads);
+            let infos = Arc::new(Mutex::new(Vec::new()));
+            let sink = infos.clone();
+            ai.on_info(move |info| sink.lock().unwrap().push(info.clone()));
+            let result = ai.think(c, &b, &limits);
+            let infos = infos.lock().unwrap().clone();
+            // without the currmove reports of slow searches
+            let infos: Vec<_> = infos.into_iter().filter(|i| matches!(i, SearchInfo::Iteration { .. })).collect();
+            (result, infos)
+        };
+        let search = |fen: &str, limits: SearchLimits| search_threads(fen, limits, 1);
+        let queen = "4k3/8/8/3q4/8/8/3R4/4K3 w";
+
+        let depth = SearchLimits { depth: Some(6), ..SearchLimits::default() };
+        let (result, infos) = search(queen, depth.clone());
+        assert_eq!(6, infos.len());
+        assert_eq!(result, search_threads(queen, depth, 4).0);
+
+        let nodes = SearchLimits { nodes: Some(3000), ..SearchLimits::default() };
+        let (result, infos) = search(queen, nodes.clone());
+        assert_eq!(result, search(queen, nodes).0);
+        match infos.last() {
+            Some(SearchInfo::Iteration { nodes, .. }) => assert!(*nodes <= 3000),
+            _ => panic!("no iteration reported"),
+        }
+
+        let mate = SearchLimits { mate: Some(2), ..SearchLimits::default() };
+        let (result, infos) = search("7k/8/5K2/8/8/8/8/6Q1 w", mate);
+        assert_eq!(Score::mate_in(1), result.score);
+        // found by the first iteration, thanks to the check extension
+        assert_eq!(1, infos.len());
+        // searched deeper than the default depth when there is no mate
+        let mate = SearchLimits { mate: Some(3), ..SearchLimits::default() };
+        assert_eq!(6, search(queen, mate).1.len());
+
+        let searchmoves = SearchLimits {
+            searchmoves: vec!["e1e2".parse().unwrap(), "d2d4".parse().unwrap()],
+            ..SearchLimits::default()
+        };
+        let (result, _) = search(queen, searchmoves);
+        assert_eq!("e1e2", result.best.unwrap().to_string());
+    }
+
+    #[test]
+    fn extensions() {
+        let mut ai = AiPlayer::new([0; 16]);
+        let ai = ai.main_thread();
+        let m = |s: &str, flags| {
+            let m: GenMove = s.parse().unwrap();
+            GenMove::new(m.from, m.to, flags)
+        };
+        let b = parse_fen("4k3/8/4P3/3p4/2N1B3/8/8/4K3 w").unwrap();
+        assert!(ai.is_forcing(&b, Color::White, &m("e6e7", Flags::NONE)));
+        assert!(!ai.is_forcing(&b, Color::White, &m("c4d5", Flags::CAPTURE)));
+        ai.path.push(m("d6d5", Flags::CAPTURE));
+        assert!(ai.is_forcing(&b, Color::White, &m("c4d5", Flags::CAPTURE)));
+        assert!(!ai.is_forcing(&b, Color::White, &m("c4d6", Flags::NONE)));
+
+        // a one ply search sees the mate through the check extension
+        let b = parse_fen("7k/8/5K2/8/8/8/8/6Q1 w").unwrap();
+        ai.path.clear();
+        ai.root_depth = 1;
+        let inf = Score::INFINITY;
+        let child = b.apply(&"g1g7".parse().unwrap()).unwrap();
+        assert_eq!(Score::mated_in(1), ai.search(&child, Color::Black, 0, -inf, inf, 1));
+    }
+
+    #[test]
+    fn quiescence_sees_recaptures() {
+        // at depth 1, only the quiescence search sees exd5 after Qxd5
+        let b = parse_fen("4k3/8/4p3/3p4/8/8/8/3QK3 w").unwrap();
+        let mut ai = AiPlayer::new([0; 16]);
+        ai.depth = 1;
+        let result = ai.think(Color::White, &b, &SearchLimits::default());
+        assert_ne!("d1d5", result.best.unwrap().to_string());
+        // still a queen against two pawns
+        assert!(result.score > Score::cp(50));
+    }
+
+    #[test]
+    fn takes_hanging_queen() {
+        let b = parse_fen("4k3/8/8/3q4/8/8/3R4/4K3 w").unwrap();
+        // the network of "nnue" comes from a file, tested on its own
+        for name in EVALUATORS.iter().filter(|&&name| name != "nnue") {
+            let mut ai = AiPlayer::new([0; 16]);
+            ai.set_evaluator(by_name(name, &EvalParams::default()).unwrap());
+            assert_eq!("d2d5", ai.get_move(Color::White, &b).to_string(), "{}", name);
+        }
+    }
+}
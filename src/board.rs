@@ -22,16 +22,16 @@ impl std::ops::Not for Color {
 
 impl Color {
     pub fn rev(&self) -> Color {
-        match self {
-            &Color::White => Color::Black,
-            &Color::Black => Color::White,
+        match *self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
         }
     }
 
     pub fn map<T>(&self, white: T, black: T) -> T {
-        match self {
-            &Color::White => white,
-            &Color::Black => black,
+        match *self {
+            Color::White => white,
+            Color::Black => black,
         }
     }
 }
@@ -97,6 +97,34 @@ impl PartialBoard {
     }
 }
 
+struct ZobristKeys {
+    pieces: [[[u64; 64]; 6]; 2],
+    black_to_move: u64,
+}
+
+lazy_static! {
+    static ref ZOBRIST: ZobristKeys = {
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+        // fixed seed so hashes are stable from one run to the next
+        let mut rng = SmallRng::seed_from_u64(0x5EED);
+        let mut pieces = [[[0u64; 64]; 6]; 2];
+        for color in pieces.iter_mut() {
+            for piece in color.iter_mut() {
+                for key in piece.iter_mut() {
+                    *key = rng.gen();
+                }
+            }
+        }
+        ZobristKeys {
+            pieces,
+            black_to_move: rng.gen(),
+        }
+    };
+}
+
+type CellFormatter = dyn Fn(Option<(Piece, Color)>, &mut fmt::Formatter);
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Board {
     pub white: PartialBoard,
@@ -142,6 +170,39 @@ impl Board {
         self.white.all() | self.black.all()
     }
 
+    /// Zobrist key of the position with `side` to move.
+    pub fn hash(&self, side: Color) -> u64 {
+        use crate::Color::*;
+        use crate::Piece::*;
+        let mut h = if side == Black { ZOBRIST.black_to_move } else { 0 };
+        for c in &[White, Black] {
+            for p in &[Pawn, Knight, Bishop, Rook, Queen, King] {
+                let keys = &ZOBRIST.pieces[*c as usize][*p as usize];
+                let mut bb = self.get_pc_board(*p, *c);
+                while bb != 0 {
+                    h ^= keys[bb.trailing_zeros() as usize];
+                    bb &= bb - 1;
+                }
+            }
+        }
+        h
+    }
+
+    /// Zobrist key of the pawns alone, indexing the pawn structure cache.
+    pub fn pawn_hash(&self) -> u64 {
+        use crate::Color::*;
+        let mut h = 0;
+        for c in &[White, Black] {
+            let keys = &ZOBRIST.pieces[*c as usize][Piece::Pawn as usize];
+            let mut bb = self.get_pc_board(Piece::Pawn, *c);
+            while bb != 0 {
+                h ^= keys[bb.trailing_zeros() as usize];
+                bb &= bb - 1;
+            }
+        }
+        h
+    }
+
     pub fn apply(&self, m: &GenMove) -> Option<Board> {
         let (x, y) = m.from.pos();
         let (tx, ty) = m.to.pos();
@@ -167,10 +228,10 @@ impl Board {
     }
 
     pub fn set(u: &mut u64, x: u8, y: u8) {
-        *u = *u | (1u64 << (y * 8 + x))
+        *u |= 1u64 << (y * 8 + x)
     }
     pub fn unset(u: &mut u64, x: u8, y: u8) {
-        *u = *u ^ (1u64 << (y * 8 + x))
+        *u ^= 1u64 << (y * 8 + x)
     }
     pub fn has(u: u64, x: u8, y: u8) -> bool {
         u & (1u64 << (y * 8 + x)) != 0u64
@@ -257,7 +318,7 @@ impl Board {
     fn fmt_f(
         &self,
         f: &mut fmt::Formatter,
-        ffn: &Fn(Option<(Piece, Color)>, &mut fmt::Formatter) -> (),
+        ffn: &CellFormatter,
     ) -> fmt::Result {
         let b = self.hydrate();
         write!(f, "  ")?;
@@ -265,7 +326,7 @@ impl Board {
             use std::char;
             write!(f, "{}", char::from_u32('a' as u32 + x as u32).unwrap())?;
         }
-        write!(f, "\n")?;
+        writeln!(f)?;
         for yy in 0u8..8u8 {
             let y = 7 - yy;
             write!(f, "{}|", y)?;
@@ -273,7 +334,7 @@ impl Board {
                 let i: usize = (y * 8 + x) as usize;
                 ffn(b[i], f);
             }
-            write!(f, "|\n")?;
+            writeln!(f, "|")?;
         }
         Ok(())
     }
@@ -284,21 +345,21 @@ impl fmt::Display for Board {
         use crate::Color::*;
         use crate::Piece::*;
         self.fmt_f(f, &|a, f| match a {
-            None => write!(f, "{}", " ").unwrap(),
-
-            Some((Pawn, Black)) => write!(f, "{}", "\u{2659}").unwrap(),
-            Some((Knight, Black)) => write!(f, "{}", "\u{2658}").unwrap(),
-            Some((Bishop, Black)) => write!(f, "{}", "\u{2657}").unwrap(),
-            Some((Rook, Black)) => write!(f, "{}", "\u{2656}").unwrap(),
-            Some((Queen, Black)) => write!(f, "{}", "\u{2655}").unwrap(),
-            Some((King, Black)) => write!(f, "{}", "\u{2654}").unwrap(),
-
-            Some((Pawn, White)) => write!(f, "{}", "\u{265F}").unwrap(),
-            Some((Knight, White)) => write!(f, "{}", "\u{265E}").unwrap(),
-            Some((Bishop, White)) => write!(f, "{}", "\u{265D}").unwrap(),
-            Some((Rook, White)) => write!(f, "{}", "\u{265C}").unwrap(),
-            Some((Queen, White)) => write!(f, "{}", "\u{265B}").unwrap(),
-            Some((King, White)) => write!(f, "{}", "\u{265A}").unwrap(),
+            None => write!(f, " ").unwrap(),
+
+            Some((Pawn, Black)) => write!(f, "\u{2659}").unwrap(),
+            Some((Knight, Black)) => write!(f, "\u{2658}").unwrap(),
+            Some((Bishop, Black)) => write!(f, "\u{2657}").unwrap(),
+            Some((Rook, Black)) => write!(f, "\u{2656}").unwrap(),
+            Some((Queen, Black)) => write!(f, "\u{2655}").unwrap(),
+            Some((King, Black)) => write!(f, "\u{2654}").unwrap(),
+
+            Some((Pawn, White)) => write!(f, "\u{265F}").unwrap(),
+            Some((Knight, White)) => write!(f, "\u{265E}").unwrap(),
+            Some((Bishop, White)) => write!(f, "\u{265D}").unwrap(),
+            Some((Rook, White)) => write!(f, "\u{265C}").unwrap(),
+            Some((Queen, White)) => write!(f, "\u{265B}").unwrap(),
+            Some((King, White)) => write!(f, "\u{265A}").unwrap(),
         })
     }
 }
@@ -320,7 +381,7 @@ where
         *col += 1;
     }
 
-    while let Some(ch) = it.next() {
+    for ch in it.by_ref() {
         // println!("    char {}", ch);
         match ch {
             '/' => {
@@ -341,7 +402,7 @@ where
             'B' => set(&mut b, Bishop, White, &mut c, r),
             'N' => set(&mut b, Knight, White, &mut c, r),
             'P' => set(&mut b, Pawn, White, &mut c, r),
-            d if d.is_digit(10) => {
+            d if d.is_ascii_digit() => {
                 let skip = d.to_digit(10).unwrap() as u8;
                 // println!("  skip {}", skip);
                 c += skip;
@@ -357,11 +418,27 @@ where
 
     Some((b, c))
 }
+#[allow(dead_code)]
 pub fn parse_fen(s: &str) -> Option<Board> {
     let mut it = s.chars().peekable();
     parse_fen_color(&mut it).map(|x| x.0)
 }
 
+#[test]
+fn test_hash() {
+    let b = Board::new_start();
+    assert_ne!(b.hash(Color::White), b.hash(Color::Black));
+    // transposed move orders reach the same key
+    let m = |s: &str| s.parse::<GenMove>().unwrap();
+    let b1 = b.apply(&m("g1f3")).unwrap().apply(&m("g8f6")).unwrap().apply(&m("b1c3")).unwrap();
+    let b2 = b.apply(&m("b1c3")).unwrap().apply(&m("g8f6")).unwrap().apply(&m("g1f3")).unwrap();
+    assert_eq!(b1.hash(Color::Black), b2.hash(Color::Black));
+    assert_ne!(b.hash(Color::White), b1.hash(Color::White));
+    // piece moves leave the pawn key alone
+    assert_eq!(b.pawn_hash(), b1.pawn_hash());
+    assert_ne!(b.pawn_hash(), b.apply(&m("e2e4")).unwrap().pawn_hash());
+}
+
 #[test]
 fn test_fen() {
     let fen = "r1bqkbnr/pp6/2n3p1/3ppp1p/2Pp1P1P/1P4P1/P1N1P3/R1BQKBNR b KQkq - 1 12";
@@ -369,6 +446,7 @@ fn test_fen() {
     println!("{}", b);
 }
 
+#[allow(dead_code)]
 pub fn parse(s: &str) -> Option<Board> {
     //KQRBNP
     use crate::Color::*;
@@ -377,7 +455,7 @@ pub fn parse(s: &str) -> Option<Board> {
     let mut y = 7;
     for l in s.lines() {
         // skip empty lines
-        if l.len() == 0 {
+        if l.is_empty() {
             continue;
         }
         let mut x = 0;
@@ -0,0 +1,951 @@
+use board::*;
+use endgame::signature;
+use move_generator::*;
+use score::Score;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+/// Most pieces of a generated table, kings included.
+pub const MAX_PIECES: usize = 4;
+
+const MAGIC: [u8; 4] = *b"DTM1";
+
+/// Endings generated in memory once at startup, so that the engine plays
+/// them perfectly without any file.
+const BUILTIN: [&str; 3] = ["KQK", "KRK", "KPK"];
+
+/// Squares of the a1-d1-d4 triangle, where the symmetries of a pawnless
+/// position can bring the white king.
+const TRIANGLE: [usize; 10] = [0, 1, 2, 3, 9, 10, 11, 18, 19, 27];
+
+/// Distance to mate of a position in plies, for the side to move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dtm {
+    Win(u32),
+    Loss(u32),
+    Draw,
+}
+
+impl Dtm {
+    // stored as the plies plus one, 0 being a draw
+    fn from_value(v: u8) -> Dtm {
+        match v {
+            0 => Dtm::Draw,
+            v if v % 2 == 0 => Dtm::Win(v as u32 - 1),
+            v => Dtm::Loss(v as u32 - 1),
+        }
+    }
+
+    /// Search score of a position `ply` plies from the root.
+    pub fn score(self, ply: usize) -> Option<Score> {
+        match self {
+            Dtm::Win(n) => Some(Score::mate_in(ply + n as usize)),
+            Dtm::Loss(n) => Some(Score::mated_in(ply + n as usize)),
+            Dtm::Draw => None,
+        }
+    }
+
+    /// Ordering of the moves reaching a position of this value for the
+    /// opponent: the quickest wins first, the longest losses last.
+    fn rank_after(self) -> (i32, i32) {
+        match self {
+            Dtm::Loss(n) => (2, -(n as i32)),
+            Dtm::Draw => (1, 0),
+            Dtm::Win(n) => (0, n as i32),
+        }
+    }
+}
+
+impl fmt::Display for Dtm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Dtm::Win(n) => write!(f, "mate in {}", n.div_ceil(2)),
+            Dtm::Loss(0) => write!(f, "mated"),
+            Dtm::Loss(n) => write!(f, "mated in {}", n / 2),
+            Dtm::Draw => write!(f, "draw"),
+        }
+    }
+}
+
+/// Material of an ending, such as `KRKP` for a king and rook against a king
+/// and pawn, White having the first king.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Material {
+    white: String,
+    black: String,
+}
+
+const LETTERS: [(char, Piece); 5] = [
+    ('Q', Piece::Queen),
+    ('R', Piece::Rook),
+    ('B', Piece::Bishop),
+    ('N', Piece::Knight),
+    ('P', Piece::Pawn),
+];
+
+/// Counts of the pieces of an ending besides the kings, four bits for each
+/// kind in the order of `LETTERS`, Black's above White's: the tables are
+/// found without allocating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Key(u64);
+
+impl Key {
+    fn of(b: &Board) -> Key {
+        let mut key = 0;
+        for (i, &(_, p)) in LETTERS.iter().enumerate() {
+            key |= (b.get_pc_board(p, Color::White).count_ones() as u64) << (4 * i);
+            key |= (b.get_pc_board(p, Color::Black).count_ones() as u64) << (4 * (i + LETTERS.len()));
+        }
+        Key(key)
+    }
+
+    fn swapped(self) -> Key {
+        let bits = 4 * LETTERS.len();
+        Key(self.0 >> bits | (self.0 & ((1 << bits) - 1)) << bits)
+    }
+
+    fn count(self, color: Color, letter: usize) -> u64 {
+        self.0 >> (4 * (letter + color.map(0, LETTERS.len()))) & 0xf
+    }
+
+    /// No mate is possible with at most a minor piece each.
+    fn is_trivial_draw(self) -> bool {
+        [Color::White, Color::Black].iter().all(|&c| {
+            let count = |l| self.count(c, l);
+            count(0) + count(1) + count(4) == 0 && count(2) + count(3) <= 1
+        })
+    }
+}
+
+fn letter_piece(letter: char) -> Option<Piece> {
+    LETTERS.iter().find(|&&(l, _)| l == letter).map(|&(_, p)| p)
+}
+
+// pieces strongest first, as in the signatures
+fn normalize(side: &str) -> Option<String> {
+    let mut pieces: Vec<usize> = side
+        .chars()
+        .map(|l| LETTERS.iter().position(|&(x, _)| x == l))
+        .collect::<Option<_>>()?;
+    pieces.sort_unstable();
+    Some(std::iter::once('K').chain(pieces.into_iter().map(|i| LETTERS[i].0)).collect())
+}
+
+impl Material {
+    pub fn parse(name: &str) -> Result<Material, String> {
+        let name = name.to_uppercase();
+        let invalid = || format!("invalid ending {}, expected kings and pieces as in KRKP", name);
+        let mut sides = name.split('K');
+        if !sides.next().is_some_and(|s| s.is_empty()) {
+            return Err(invalid());
+        }
+        let (white, black) = match (sides.next(), sides.next(), sides.next()) {
+            (Some(white), Some(black), None) => (white, black),
+            _ => return Err(invalid()),
+        };
+        let material = Material {
+            white: normalize(white).ok_or_else(invalid)?,
+            black: normalize(black).ok_or_else(invalid)?,
+        };
+        if material.pieces().len() > MAX_PIECES {
+            return Err(format!("{} has more than {} pieces", material, MAX_PIECES));
+        }
+        Ok(material)
+    }
+
+    pub fn of(b: &Board) -> Material {
+        Material {
+            white: signature(b, Color::White),
+            black: signature(b, Color::Black),
+        }
+    }
+
+    fn key(&self) -> Key {
+        let mut key = 0;
+        for (i, side) in [&self.white, &self.black].iter().enumerate() {
+            for l in side.chars().skip(1) {
+                let letter = LETTERS.iter().position(|&(x, _)| x == l).unwrap();
+                key += 1 << (4 * (letter + i * LETTERS.len()));
+            }
+        }
+        Key(key)
+    }
+
+    /// Pieces in the order of the index: the kings, then the other white
+    /// and black pieces.
+    fn pieces(&self) -> Vec<(Piece, Color)> {
+        let others = |side: &str, c: Color| -> Vec<(Piece, Color)> {
+            side.chars().skip(1).filter_map(|l| letter_piece(l).map(|p| (p, c))).collect()
+        };
+        let mut pieces = vec![(Piece::King, Color::White), (Piece::King, Color::Black)];
+        pieces.extend(others(&self.white, Color::White));
+        pieces.extend(others(&self.black, Color::Black));
+        pieces
+    }
+
+    fn has_pawns(&self) -> bool {
+        self.white.contains('P') || self.black.contains('P')
+    }
+
+    /// No mate is possible with at most a minor piece each.
+    #[cfg(test)]
+    fn is_trivial_draw(&self) -> bool {
+        self.key().is_trivial_draw()
+    }
+
+    /// Endings reached by a capture or a promotion.
+    fn successors(&self) -> Vec<Material> {
+        let mut found = Vec::new();
+        for &c in [Color::White, Color::Black].iter() {
+            let (side, other) = c.map((&self.white, &self.black), (&self.black, &self.white));
+            let mut reached = Vec::new();
+            for (i, l) in side.char_indices().skip(1) {
+                let rest = format!("{}{}", &side[..i], &side[i + 1..]);
+                reached.push(rest.clone());
+                if l == 'P' {
+                    reached.extend("QRBN".chars().map(|p| format!("{}{}", rest, p)));
+                }
+            }
+            for s in reached {
+                let s = normalize(&s[1..]).unwrap();
+                let m = c.map(
+                    Material {
+                        white: s.clone(),
+                        black: other.to_string(),
+                    },
+                    Material {
+                        white: other.to_string(),
+                        black: s,
+                    },
+                );
+                if !found.contains(&m) {
+                    found.push(m);
+                }
+            }
+        }
+        found
+    }
+}
+
+impl fmt::Display for Material {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.white, self.black)
+    }
+}
+
+/// Board with the colors swapped, White moving up the board as Black did.
+fn flip_colors(b: &Board) -> Board {
+    let flip = |p: &PartialBoard| PartialBoard {
+        pawns: p.pawns.swap_bytes(),
+        knights: p.knights.swap_bytes(),
+        bishops: p.bishops.swap_bytes(),
+        rooks: p.rooks.swap_bytes(),
+        queens: p.queens.swap_bytes(),
+        king: p.king.swap_bytes(),
+    };
+    Board {
+        white: flip(&b.black),
+        black: flip(&b.white),
+    }
+}
+
+fn transpose(sq: usize) -> usize {
+    (sq % 8) * 8 + sq / 8
+}
+
+/// Squares a pawn of `c` on `sq` can have come from without capturing.
+fn pawn_retreats(sq: usize, c: Color, occupied: u64) -> u64 {
+    let (back, second, fourth) = c.map((8, 1, 3), (-8, 6, 4));
+    let row = sq as i32 / 8;
+    let from = sq as i32 - back;
+    if row == second || occupied & 1 << from != 0 {
+        return 0;
+    }
+    let mut squares = 1 << from;
+    if row == fourth && occupied & 1 << (from - back) == 0 {
+        squares |= 1 << (from - back);
+    }
+    squares
+}
+
+/// Distance to mate of every position of an ending, for both sides to move.
+///
+/// The white king is brought to the a-d files by a mirror, and without pawns
+/// to the a1-d1-d4 triangle by the symmetries of the board, the lowest index
+/// of the symmetric positions being kept. The other pieces take any square.
+pub struct Table {
+    material: Material,
+    pieces: Vec<(Piece, Color)>,
+    // squares of the white king, by index
+    region: Vec<usize>,
+    values: Vec<u8>,
+}
+
+impl Table {
+    fn new(material: Material) -> Table {
+        let region = if material.has_pawns() {
+            (0..64).filter(|sq| sq % 8 < 4).collect()
+        } else {
+            TRIANGLE.to_vec()
+        };
+        Table {
+            pieces: material.pieces(),
+            material,
+            region,
+            values: Vec::new(),
+        }
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn size(&self) -> usize {
+        2 * self.region.len() * 64usize.pow(self.pieces.len() as u32 - 1)
+    }
+
+    /// Squares of the pieces in their order, the first `pieces.len()` ones
+    /// used.
+    fn squares(&self, b: &Board) -> [usize; MAX_PIECES] {
+        let mut squares = [0; MAX_PIECES];
+        let mut n = 0;
+        for (i, &(p, c)) in self.pieces.iter().enumerate() {
+            if i == 0 || self.pieces[i - 1] != (p, c) {
+                for sq in CaseIterator::new(b.get_pc_board(p, c)) {
+                    squares[n] = sq.0 as usize;
+                    n += 1;
+                }
+            }
+        }
+        squares
+    }
+
+    fn index_of(&self, c: Color, squares: &mut [usize]) -> usize {
+        // pieces of a kind in increasing squares
+        let mut start = 0;
+        for i in 1..=squares.len() {
+            if i == squares.len() || self.pieces[i] != self.pieces[start] {
+                squares[start..i].sort_unstable();
+                start = i;
+            }
+        }
+        let king = self.region.iter().position(|&sq| sq == squares[0]).unwrap();
+        let idx = c.map(0, 1) * self.region.len() + king;
+        squares[1..].iter().fold(idx, |idx, &sq| idx * 64 + sq)
+    }
+
+    /// Index of `b` with `c` to move, the same for all its symmetric images.
+    fn index(&self, b: &Board, c: Color) -> usize {
+        let squares = self.squares(b);
+        let king = squares[0];
+        let mut mirror = if king % 8 > 3 { 7 } else { 0 };
+        if !self.material.has_pawns() && king / 8 > 3 {
+            mirror |= 56;
+        }
+        let n = self.pieces.len();
+        let mut squares = squares.map(|sq| sq ^ mirror);
+        let king = squares[0];
+        if self.material.has_pawns() || king / 8 < king % 8 {
+            return self.index_of(c, &mut squares[..n]);
+        }
+        let mut transposed = squares.map(transpose);
+        let flipped = self.index_of(c, &mut transposed[..n]);
+        if king / 8 > king % 8 {
+            flipped
+        } else {
+            std::cmp::min(flipped, self.index_of(c, &mut squares[..n]))
+        }
+    }
+
+    /// Legal position with `c` to move: no pawn on the first or last rank,
+    /// and the side that just moved not in check.
+    fn is_valid(b: &Board, c: Color) -> bool {
+        (b.white.pawns | b.black.pawns) & 0xff00_0000_0000_00ff == 0 && !in_check(b, !c)
+    }
+
+    /// Position of `idx`, if it is legal and the index of its symmetries.
+    fn position(&self, idx: usize) -> Option<(Board, Color)> {
+        let mut rest = idx;
+        let mut squares = vec![0; self.pieces.len()];
+        for sq in squares[1..].iter_mut().rev() {
+            *sq = rest % 64;
+            rest /= 64;
+        }
+        squares[0] = self.region[rest % self.region.len()];
+        let c = if rest < self.region.len() { Color::White } else { Color::Black };
+        let mut b = Board::empty();
+        for (&(p, color), &sq) in self.pieces.iter().zip(squares.iter()) {
+            if b.all() & 1 << sq != 0 {
+                return None;
+            }
+            *b.get_pc_board_mut(p, color) |= 1 << sq;
+        }
+        if Self::is_valid(&b, c) && self.index(&b, c) == idx {
+            Some((b, c))
+        } else {
+            None
+        }
+    }
+
+    /// Positions with `!c` to move from which a move without capture nor
+    /// promotion reaches `b`.
+    fn predecessors(&self, b: &Board, c: Color) -> Vec<usize> {
+        use crate::Piece::*;
+        let mover = !c;
+        let occupied = b.all();
+        let mut found = Vec::new();
+        for &p in [Pawn, Knight, Bishop, Rook, Queen, King].iter() {
+            for to in CaseIterator::new(b.get_pc_board(p, mover)) {
+                let from = if p == Pawn {
+                    pawn_retreats(to.0 as usize, mover, occupied)
+                } else {
+                    piece_attacks(p, to, occupied) & !occupied
+                };
+                for from in CaseIterator::new(from) {
+                    let mut before = b.clone();
+                    *before.get_pc_board_mut(p, mover) ^= 1 << to.0 | 1 << from.0;
+                    if Self::is_valid(&before, mover) {
+                        found.push(self.index(&before, mover));
+                    }
+                }
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+
+    /// Retrograde analysis of `material`, the endings reached by a capture
+    /// or a promotion being probed in `tables`.
+    ///
+    /// Positions are settled by increasing distance: a mated side loses in
+    /// 0 plies, a move to a loss in `n` plies wins in `n + 1`, and a
+    /// position loses in `n + 1` plies once all its moves reach wins, `n`
+    /// being the longest of them.
+    pub fn generate(material: Material, tables: &DtmTables) -> Result<Table, String> {
+        let mut table = Table::new(material);
+        let size = table.size();
+        // moves within the ending to positions not known to be won yet
+        let mut remaining = vec![0u8; size];
+        // a capture or promotion draws: never lost
+        let mut escapes = vec![false; size];
+        // quickest win found so far, which a quiet move can still shorten
+        let mut best_win = vec![u32::MAX; size];
+        // longest loss through a capture or promotion, plus one
+        let mut exit_loss = vec![0u32; size];
+        let mut queue: Vec<Vec<u32>> = Vec::new();
+        let push = |queue: &mut Vec<Vec<u32>>, plies: u32, idx: usize| {
+            if queue.len() <= plies as usize {
+                queue.resize(plies as usize + 1, Vec::new());
+            }
+            queue[plies as usize].push(idx as u32);
+        };
+
+        for idx in 0..size {
+            let (b, c) = match table.position(idx) {
+                Some(position) => position,
+                None => continue,
+            };
+            let moves = generate_legal_moves(&b, c);
+            if moves.is_empty() && in_check(&b, c) {
+                push(&mut queue, 0, idx);
+                continue;
+            }
+            let mut children = Vec::new();
+            let mut win = None;
+            for m in moves.iter() {
+                let child = b.apply(m).unwrap();
+                if !m.flags.contains(Flags::CAPTURE) && m.promotion.is_none() {
+                    children.push(table.index(&child, !c));
+                    continue;
+                }
+                let dtm = tables
+                    .probe(&child, !c)
+                    .ok_or_else(|| format!("{} needs {}", table.material, Material::of(&child)))?;
+                match dtm {
+                    Dtm::Loss(n) => win = Some(std::cmp::min(win.unwrap_or(n + 1), n + 1)),
+                    Dtm::Win(n) => exit_loss[idx] = std::cmp::max(exit_loss[idx], n + 1),
+                    Dtm::Draw => escapes[idx] = true,
+                }
+            }
+            children.sort_unstable();
+            children.dedup();
+            remaining[idx] = children.len() as u8;
+            if let Some(n) = win {
+                best_win[idx] = n;
+                push(&mut queue, n, idx);
+            } else if children.is_empty() && !escapes[idx] && exit_loss[idx] > 0 {
+                push(&mut queue, exit_loss[idx], idx);
+            }
+        }
+
+        let mut values = vec![0u8; size];
+        let mut plies = 0;
+        while plies < queue.len() {
+            for idx in std::mem::take(&mut queue[plies]) {
+                let idx = idx as usize;
+                if values[idx] != 0 {
+                    continue;
+                }
+                if plies >= u8::MAX as usize {
+                    return Err(format!("{} has mates longer than {} plies", table.material, plies));
+                }
+                values[idx] = plies as u8 + 1;
+                let (b, c) = table.position(idx).unwrap();
+                for before in table.predecessors(&b, c) {
+                    if plies % 2 == 0 {
+                        if plies as u32 + 1 < best_win[before] {
+                            best_win[before] = plies as u32 + 1;
+                            push(&mut queue, plies as u32 + 1, before);
+                        }
+                    } else if best_win[before] == u32::MAX && !escapes[before] {
+                        remaining[before] -= 1;
+                        if remaining[before] == 0 {
+                            push(&mut queue, std::cmp::max(plies as u32 + 1, exit_loss[before]), before);
+                        }
+                    }
+                }
+            }
+            plies += 1;
+        }
+        table.values = values;
+        Ok(table)
+    }
+
+    pub fn probe(&self, b: &Board, c: Color) -> Dtm {
+        Dtm::from_value(self.values[self.index(b, c)])
+    }
+
+    /// Positions won, lost and drawn for the side to move, and the longest
+    /// mate in plies.
+    pub fn stats(&self) -> (usize, usize, usize, u32) {
+        let (mut wins, mut losses, mut draws, mut longest) = (0, 0, 0, 0);
+        for idx in 0..self.size() {
+            match Dtm::from_value(self.values[idx]) {
+                Dtm::Win(n) => {
+                    wins += 1;
+                    longest = std::cmp::max(longest, n);
+                }
+                Dtm::Loss(_) => losses += 1,
+                Dtm::Draw if self.position(idx).is_some() => draws += 1,
+                Dtm::Draw => {}
+            }
+        }
+        (wins, losses, draws, longest)
+    }
+
+    /// Writes the table as its name then its values, packed as in PackBits:
+    /// a control byte `n` below 128 starts `n + 1` literal values, else the
+    /// next value repeats `n - 125` times. The unreachable positions repeat
+    /// the previous value, for longer runs.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let name = self.material.to_string();
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(name.len() as u8);
+        bytes.extend(name.bytes());
+        let mut values = self.values.clone();
+        for idx in 1..values.len() {
+            if values[idx] == 0 && self.position(idx).is_none() {
+                values[idx] = values[idx - 1];
+            }
+        }
+        let mut literals: Vec<u8> = Vec::new();
+        let flush = |bytes: &mut Vec<u8>, literals: &mut Vec<u8>| {
+            for chunk in literals.chunks(128) {
+                bytes.push(chunk.len() as u8 - 1);
+                bytes.extend_from_slice(chunk);
+            }
+            literals.clear();
+        };
+        let mut start = 0;
+        while start < values.len() {
+            let value = values[start];
+            let run = values[start..].iter().take(130).take_while(|&&v| v == value).count();
+            if run >= 3 {
+                flush(&mut bytes, &mut literals);
+                bytes.push(run as u8 + 125);
+                bytes.push(value);
+            } else {
+                literals.extend_from_slice(&values[start..start + run]);
+            }
+            start += run;
+        }
+        flush(&mut bytes, &mut literals);
+        std::fs::File::create(path)?.write_all(&bytes)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Table, String> {
+        let path = path.as_ref();
+        let error = |e: &dyn fmt::Display| format!("{}: {}", path.display(), e);
+        let mut bytes = Vec::new();
+        std::fs::File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|e| error(&e))?;
+        if bytes.len() < 5 || bytes[..4] != MAGIC {
+            return Err(error(&"not a table"));
+        }
+        let end = 5 + bytes[4] as usize;
+        let name = bytes.get(5..end).and_then(|b| std::str::from_utf8(b).ok());
+        let material = Material::parse(name.ok_or_else(|| error(&"invalid name"))?).map_err(|e| error(&e))?;
+        let mut table = Table::new(material);
+        let size = table.size();
+        let mut values = Vec::with_capacity(size);
+        let mut pos = end;
+        while pos < bytes.len() && values.len() < size {
+            let n = bytes[pos] as usize;
+            let packet = if n < 128 {
+                bytes.get(pos + 1..pos + n + 2)
+            } else {
+                bytes.get(pos + 1).map(std::slice::from_ref)
+            };
+            let packet = packet.ok_or_else(|| error(&"truncated"))?;
+            if n < 128 {
+                values.extend_from_slice(packet);
+            } else {
+                values.resize(values.len() + n - 125, packet[0]);
+            }
+            pos += packet.len() + 1;
+        }
+        if values.len() != size || pos != bytes.len() {
+            return Err(error(&"wrong size"));
+        }
+        table.values = values;
+        Ok(table)
+    }
+}
+
+/// Distance to mate tables: the built-in ones and those loaded from files
+/// or generated on request.
+#[derive(Clone)]
+pub struct DtmTables {
+    tables: HashMap<Key, Arc<Table>>,
+    max_pieces: usize,
+}
+
+impl DtmTables {
+    /// The built-in tables.
+    pub fn new() -> DtmTables {
+        (*Self::builtin()).clone()
+    }
+
+    /// The built-in tables, shared by all the searches and generated by the
+    /// first call, which the engine makes at startup.
+    pub fn builtin() -> Arc<DtmTables> {
+        static BUILTIN_TABLES: OnceLock<Arc<DtmTables>> = OnceLock::new();
+        BUILTIN_TABLES
+            .get_or_init(|| {
+                let mut tables = DtmTables {
+                    tables: HashMap::new(),
+                    max_pieces: 0,
+                };
+                for name in BUILTIN.iter() {
+                    let material = Material::parse(name).unwrap();
+                    info!("generating {}", material);
+                    // each only reaches the ones before it
+                    tables.add(Table::generate(material, &tables).unwrap());
+                }
+                Arc::new(tables)
+            })
+            .clone()
+    }
+
+    /// The built-in tables and the `.dtm` files of `dir`, as written by
+    /// the `tbgen` command.
+    pub fn open<P: AsRef<Path>>(dir: P) -> DtmTables {
+        let mut tables = DtmTables::new();
+        let dir = dir.as_ref();
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("{}: {}", dir.display(), e);
+                return tables;
+            }
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "dtm"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            match Table::load(&path) {
+                Ok(table) => tables.add(table),
+                Err(e) => error!("{}", e),
+            }
+        }
+        tables
+    }
+
+    fn add(&mut self, table: Table) {
+        self.max_pieces = std::cmp::max(self.max_pieces, table.pieces.len());
+        let key = table.material.key();
+        self.tables.remove(&key.swapped());
+        self.tables.insert(key, Arc::new(table));
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    #[cfg(test)]
+    fn table(&self, material: &Material) -> Option<Arc<Table>> {
+        self.tables.get(&material.key()).cloned()
+    }
+
+    fn contains(&self, material: &Material) -> bool {
+        let key = material.key();
+        key.is_trivial_draw() || self.tables.contains_key(&key) || self.tables.contains_key(&key.swapped())
+    }
+
+    /// Generates the table of `material` and first those it depends on,
+    /// calling `done` with each generated table.
+    pub fn generate<F: FnMut(&Table)>(&mut self, material: &Material, done: &mut F) -> Result<(), String> {
+        for m in material.successors() {
+            if !self.contains(&m) {
+                self.generate(&m, done)?;
+            }
+        }
+        let table = Table::generate(material.clone(), self)?;
+        done(&table);
+        self.add(table);
+        Ok(())
+    }
+
+    /// Distance to mate of `b` with `c` to move, if it is in a table.
+    pub fn probe(&self, b: &Board, c: Color) -> Option<Dtm> {
+        let key = Key::of(b);
+        if key.is_trivial_draw() {
+            return Some(Dtm::Draw);
+        }
+        if let Some(table) = self.tables.get(&key) {
+            return Some(table.probe(b, c));
+        }
+        let table = self.tables.get(&key.swapped())?;
+        Some(table.probe(&flip_colors(b), !c))
+    }
+
+    /// Moves of `b` keeping its value the best: the quickest mates when
+    /// winning, the slowest when losing.
+    pub fn best_moves(&self, b: &Board, c: Color) -> Option<(Vec<GenMove>, Dtm)> {
+        let dtm = self.probe(b, c)?;
+        let mut ranked = Vec::new();
+        for m in generate_legal_moves(b, c) {
+            let rank = self.probe(&b.apply(&m).unwrap(), !c)?.rank_after();
+            ranked.push((m, rank));
+        }
+        let best = ranked.iter().map(|&(_, rank)| rank).max()?;
+        let moves = ranked.into_iter().filter(|&(_, rank)| rank == best).map(|(m, _)| m).collect();
+        Some((moves, dtm))
+    }
+}
+
+impl Default for DtmTables {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use endgame;
+
+    fn parse(fen: &str) -> (Board, Color) {
+        parse_fen_color(&mut fen.chars()).unwrap()
+    }
+
+    fn probe(fen: &str) -> Option<Dtm> {
+        let (b, c) = parse(fen);
+        DtmTables::builtin().probe(&b, c)
+    }
+
+    #[test]
+    fn material_names() {
+        assert_eq!("KBNK", Material::parse("knbk").unwrap().to_string());
+        assert_eq!("KPKR", Material::parse("KPKR").unwrap().to_string());
+        assert_eq!("KK", Material::parse("KK").unwrap().to_string());
+        for name in ["KQ", "KXK", "QKK", "KQKRK", "KQRKQR"].iter() {
+            assert!(Material::parse(name).is_err(), "{}", name);
+        }
+        let successors: Vec<_> = Material::parse("KRKP")
+            .unwrap()
+            .successors()
+            .iter()
+            .map(|m| m.to_string())
+            .collect();
+        assert_eq!(vec!["KKP", "KRK", "KRKQ", "KRKR", "KRKB", "KRKN"], successors);
+        assert!(Material::parse("KBKN").unwrap().is_trivial_draw());
+        assert!(!Material::parse("KPK").unwrap().is_trivial_draw());
+    }
+
+    #[test]
+    fn symmetric_indices() {
+        let table = Table::new(Material::parse("KRK").unwrap());
+        let (b, c) = parse("8/8/8/2k5/8/8/1R6/6K1 w");
+        let side = |p: &PartialBoard, f: &dyn Fn(u64) -> u64| PartialBoard {
+            pawns: f(p.pawns),
+            rooks: f(p.rooks),
+            king: f(p.king),
+            ..PartialBoard::empty()
+        };
+        let flip = |b: &Board, f: &dyn Fn(u64) -> u64| Board {
+            white: side(&b.white, f),
+            black: side(&b.black, f),
+        };
+        let map = |bb: u64, sq: &dyn Fn(usize) -> usize| {
+            CaseIterator::new(bb).fold(0, |acc, s| acc | 1 << sq(s.0 as usize))
+        };
+        let idx = table.index(&b, c);
+        assert_eq!(idx, table.index(&flip(&b, &|bb| bb.swap_bytes()), c));
+        assert_eq!(idx, table.index(&flip(&b, &|bb| map(bb, &|sq| sq ^ 7)), c));
+        assert_eq!(idx, table.index(&flip(&b, &|bb| map(bb, &transpose)), c));
+        assert_ne!(idx, table.index(&b, !c));
+        let (found, _) = table.position(idx).unwrap();
+        assert_eq!(idx, table.index(&found, c));
+        assert!(table.position(idx + 1).is_none_or(|(b, c)| table.index(&b, c) == idx + 1));
+
+        // with pawns, only the files are mirrored
+        let table = Table::new(Material::parse("KPK").unwrap());
+        let (b, c) = parse("8/8/8/2k5/8/8/1P6/6K1 w");
+        assert_eq!(table.index(&b, c), table.index(&flip(&b, &|bb| map(bb, &|sq| sq ^ 7)), c));
+        let (b, c) = parse("8/8/8/2k5/8/8/6P1/1K6 w");
+        assert_eq!(Some((b.clone(), c)), table.position(table.index(&b, c)));
+    }
+
+    #[test]
+    fn builtin_tables() {
+        assert_eq!(Some(Dtm::Win(1)), probe("k7/8/1K6/8/8/8/8/6Q1 w"));
+        // Black in check with White to move
+        assert_eq!(Some(Dtm::Draw), probe("k7/8/1K6/8/8/8/8/7Q w"));
+        assert_eq!(Some(Dtm::Loss(0)), probe("k7/Q7/1K6/8/8/8/8/8 b"));
+        // stalemate, and the queen taken
+        assert_eq!(Some(Dtm::Draw), probe("k7/2Q5/1K6/8/8/8/8/8 b"));
+        assert_eq!(Some(Dtm::Draw), probe("8/8/8/8/8/2k5/2Q5/7K b"));
+        assert_eq!(Some(Dtm::Draw), probe("8/8/8/8/8/2k5/8/KB6 w"));
+        assert_eq!(None, probe("8/8/8/8/8/2k5/8/KBN5 w"));
+        // the same ending for Black
+        assert_eq!(Some(Dtm::Win(1)), probe("6q1/8/8/8/8/1k6/8/K7 b"));
+        assert_eq!(probe("8/8/8/8/8/2k5/6P1/6K1 w"), probe("6k1/6p1/2K5/8/8/8/8/8 b"));
+
+        let tables = DtmTables::builtin();
+        let longest: Vec<_> = BUILTIN
+            .iter()
+            .map(|name| tables.table(&Material::parse(name).unwrap()).unwrap().stats().3)
+            .collect();
+        assert_eq!(vec![19, 31, 55], longest);
+        assert_eq!("mate in 10", Dtm::Win(19).to_string());
+        assert_eq!("mated in 3", Dtm::Loss(6).to_string());
+    }
+
+    /// Checks every value of `table` against the best one over its moves.
+    fn check_children(tables: &DtmTables, table: &Table) {
+        for idx in 0..table.size() {
+            let (b, c) = match table.position(idx) {
+                Some(position) => position,
+                None => continue,
+            };
+            let children: Vec<Dtm> = generate_legal_moves(&b, c)
+                .iter()
+                .map(|m| tables.probe(&b.apply(m).unwrap(), !c).unwrap())
+                .collect();
+            let quickest_win = children.iter().filter_map(|&d| match d {
+                Dtm::Loss(n) => Some(n + 1),
+                _ => None,
+            });
+            let longest_loss = children.iter().map(|&d| match d {
+                Dtm::Win(n) => Some(n + 1),
+                _ => None,
+            });
+            let expected = match (quickest_win.min(), longest_loss.collect::<Option<Vec<_>>>()) {
+                (Some(n), _) => Dtm::Win(n),
+                (None, Some(losses)) if !losses.is_empty() => Dtm::Loss(*losses.iter().max().unwrap()),
+                (None, _) if children.is_empty() && in_check(&b, c) => Dtm::Loss(0),
+                _ => Dtm::Draw,
+            };
+            assert_eq!(expected, table.probe(&b, c), "{}{:?}", b, c);
+        }
+    }
+
+    #[test]
+    fn values_match_children() {
+        let tables = DtmTables::builtin();
+        for name in BUILTIN.iter() {
+            check_children(&tables, &tables.table(&Material::parse(name).unwrap()).unwrap());
+        }
+    }
+
+    #[test]
+    fn kpk_matches_bitbase() {
+        let table = DtmTables::builtin().table(&Material::parse("KPK").unwrap()).unwrap();
+        let mut checked = 0;
+        for idx in (0..table.size()).step_by(7) {
+            if let Some((b, c)) = table.position(idx) {
+                let win = endgame::probe(&b, c).is_some_and(|k| k.score > 0);
+                let expected = table.probe(&b, c) != Dtm::Draw;
+                assert_eq!(expected, win, "{}{:?}", b, c);
+                checked += 1;
+            }
+        }
+        assert!(checked > 10000);
+    }
+
+    #[test]
+    fn kpk_underpromotion() {
+        // b8=Q stalemates, b8=R wins
+        let (b, c) = parse("8/1P6/k7/8/K7/8/8/8 w");
+        let (moves, dtm) = DtmTables::builtin().best_moves(&b, c).unwrap();
+        assert!(matches!(dtm, Dtm::Win(_)), "{:?}", dtm);
+        assert_eq!(vec!["b7b8r"], moves.iter().map(|m| m.to_string()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn best_moves_mate() {
+        let tables = DtmTables::builtin();
+        // the quickest mate for White, the slowest for Black
+        let (mut b, mut c) = parse("8/8/8/3k4/8/8/8/R5K1 w");
+        let plies = match tables.probe(&b, c) {
+            Some(Dtm::Win(n)) => n,
+            dtm => panic!("{:?}", dtm),
+        };
+        for ply in 0..plies {
+            let (moves, dtm) = tables.best_moves(&b, c).unwrap();
+            let left = plies - ply;
+            assert_eq!(c.map(Dtm::Win(left), Dtm::Loss(left)), dtm);
+            b = b.apply(&moves[0]).unwrap();
+            c = !c;
+        }
+        assert!(generate_legal_moves(&b, c).is_empty() && in_check(&b, c));
+
+        let (b, c) = parse("8/8/8/8/8/2k5/8/K7 w");
+        assert_eq!(Some((generate_legal_moves(&b, c), Dtm::Draw)), tables.best_moves(&b, c));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let table = DtmTables::builtin().table(&Material::parse("KRK").unwrap()).unwrap();
+        let path = std::env::temp_dir().join(format!("chess-dtm-{}.dtm", std::process::id()));
+        table.save(&path).unwrap();
+        assert!((std::fs::metadata(&path).unwrap().len() as usize) < table.size() * 3 / 5);
+        let loaded = Table::load(&path).unwrap();
+        assert_eq!(table.material, loaded.material);
+        // the unreachable positions don't keep their value
+        for idx in 0..table.size() {
+            if table.position(idx).is_some() {
+                assert_eq!(table.values[idx], loaded.values[idx]);
+            }
+        }
+        let (b, c) = parse("8/8/8/3k4/8/8/8/R5K1 w");
+        let mut tables = DtmTables::new();
+        tables.add(loaded);
+        assert_eq!(DtmTables::builtin().probe(&b, c), tables.probe(&b, c));
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(Table::load(&path).is_err());
+        std::fs::write(&path, b"DTM1\x03KXK").unwrap();
+        assert!(Table::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,457 @@
+use board::*;
+use move_generator::{king_attacks, pawn_attacks, Case, CaseIterator, FILE_A, FILE_H};
+use see::SEE_VALUES;
+
+/// Score of an ending the strong side is known to win, on top of which the
+/// mating nets add their progress terms. In centipawns like them, rescaled
+/// to the pawn of the evaluator by `evaluate`. Far from the mate scores.
+pub const KNOWN_WIN: i32 = 10000;
+
+/// Scale factors of the evaluation of drawish endings, out of
+/// `SCALE_NORMAL`.
+pub const SCALE_NORMAL: i32 = 64;
+pub const SCALE_DRAW: i32 = 0;
+
+const LIGHT_SQUARES: u64 = 0x55aa_55aa_55aa_55aa;
+
+fn distance(a: Case, b: Case) -> i32 {
+    let rows = (a.row() as i32 - b.row() as i32).abs();
+    let cols = (a.col() as i32 - b.col() as i32).abs();
+    std::cmp::max(rows, cols)
+}
+
+fn manhattan(a: Case, b: Case) -> i32 {
+    (a.row() as i32 - b.row() as i32).abs() + (a.col() as i32 - b.col() as i32).abs()
+}
+
+fn king(b: &Board, c: Color) -> Case {
+    Case(b.get_pc_board(Piece::King, c).trailing_zeros() as u8)
+}
+
+fn light_square(sq: Case) -> bool {
+    (sq.row() + sq.col()) % 2 == 1
+}
+
+/// Rewards a king close to the edges, and more so to the corners.
+fn push_to_edge(sq: Case) -> i32 {
+    let row = std::cmp::min(sq.row(), 7 - sq.row()) as i32;
+    let col = std::cmp::min(sq.col(), 7 - sq.col()) as i32;
+    20 * (6 - row - col) + 10 * (3 - std::cmp::min(row, col))
+}
+
+/// Rewards kings close to each other.
+fn push_close(a: Case, b: Case) -> i32 {
+    20 * (7 - distance(a, b))
+}
+
+/// Pieces of `c` as a signature such as `KBN`, strongest first.
+pub fn signature(b: &Board, c: Color) -> String {
+    use crate::Piece::*;
+    let mut s = String::from("K");
+    for &(p, letter) in [(Queen, 'Q'), (Rook, 'R'), (Bishop, 'B'), (Knight, 'N'), (Pawn, 'P')].iter() {
+        for _ in 0..b.get_pc_board(p, c).count_ones() {
+            s.push(letter);
+        }
+    }
+    s
+}
+
+/// Specialized evaluation of an ending, for the strong side.
+pub struct Known {
+    /// Signature of the ending, such as `KBNK`.
+    pub name: String,
+    pub strong: Color,
+    pub score: i32,
+}
+
+/// Evaluation of `b`, `player` being to move, when one side is down to its
+/// king and the other has either a single pawn or enough to mate.
+pub fn probe(b: &Board, player: Color) -> Option<Known> {
+    let strong = if b.black.all() == b.black.king {
+        Color::White
+    } else if b.white.all() == b.white.king {
+        Color::Black
+    } else {
+        return None;
+    };
+    let ours = b.color(strong);
+    let signature = signature(b, strong);
+    let score = match signature.as_str() {
+        "K" => return None,
+        "KP" => kpk(b, strong, player == strong),
+        "KBN" => kbnk(b, strong),
+        _ => {
+            let bishops = ours.bishops;
+            let both_colors = bishops & LIGHT_SQUARES != 0 && bishops & !LIGHT_SQUARES != 0;
+            if ours.queens | ours.rooks == 0 && !both_colors && !(bishops != 0 && ours.knights != 0) {
+                // not enough to force mate, if anything the pawns will tell
+                return None;
+            }
+            kxk(b, strong)
+        }
+    };
+    Some(Known {
+        name: signature + "K",
+        strong,
+        score,
+    })
+}
+
+fn material(b: &Board, c: Color) -> i32 {
+    use crate::Piece::*;
+    [Pawn, Knight, Bishop, Rook, Queen]
+        .iter()
+        .map(|&p| b.get_pc_board(p, c).count_ones() as i32 * SEE_VALUES[p as usize])
+        .sum()
+}
+
+/// Mating material against a lone king: the defending king is driven to
+/// the edge, with the attacking king coming close.
+fn kxk(b: &Board, strong: Color) -> i32 {
+    let weak_king = king(b, !strong);
+    KNOWN_WIN + material(b, strong) + push_to_edge(weak_king) + push_close(king(b, strong), weak_king)
+}
+
+/// Bishop and knight: the mate can only be forced in a corner of the color
+/// of the bishop, which the defending king is driven to.
+fn kbnk(b: &Board, strong: Color) -> i32 {
+    let weak_king = king(b, !strong);
+    let light = b.get_pc_board(Piece::Bishop, strong) & LIGHT_SQUARES != 0;
+    let corners = if light {
+        [Case::new(0, 7), Case::new(7, 0)]
+    } else {
+        [Case::new(0, 0), Case::new(7, 7)]
+    };
+    let corner = corners.iter().map(|&c| manhattan(weak_king, c)).min().unwrap();
+    KNOWN_WIN + material(b, strong) + 20 * (14 - corner) + push_close(king(b, strong), weak_king)
+}
+
+fn kpk(b: &Board, strong: Color, strong_to_move: bool) -> i32 {
+    let pawn = Case(b.get_pc_board(Piece::Pawn, strong).trailing_zeros() as u8);
+    let (strong_king, weak_king) = (king(b, strong), king(b, !strong));
+    // seen from White with the pawn on the queen side
+    let flip = |sq: Case| {
+        let sq = strong.map(sq.0, sq.0 ^ 56);
+        Case(if pawn.col() >= 4 { sq ^ 7 } else { sq })
+    };
+    let (pawn, strong_king, weak_king) = (flip(pawn), flip(strong_king), flip(weak_king));
+    if KPK.win(strong_to_move, strong_king, weak_king, pawn) {
+        KNOWN_WIN + SEE_VALUES[Piece::Pawn as usize] + 10 * pawn.row() as i32
+    } else {
+        0
+    }
+}
+
+/// Scale factor of the evaluation of `b` for `strong`, the side ahead, in
+/// endings hard to win despite the extra material.
+pub fn scale_factor(b: &Board, strong: Color) -> i32 {
+    use crate::Piece::*;
+    let ours = b.color(strong);
+    let theirs = b.color(!strong);
+    let minors = (ours.knights | ours.bishops).count_ones();
+    if ours.pawns == 0 && ours.rooks | ours.queens == 0 && (minors <= 1 || ours.bishops == 0) {
+        // a minor piece or two knights can't mate
+        return SCALE_DRAW;
+    }
+    if ours.pawns == 0 && material(b, strong) - material(b, !strong) <= SEE_VALUES[Bishop as usize] {
+        // such as a rook against a minor piece
+        return SCALE_NORMAL / 4;
+    }
+    if ours.knights | ours.rooks | ours.queens == 0 && ours.bishops.count_ones() <= 1 && ours.pawns != 0 {
+        let rook_file = if ours.pawns & !FILE_A == 0 {
+            Some(0)
+        } else if ours.pawns & !FILE_H == 0 {
+            Some(7)
+        } else {
+            None
+        };
+        if let Some(col) = rook_file {
+            let promotion = Case::new(strong.map(7, 0), col);
+            let bishop_controls = ours.bishops & LIGHT_SQUARES != 0 && light_square(promotion)
+                || ours.bishops & !LIGHT_SQUARES != 0 && !light_square(promotion);
+            // the defending king holds the corner the bishop can't drive it out of
+            if !bishop_controls && distance(king(b, !strong), promotion) <= 1 {
+                return SCALE_DRAW;
+            }
+        }
+    }
+    if ours.bishops.count_ones() == 1 && theirs.bishops.count_ones() == 1 {
+        let opposite = (ours.bishops & LIGHT_SQUARES != 0) != (theirs.bishops & LIGHT_SQUARES != 0);
+        if opposite {
+            let pieces = ours.knights | ours.rooks | ours.queens | theirs.knights | theirs.rooks | theirs.queens;
+            return if pieces == 0 {
+                SCALE_NORMAL / 2
+            } else {
+                SCALE_NORMAL * 3 / 4
+            };
+        }
+    }
+    SCALE_NORMAL
+}
+
+/// Score of a known ending, in centipawns, for an evaluator whose pawn is
+/// worth `pawn`.
+pub fn rescale(score: i32, pawn: i32) -> i32 {
+    score * pawn / SEE_VALUES[Piece::Pawn as usize]
+}
+
+/// Score of `b` for `player` with the endgame knowledge applied: the known
+/// endings get their own evaluation, rescaled to `pawn`, the value of a
+/// pawn for `eval`, which is the score of the others, scaled down in
+/// drawish endings.
+pub fn evaluate<F: FnOnce() -> i32>(b: &Board, player: Color, pawn: i32, eval: F) -> i32 {
+    if let Some(known) = probe(b, player) {
+        let score = rescale(known.score, pawn);
+        return if known.strong == player { score } else { -score };
+    }
+    let score = eval();
+    let strong = if score >= 0 { player } else { !player };
+    score * scale_factor(b, strong) / SCALE_NORMAL
+}
+
+/// King and pawn against king, won or drawn with perfect play, for the
+/// strong side playing up the board with its pawn on the files a to d.
+struct Kpk {
+    wins: Vec<u64>,
+}
+
+const KPK_SIZE: usize = 2 * 24 * 64 * 64;
+
+const UNKNOWN: u8 = 0;
+const INVALID: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 3;
+
+fn kpk_index(strong_to_move: bool, strong_king: Case, weak_king: Case, pawn: Case) -> usize {
+    let pawn = (pawn.row() as usize - 1) * 4 + pawn.col() as usize;
+    ((strong_to_move as usize * 24 + pawn) * 64 + strong_king.0 as usize) * 64 + weak_king.0 as usize
+}
+
+impl Kpk {
+    /// Retrograde analysis: the positions where the pawn promotes safely are
+    /// won, the ones where it falls or the defending king is stalemated are
+    /// drawn, and the others follow from their children until nothing moves.
+    fn generate() -> Self {
+        let mut results = vec![UNKNOWN; KPK_SIZE];
+        let mut positions = Vec::with_capacity(KPK_SIZE);
+        for &strong_to_move in [false, true].iter() {
+            for row in 1..7 {
+                for col in 0..4 {
+                    for strong_king in 0..64 {
+                        for weak_king in 0..64 {
+                            positions.push((strong_to_move, Case(strong_king), Case(weak_king), Case::new(row, col)));
+                        }
+                    }
+                }
+            }
+        }
+        for &(strong_to_move, strong_king, weak_king, pawn) in positions.iter() {
+            let i = kpk_index(strong_to_move, strong_king, weak_king, pawn);
+            results[i] = Self::classify(strong_to_move, strong_king, weak_king, pawn, None);
+        }
+        loop {
+            let mut changed = false;
+            for &(strong_to_move, strong_king, weak_king, pawn) in positions.iter() {
+                let i = kpk_index(strong_to_move, strong_king, weak_king, pawn);
+                if results[i] == UNKNOWN {
+                    results[i] = Self::classify(strong_to_move, strong_king, weak_king, pawn, Some(&results));
+                    changed |= results[i] != UNKNOWN;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        let mut wins = vec![0; KPK_SIZE / 64];
+        for (i, &r) in results.iter().enumerate() {
+            if r == WIN {
+                wins[i / 64] |= 1 << (i % 64);
+            }
+        }
+        Kpk { wins }
+    }
+
+    /// Result of a position from the rules alone when `results` is `None`,
+    /// and from the results of its children otherwise.
+    fn classify(strong_to_move: bool, strong_king: Case, weak_king: Case, pawn: Case, results: Option<&[u8]>) -> u8 {
+        let strong_attacks = king_attacks(strong_king);
+        let pawn_attacks = pawn_attacks(pawn.board(), Color::White);
+        let stop = Case(pawn.0 + 8);
+        let results = match results {
+            Some(results) => results,
+            None => {
+                if strong_king == weak_king
+                    || pawn == strong_king
+                    || pawn == weak_king
+                    || strong_attacks & weak_king.board() != 0
+                    || strong_to_move && pawn_attacks & weak_king.board() != 0
+                {
+                    return INVALID;
+                }
+                if strong_to_move
+                    && pawn.row() == 6
+                    && stop != strong_king
+                    && stop != weak_king
+                    && (distance(weak_king, stop) > 1 || distance(strong_king, stop) == 1)
+                {
+                    return WIN;
+                }
+                if !strong_to_move {
+                    let moves = king_attacks(weak_king) & !strong_attacks & !pawn_attacks;
+                    if moves == 0 {
+                        return if pawn_attacks & weak_king.board() != 0 { WIN } else { DRAW };
+                    }
+                    if moves & pawn.board() != 0 {
+                        return DRAW;
+                    }
+                }
+                return UNKNOWN;
+            }
+        };
+        if strong_to_move {
+            let mut children = Vec::new();
+            let moves = strong_attacks & !king_attacks(weak_king) & !pawn.board();
+            for to in CaseIterator::new(moves) {
+                children.push(kpk_index(false, to, weak_king, pawn));
+            }
+            if pawn.row() < 6 && stop != strong_king && stop != weak_king {
+                children.push(kpk_index(false, strong_king, weak_king, stop));
+                let double = Case(pawn.0 + 16);
+                if pawn.row() == 1 && double != strong_king && double != weak_king {
+                    children.push(kpk_index(false, strong_king, weak_king, double));
+                }
+            }
+            if children.iter().any(|&c| results[c] == WIN) {
+                WIN
+            } else if children.iter().all(|&c| results[c] == DRAW) {
+                DRAW
+            } else {
+                UNKNOWN
+            }
+        } else {
+            let moves = king_attacks(weak_king) & !strong_attacks & !pawn_attacks & !pawn.board();
+            let children: Vec<_> = CaseIterator::new(moves)
+                .map(|to| kpk_index(true, strong_king, to, pawn))
+                .collect();
+            if children.iter().any(|&c| results[c] == DRAW) {
+                DRAW
+            } else if children.iter().all(|&c| results[c] == WIN) {
+                WIN
+            } else {
+                UNKNOWN
+            }
+        }
+    }
+
+    fn win(&self, strong_to_move: bool, strong_king: Case, weak_king: Case, pawn: Case) -> bool {
+        let i = kpk_index(strong_to_move, strong_king, weak_king, pawn);
+        self.wins[i / 64] & (1 << (i % 64)) != 0
+    }
+}
+
+lazy_static! {
+    static ref KPK: Kpk = Kpk::generate();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evaluate::{BasicEvaluator, EvalParams, Evaluator};
+    use pst::TaperedEvaluator;
+
+    fn known(fen: &str) -> Option<i32> {
+        let (b, c) = parse_fen_color(&mut fen.chars()).unwrap();
+        probe(&b, c).map(|k| if k.strong == c { k.score } else { -k.score })
+    }
+
+    fn scale(fen: &str) -> i32 {
+        scale_factor(&parse_fen(fen).unwrap(), Color::White)
+    }
+
+    #[test]
+    fn kpk_bitbase() {
+        // the king in front of the pawn on the 6th rank wins whoever moves
+        assert!(known("4k3/8/4K3/4P3/8/8/8/8 w").unwrap() > KNOWN_WIN);
+        assert!(known("4k3/8/4K3/4P3/8/8/8/8 b").unwrap() < -KNOWN_WIN);
+        // the opposition decides
+        assert_eq!(Some(0), known("8/4k3/8/4K3/4P3/8/8/8 w"));
+        assert!(known("8/4k3/8/4K3/4P3/8/8/8 b").unwrap() < -KNOWN_WIN);
+        // Ke7 holds, leaving only stalemates
+        assert_eq!(Some(0), known("4k3/8/4P3/4K3/8/8/8/8 b"));
+        // the same for Black, on the king side
+        assert_eq!(Some(0), known("8/8/8/8/6k1/6p1/8/6K1 b"));
+        assert!(known("8/8/8/8/6p1/6k1/8/6K1 w").unwrap() < -KNOWN_WIN);
+        // the defending king holds the corner against a rook pawn
+        assert_eq!(Some(0), known("k7/8/8/8/8/8/P7/K7 w"));
+        assert_eq!(Some(0), known("7k/8/8/8/8/7P/8/5K2 w"));
+        // the pawn runs away
+        assert!(known("8/P7/8/8/8/8/8/K6k b").unwrap() < -KNOWN_WIN);
+    }
+
+    #[test]
+    fn mating_nets() {
+        let (b, c) = parse_fen_color(&mut "8/8/8/4k3/8/8/8/1NB1K3 w".chars()).unwrap();
+        let kbnk = probe(&b, c).unwrap();
+        assert_eq!("KBNK", kbnk.name);
+        assert_eq!(Color::White, kbnk.strong);
+        // the dark squared bishop mates in a1 or h8, not in a8
+        let right = known("8/8/8/8/8/2K5/8/k1BN4 w").unwrap();
+        let wrong = known("k7/8/2K5/8/8/8/8/2BN4 w").unwrap();
+        assert!(right > wrong);
+        assert!(wrong > KNOWN_WIN);
+        // the lone king gets driven to the edge, the other one follows
+        let center = known("8/8/8/3k4/8/8/8/R3K3 b").unwrap();
+        let edge = known("3k4/8/8/8/8/8/8/R3K3 b").unwrap();
+        let close = known("3k4/8/3K4/8/8/8/8/R7 b").unwrap();
+        assert!(center < -KNOWN_WIN);
+        assert!(edge < center && close < edge);
+        assert!(known("3k4/8/8/8/8/8/8/Q3K3 b").unwrap() < edge);
+        // two knights can't force mate
+        assert!(known("3k4/8/8/8/8/8/8/1N2KN2 w").is_none());
+    }
+
+    #[test]
+    fn drawish_endings() {
+        assert_eq!(SCALE_DRAW, scale("4k3/8/8/8/8/8/8/2B1K3 w"));
+        assert_eq!(SCALE_DRAW, scale("4k3/8/8/8/8/8/8/1N2KN2 w"));
+        assert_eq!(SCALE_NORMAL / 4, scale("4k3/8/8/8/8/4b3/8/R3K3 w"));
+        // a bishop of the wrong color for the a8 corner
+        assert_eq!(SCALE_DRAW, scale("k7/8/8/8/8/P7/P7/2B1K3 w"));
+        assert_eq!(SCALE_NORMAL, scale("k7/8/8/8/8/P7/P7/1B2K3 w"));
+        assert_eq!(SCALE_NORMAL, scale("8/8/8/4k3/8/P7/P7/2B1K3 w"));
+        assert_eq!(SCALE_NORMAL / 2, scale("4k3/1pp2b2/8/8/8/8/PPP5/2B1K3 w"));
+        assert_eq!(SCALE_NORMAL * 3 / 4, scale("3rk3/1pp2b2/8/8/8/8/PPP5/2B1K2R w"));
+        assert_eq!(SCALE_NORMAL, scale("4k3/1pp1b3/8/8/8/8/PPP5/2B1K3 w"));
+
+        // the evaluators apply them
+        let params = EvalParams::default();
+        let b = parse_fen("k7/8/8/8/8/P7/P7/2B1K3 w").unwrap();
+        assert_eq!(0, BasicEvaluator::new(&params).evaluate(&b, Color::White));
+        assert_eq!(0, TaperedEvaluator::new(&params).evaluate(&b, Color::Black));
+        let b = parse_fen("8/8/8/3k4/8/8/8/1NB1K3 w").unwrap();
+        let trace = TaperedEvaluator::new(&params).trace(&b);
+        assert_eq!(Some("KBNK".to_string()), trace.endgame);
+        let b = parse_fen("4k3/1pp2b2/8/8/8/8/PPPP4/2B1K3 w").unwrap();
+        let mut e = TaperedEvaluator::new(&params);
+        let trace = e.trace(&b);
+        assert_eq!(SCALE_NORMAL / 2, trace.scale);
+        assert_eq!(e.evaluate(&b, Color::White), trace.score);
+    }
+    #[test]
+    fn known_wins_in_evaluator_units() {
+        let params = EvalParams::default();
+        let b = parse_fen("8/8/8/3k4/8/8/8/1NB1K3 w").unwrap();
+        // a pawn is worth 10 to the basic evaluator, about 100 to the tapered one
+        let pawns = [
+            params.basic.material[Piece::Pawn as usize],
+            params.pst.material[Piece::Pawn as usize].eg,
+        ];
+        let mut evaluators: Vec<Box<dyn Evaluator>> =
+            vec![Box::new(BasicEvaluator::new(&params)), Box::new(TaperedEvaluator::new(&params))];
+        for (e, &pawn) in evaluators.iter_mut().zip(pawns.iter()) {
+            let score = e.evaluate(&b, Color::White);
+            assert!(score > rescale(KNOWN_WIN, pawn) && score < rescale(2 * KNOWN_WIN, pawn), "{}", score);
+            assert_eq!(-score, e.evaluate(&b, Color::Black));
+        }
+    }
+}
@@ -1,24 +1,374 @@
 use board::*;
+use endgame;
+use king_safety::KingWeights;
+use mobility::MobilityWeights;
+use move_generator::GenMove;
+use nnue::{Network, NnueEvaluator};
+use pawns::PawnWeights;
+use positional::PositionalWeights;
+use pst::{PstWeights, TaperedEvaluator};
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+use std::path::Path;
+use std::sync::Arc;
 
-pub trait Evaluator {
-    fn evaluate(&self, b: &Board, player: Color) -> i32;
+pub trait Evaluator: Send {
+    /// Score of `b` for `player`. Takes `&mut self` so that evaluators can
+    /// keep caches between calls.
+    fn evaluate(&mut self, b: &Board, player: Color) -> i32;
+
+    /// Called when the search plays `m` from `b`, reaching `child`, so that
+    /// evaluators can update their state incrementally.
+    fn make_move(&mut self, _b: &Board, _child: &Board, _m: &GenMove) {}
+
+    /// Takes back the last `make_move`.
+    fn unmake_move(&mut self) {}
+
+    /// Breakdown of the score of `b` by term, for White.
+    fn trace(&mut self, b: &Board) -> Trace;
+
+    /// Copy for another search thread.
+    fn box_clone(&self) -> Box<dyn Evaluator>;
+}
+
+/// Value of an evaluation term for each side, before interpolation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceTerm {
+    pub name: &'static str,
+    pub white: Tapered,
+    pub black: Tapered,
+}
+
+/// Evaluation of a position split into its terms, as printed by the `eval`
+/// command. `score` is the evaluation for White, which evaluators without
+/// separate terms report alone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    pub terms: Vec<TraceTerm>,
+    pub phase: i32,
+    /// Signature of the ending when it has its own evaluation.
+    pub endgame: Option<String>,
+    /// Scale factor of drawish endings, out of `endgame::SCALE_NORMAL`.
+    pub scale: i32,
+    pub score: i32,
+}
+
+impl Trace {
+    pub fn new(b: &Board) -> Self {
+        Trace {
+            terms: Vec::new(),
+            phase: crate::pst::phase(b),
+            endgame: None,
+            scale: endgame::SCALE_NORMAL,
+            score: 0,
+        }
+    }
+
+    /// Sets the score from `score`, the evaluation for White of the terms,
+    /// applying the endgame knowledge as `endgame::evaluate` does for an
+    /// evaluator whose pawn is worth `pawn`.
+    pub fn set_score(&mut self, b: &Board, pawn: i32, score: i32) {
+        if let Some(known) = endgame::probe(b, Color::White) {
+            let known_score = endgame::rescale(known.score, pawn);
+            self.score = known.strong.map(known_score, -known_score);
+            self.endgame = Some(known.name);
+            return;
+        }
+        let strong = if score >= 0 { Color::White } else { Color::Black };
+        self.scale = endgame::scale_factor(b, strong);
+        self.score = score * self.scale / endgame::SCALE_NORMAL;
+    }
+
+    pub fn add(&mut self, name: &'static str, white: Tapered, black: Tapered) {
+        self.terms.push(TraceTerm { name, white, black });
+    }
+
+    /// Sum of the terms, White's minus Black's.
+    pub fn total(&self) -> Tapered {
+        self.terms.iter().fold(Tapered::default(), |acc, t| acc + t.white - t.black)
+    }
+}
+
+impl std::fmt::Display for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if !self.terms.is_empty() {
+            writeln!(f, "{:>13} | {:>13} | {:>13} | {:>13}", "term", "White", "Black", "Total")?;
+            writeln!(f, "{:>13} | {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6}", "", "mg", "eg", "mg", "eg", "mg", "eg")?;
+            let mut line = |name: &str, w: Tapered, b: Tapered| {
+                let t = w - b;
+                writeln!(
+                    f,
+                    "{:>13} | {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6}",
+                    name, w.mg, w.eg, b.mg, b.eg, t.mg, t.eg
+                )
+            };
+            for t in self.terms.iter() {
+                line(t.name, t.white, t.black)?;
+            }
+            let white = self.terms.iter().fold(Tapered::default(), |acc, t| acc + t.white);
+            let black = self.terms.iter().fold(Tapered::default(), |acc, t| acc + t.black);
+            line("total", white, black)?;
+        }
+        writeln!(f, "phase {}/{}", self.phase, crate::pst::MAX_PHASE)?;
+        if let Some(name) = self.endgame.as_ref() {
+            writeln!(f, "endgame {}", name)?;
+        }
+        if self.scale != endgame::SCALE_NORMAL {
+            writeln!(f, "scale {}/{}", self.scale, endgame::SCALE_NORMAL)?;
+        }
+        write!(f, "score {} (White)", self.score)
+    }
+}
+
+/// Names of the evaluators selectable with `by_name`.
+pub const EVALUATORS: [&str; 3] = ["basic", "tapered", "nnue"];
+
+/// Evaluator called `name`. The network of "nnue" is loaded from
+/// `params.nnue_file`.
+pub fn by_name(name: &str, params: &EvalParams) -> Option<Box<dyn Evaluator>> {
+    match name.to_lowercase().as_str() {
+        "basic" => Some(Box::new(BasicEvaluator::new(params))),
+        "tapered" => Some(Box::new(TaperedEvaluator::new(params))),
+        "nnue" => match Network::load(&params.nnue_file) {
+            Ok(net) => Some(Box::new(NnueEvaluator::new(Arc::new(net)))),
+            Err(e) => {
+                error!("invalid network {}", e);
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Weights of all the evaluators, loaded from TOML or JSON files. Missing
+/// entries keep their default value.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EvalParams {
+    /// Network of the "nnue" evaluator.
+    pub nnue_file: String,
+    pub basic: BasicWeights,
+    pub pst: PstWeights,
+    pub pawns: PawnWeights,
+    pub king: KingWeights,
+    pub mobility: MobilityWeights,
+    pub positional: PositionalWeights,
 }
 
-pub struct BasicEvaluator;
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json"))
+}
+
+impl EvalParams {
+    /// Reads `path`, as JSON if its extension is `.json` and as TOML otherwise.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let params = if is_json(path) {
+            serde_json::from_str(&text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&text).map_err(|e| e.to_string())
+        };
+        params.map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Writes to `path`, in the format chosen by `load`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let text = if is_json(path) {
+            serde_json::to_string_pretty(self).map_err(|e| e.to_string())?
+        } else {
+            toml::to_string(self).map_err(|e| e.to_string())?
+        };
+        std::fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
 
-fn eval(b: &PartialBoard) -> u32 {
-    b.pawns.count_ones() * 10 +
-    b.knights.count_ones() * 30 +
-    b.bishops.count_ones() * 30 +
-    b.rooks.count_ones() * 50 +
-    b.queens.count_ones() * 90 +
-    b.king.count_ones() * 900
+/// Pair of middlegame and endgame values, blended by the game phase.
+/// Stored as `[mg, eg]` in the parameter files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "(i32, i32)", into = "(i32, i32)")]
+pub struct Tapered {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl Tapered {
+    pub const fn new(mg: i32, eg: i32) -> Self {
+        Tapered { mg, eg }
+    }
+
+    /// Value at `phase`, from `pst::MAX_PHASE` (middlegame) down to 0 (endgame).
+    pub fn interpolate(self, phase: i32) -> i32 {
+        let max = crate::pst::MAX_PHASE;
+        (self.mg * phase + self.eg * (max - phase)) / max
+    }
+}
+
+impl From<(i32, i32)> for Tapered {
+    fn from((mg, eg): (i32, i32)) -> Self {
+        Tapered::new(mg, eg)
+    }
+}
+
+impl From<Tapered> for (i32, i32) {
+    fn from(t: Tapered) -> Self {
+        (t.mg, t.eg)
+    }
+}
+
+impl Add for Tapered {
+    type Output = Tapered;
+
+    fn add(self, rhs: Tapered) -> Tapered {
+        Tapered::new(self.mg + rhs.mg, self.eg + rhs.eg)
+    }
+}
+
+impl Sub for Tapered {
+    type Output = Tapered;
+
+    fn sub(self, rhs: Tapered) -> Tapered {
+        Tapered::new(self.mg - rhs.mg, self.eg - rhs.eg)
+    }
+}
+
+impl Neg for Tapered {
+    type Output = Tapered;
+
+    fn neg(self) -> Tapered {
+        Tapered::new(-self.mg, -self.eg)
+    }
+}
+
+impl Mul<i32> for Tapered {
+    type Output = Tapered;
+
+    fn mul(self, rhs: i32) -> Tapered {
+        Tapered::new(self.mg * rhs, self.eg * rhs)
+    }
+}
+
+impl AddAssign for Tapered {
+    fn add_assign(&mut self, rhs: Tapered) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Tapered {
+    fn sub_assign(&mut self, rhs: Tapered) {
+        *self = *self - rhs;
+    }
+}
+
+/// Piece values of `BasicEvaluator`, a pawn being worth 10.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BasicWeights {
+    pub material: [i32; 6],
+}
+
+impl Default for BasicWeights {
+    fn default() -> Self {
+        BasicWeights {
+            material: [10, 30, 30, 50, 90, 900],
+        }
+    }
+}
+
+/// Material count only.
+#[derive(Debug, Clone, Default)]
+pub struct BasicEvaluator {
+    pub weights: BasicWeights,
+}
+
+impl BasicEvaluator {
+    pub fn new(params: &EvalParams) -> Self {
+        BasicEvaluator {
+            weights: params.basic.clone(),
+        }
+    }
+}
+
+fn eval(b: &PartialBoard, w: &BasicWeights) -> i32 {
+    use crate::Piece::*;
+    [Pawn, Knight, Bishop, Rook, Queen, King]
+        .iter()
+        .map(|&p| b.get_pc_board(p).count_ones() as i32 * w.material[p as usize])
+        .sum()
 }
 
 impl Evaluator for BasicEvaluator {
-    fn evaluate(&self, b: &Board, player: Color) -> i32 {
-        let this = b.color(player);
-        let other = b.color(!player);
-        return eval(this) as i32 - eval(other) as i32;
+    fn evaluate(&mut self, b: &Board, player: Color) -> i32 {
+        let pawn = self.weights.material[Piece::Pawn as usize];
+        endgame::evaluate(b, player, pawn, || {
+            eval(b.color(player), &self.weights) - eval(b.color(!player), &self.weights)
+        })
+    }
+
+    fn trace(&mut self, b: &Board) -> Trace {
+        let mut trace = Trace::new(b);
+        let white = eval(&b.white, &self.weights);
+        let black = eval(&b.black, &self.weights);
+        trace.add("material", Tapered::new(white, white), Tapered::new(black, black));
+        trace.set_score(b, self.weights.material[Piece::Pawn as usize], white - black);
+        trace
+    }
+
+    fn box_clone(&self) -> Box<dyn Evaluator> {
+        Box::new(self.clone())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eval_params_files() {
+        let dir = std::env::temp_dir();
+        let mut params = EvalParams::default();
+        params.basic.material[0] = 12;
+        params.pawns.doubled = Tapered::new(-7, -30);
+        for name in ["chess-params.toml", "chess-params.json"].iter() {
+            let path = dir.join(name);
+            params.save(&path).unwrap();
+            assert_eq!(params, EvalParams::load(&path).unwrap());
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        // missing weights keep their defaults
+        let path = dir.join("chess-partial.toml");
+        std::fs::write(&path, "[pawns]\ndoubled = [-7, -30]\n").unwrap();
+        let partial = EvalParams::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(Tapered::new(-7, -30), partial.pawns.doubled);
+        assert_eq!(EvalParams::default().pawns.isolated, partial.pawns.isolated);
+        assert_eq!(EvalParams::default().pst, partial.pst);
+
+        assert!(EvalParams::load(dir.join("chess-missing.toml")).is_err());
+    }
+
+    #[test]
+    fn traces() {
+        let params = EvalParams::default();
+        let b = parse_fen("r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w").unwrap();
+        for name in ["basic", "tapered"].iter() {
+            let mut e = by_name(name, &params).unwrap();
+            let trace = e.trace(&b);
+            assert_eq!(e.evaluate(&b, Color::White), trace.score);
+            assert_eq!(-trace.score, e.evaluate(&b, Color::Black));
+        }
+
+        let mut e = TaperedEvaluator::new(&params);
+        let trace = e.trace(&b);
+        let names: Vec<_> = trace.terms.iter().map(|t| t.name).collect();
+        assert_eq!(vec!["material", "pst", "pawns", "king safety", "mobility", "positional"], names);
+        // both sides have all their pieces
+        assert_eq!(trace.terms[0].white, trace.terms[0].black);
+        assert_eq!(crate::pst::MAX_PHASE, trace.phase);
+        let text = trace.to_string();
+        assert!(text.contains("king safety"));
+        assert!(text.ends_with(&format!("score {} (White)", trace.score)));
+    }
+}
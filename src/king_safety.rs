@@ -0,0 +1,146 @@
+use board::*;
+use evaluate::Tapered;
+use move_generator::{king_attacks, piece_attacks, Case, CaseIterator, FILE_A};
+use serde::{Deserialize, Serialize};
+
+/// King safety weights, in centipawns. The endgame values are mostly zero:
+/// king safety fades out with the material.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KingWeights {
+    /// Own pawn one and two ranks in front of the king, on its file or the
+    /// adjacent ones.
+    pub shield: [Tapered; 2],
+    /// Nearest enemy pawn coming on those files, by its distance in ranks.
+    pub storm: [Tapered; 8],
+    /// File next to the king without own pawn.
+    pub half_open_file: Tapered,
+    /// File next to the king without any pawn.
+    pub open_file: Tapered,
+    /// Attack units of a piece hitting the king zone, by piece type.
+    pub attack_weight: [i32; 6],
+    /// Danger of the king, by the attack units against it.
+    pub safety: Vec<i32>,
+}
+
+const fn t(mg: i32, eg: i32) -> Tapered {
+    Tapered::new(mg, eg)
+}
+
+const SAFETY_SIZE: usize = 64;
+const MAX_DANGER: i32 = 500;
+
+impl Default for KingWeights {
+    fn default() -> Self {
+        KingWeights {
+            shield: [t(12, 0), t(6, 0)],
+            storm: [t(0, 0), t(-5, 0), t(-20, 0), t(-12, 0), t(-6, 0), t(0, 0), t(0, 0), t(0, 0)],
+            half_open_file: t(-12, 0),
+            open_file: t(-20, 0),
+            attack_weight: [0, 2, 2, 3, 5, 0],
+            // a lone attacker is harmless, a coordinated attack decisive
+            safety: (0..SAFETY_SIZE as i32).map(|u| std::cmp::min(u * u / 2, MAX_DANGER)).collect(),
+        }
+    }
+}
+
+/// Squares around the king of `c`, and the ones further ahead of it.
+fn king_zone(king: Case, c: Color) -> u64 {
+    let around = king_attacks(king) | king.board();
+    around | c.map(around << 8, around >> 8)
+}
+
+/// Pawn shield, pawn storm and open files on the king's file and the
+/// adjacent ones.
+fn shelter(b: &Board, king: Case, c: Color, w: &KingWeights) -> Tapered {
+    let ours = b.get_pc_board(Piece::Pawn, c);
+    let theirs = b.get_pc_board(Piece::Pawn, !c);
+    let mut score = Tapered::default();
+    let first = king.col().saturating_sub(1);
+    let last = std::cmp::min(king.col() + 1, 7);
+    for col in first..=last {
+        let file = FILE_A << col;
+        if (ours | theirs) & file == 0 {
+            score += w.open_file;
+        } else if ours & file == 0 {
+            score += w.half_open_file;
+        }
+        for (distance, &bonus) in w.shield.iter().enumerate() {
+            if let Some(sq) = king.try_offset(c.map(1, -1) * (distance as i8 + 1), 0) {
+                if ours & (file & (0xff << (8 * sq.row()))) != 0 {
+                    score += bonus;
+                }
+            }
+        }
+        let storm = (1..8i8).find(|&d| {
+            king.try_offset(c.map(d, -d), 0)
+                .is_some_and(|sq| theirs & file & (0xff << (8 * sq.row())) != 0)
+        });
+        if let Some(d) = storm {
+            score += w.storm[d as usize];
+        }
+    }
+    score
+}
+
+/// Danger from the pieces of `!c` attacking the king zone of `c`, looked
+/// up in the nonlinear safety table by their attack units.
+fn king_danger(b: &Board, king: Case, c: Color, w: &KingWeights) -> i32 {
+    use crate::Piece::*;
+    let zone = king_zone(king, c);
+    let occupied = b.all();
+    let mut attackers = 0;
+    let mut units = 0;
+    for &p in [Knight, Bishop, Rook, Queen].iter() {
+        for sq in CaseIterator::new(b.get_pc_board(p, !c)) {
+            let hits = piece_attacks(p, sq, occupied) & zone;
+            if hits != 0 {
+                attackers += 1;
+                units += w.attack_weight[p as usize] + hits.count_ones() as i32;
+            }
+        }
+    }
+    if attackers < 2 || w.safety.is_empty() {
+        return 0;
+    }
+    w.safety[std::cmp::min(units as usize, w.safety.len() - 1)]
+}
+
+/// Safety of the king of `c`: positive when sheltered, negative when exposed.
+pub fn evaluate(b: &Board, c: Color, w: &KingWeights) -> Tapered {
+    let king = b.get_pc_board(Piece::King, c);
+    if king == 0 {
+        return Tapered::default();
+    }
+    let king = Case(king.trailing_zeros() as u8);
+    shelter(b, king, c, w) - Tapered::new(king_danger(b, king, c, w), 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn safety(fen: &str) -> Tapered {
+        evaluate(&parse_fen(fen).unwrap(), Color::White, &KingWeights::default())
+    }
+
+    #[test]
+    fn king_safety() {
+        let w = KingWeights::default();
+        // full shield in front of the castled king
+        assert_eq!(w.shield[0] * 3, safety("4k3/8/8/8/8/8/5PPP/6K1 w"));
+        // pushed pawn and open file
+        assert_eq!(w.shield[0] + w.shield[1] + w.open_file, safety("4k3/8/8/8/8/6P1/7P/6K1 w"));
+        // a pawn storming down the g file
+        assert_eq!(w.shield[0] * 3 + w.storm[3], safety("4k3/8/8/8/6p1/8/5PPP/6K1 w"));
+        // the king marching up the board loses its shelter
+        assert!(safety("4k3/8/8/8/4K3/8/5PPP/8 w").mg < safety("4k3/8/8/8/8/8/5PPP/6K1 w").mg);
+
+        // a lone queen is not an attack, queen and rook together are
+        let lone = safety("4k3/8/8/8/8/5q2/5PPP/6K1 w");
+        assert_eq!(w.shield[0] * 3, lone);
+        let attack = safety("4k3/8/8/8/8/5q2/5PPP/4r1K1 w");
+        assert!(attack.mg < lone.mg - 50);
+        assert_eq!(lone.eg, attack.eg);
+    }
+}
@@ -1,8 +1,22 @@
 mod ai;
 mod board;
+mod dtm;
+mod endgame;
 mod evaluate;
+mod king_safety;
+mod mobility;
 mod move_generator;
+mod nnue;
+mod pawns;
+mod positional;
 mod player;
+mod pst;
+mod score;
+mod see;
+mod syzygy;
+mod train;
+mod tt;
+mod tune;
 mod validator;
 
 #[macro_use]
@@ -17,16 +31,23 @@ extern crate simplelog;
 extern crate bitflags;
 
 extern crate clap;
-use clap::{App, Arg, SubCommand};
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
+use clap::{App, Arg, ArgMatches, SubCommand};
 
 #[cfg(test)]
 #[macro_use]
 extern crate galvanic_assert;
 
+use crate::ai::{SearchLimits, Signals, Strength};
 use crate::board::*;
 use crate::move_generator::*;
 use crate::validator::Validator;
-use player::Player;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 fn main() {
     use simplelog::*;
@@ -57,11 +78,145 @@ fn main() {
                 .help("fen string")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("eval")
+                .short("e")
+                .long("eval")
+                .value_name("EVALUATOR")
+                .help("evaluation function")
+                .possible_values(&evaluate::EVALUATORS)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("eval-file")
+                .long("eval-file")
+                .value_name("FILE")
+                .help("evaluation weights, TOML or JSON")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("nnue-file")
+                .long("nnue-file")
+                .value_name("FILE")
+                .help("network of the nnue evaluator")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("save-eval-file")
+                .long("save-eval-file")
+                .value_name("FILE")
+                .help("writes the evaluation weights and exits")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trace-eval")
+                .long("trace-eval")
+                .help("prints the evaluation of --fen by term and exits")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("dtm-path")
+                .long("dtm-path")
+                .value_name("DIR")
+                .help("distance to mate tables written by tbgen")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("next-move")
                 .short("n")
                 .help("compute next move"),
         )
+        .subcommand(
+            SubCommand::with_name("tune")
+                .about("tunes the evaluation weights on quiet positions")
+                .arg(
+                    Arg::with_name("positions")
+                        .value_name("FILE")
+                        .help("one FEN and game result per line")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("tuned weights, TOML or JSON")
+                        .default_value("tuned.toml"),
+                )
+                .arg(
+                    Arg::with_name("passes")
+                        .short("p")
+                        .long("passes")
+                        .default_value("100"),
+                )
+                .arg(
+                    Arg::with_name("step")
+                        .short("s")
+                        .long("step")
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::with_name("threads")
+                        .short("t")
+                        .long("threads")
+                        .default_value("1"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("train")
+                .about("trains the network of the nnue evaluator")
+                .arg(
+                    Arg::with_name("positions")
+                        .value_name("FILE")
+                        .help("one FEN, score and game result per line")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("quantized network")
+                        .default_value("net.nnue"),
+                )
+                .arg(Arg::with_name("hidden").long("hidden").default_value("128"))
+                .arg(Arg::with_name("epochs").long("epochs").default_value("10"))
+                .arg(Arg::with_name("batch").long("batch").default_value("1024"))
+                .arg(Arg::with_name("lr").long("lr").default_value("0.001"))
+                .arg(
+                    Arg::with_name("optimizer")
+                        .long("optimizer")
+                        .possible_values(&["sgd", "adam"])
+                        .default_value("adam"),
+                )
+                .arg(
+                    Arg::with_name("lambda")
+                        .long("lambda")
+                        .help("weight of the score against the game result")
+                        .default_value("0.5"),
+                )
+                .arg(Arg::with_name("validation").long("validation").default_value("0.1"))
+                .arg(Arg::with_name("seed").long("seed").default_value("1")),
+        )
+        .subcommand(
+            SubCommand::with_name("tbgen")
+                .about("generates distance to mate tables by retrograde analysis")
+                .arg(
+                    Arg::with_name("endings")
+                        .value_name("ENDING")
+                        .help("material such as KQK, KPK or KRKP, White first")
+                        .multiple(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("DIR")
+                        .help("directory of the .dtm files")
+                        .default_value("."),
+                ),
+        )
         .get_matches();
 
     let uci = !args.is_present("interactive");
@@ -90,14 +245,80 @@ fn main() {
         error!("{}\r\n{:?}", p, backtrace);
     }));
 
+    let mut params = match args.value_of("eval-file") {
+        Some(file) => match evaluate::EvalParams::load(file) {
+            Ok(params) => params,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => evaluate::EvalParams::default(),
+    };
+    if let Some(file) = args.value_of("nnue-file") {
+        params.nnue_file = file.to_string();
+    }
+    if let Some(file) = args.value_of("save-eval-file") {
+        if let Err(e) = params.save(file) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.is_present("trace-eval") {
+        let eval = args.value_of("eval").unwrap_or(DEFAULT_EVALUATOR);
+        let b = match args.value_of("fen") {
+            Some(fen) => match board::parse_fen_color(&mut fen.chars()) {
+                Some((b, _)) => b,
+                None => {
+                    eprintln!("invalid fen {}", fen);
+                    std::process::exit(1);
+                }
+            },
+            None => Board::new_start(),
+        };
+        match evaluate::by_name(eval, &params) {
+            Some(mut e) => println!("{}", e.trace(&b)),
+            None => std::process::exit(1),
+        }
+        return;
+    }
+
+    if let Some(tune) = args.subcommand_matches("tune") {
+        tune_eval(tune, args.value_of("eval").unwrap_or(DEFAULT_EVALUATOR), &params);
+        return;
+    }
+
+    if let Some(train) = args.subcommand_matches("train") {
+        train_network(train);
+        return;
+    }
+
+    if let Some(tbgen) = args.subcommand_matches("tbgen") {
+        generate_tables(tbgen);
+        return;
+    }
+
+    let endgames = match args.value_of("dtm-path") {
+        Some(dir) => Arc::new(dtm::DtmTables::open(dir)),
+        None => dtm::DtmTables::builtin(),
+    };
+
     if uci {
-        engine_uci(args.is_present("next-move"), args.value_of("fen"));
+        engine_uci(
+            args.is_present("next-move"),
+            args.value_of("fen"),
+            args.value_of("eval").unwrap_or(DEFAULT_EVALUATOR),
+            params,
+            endgames,
+        );
         return;
     }
 
     use crate::player::Player;
     let mut bo = Board::new_start();
-    let mut w = player::IOPlayer {};
+    let mut w = player::IOPlayer::new(endgames);
     let mut b = player::SeqPlayer {};
 
     let mut t = 0usize;
@@ -111,7 +332,7 @@ fn main() {
     println!("size of board: {} bytes", std::mem::size_of::<Board>());
     println!("{}", bo);
     loop {
-        let (cur, color): (&mut Player, Color) = if t % 2 == 0 {
+        let (cur, color): (&mut dyn Player, Color) = if t.is_multiple_of(2) {
             (&mut w, Color::White)
         } else {
             (&mut b, Color::Black)
@@ -132,24 +353,58 @@ fn main() {
     }
 }
 
+const DEFAULT_ELO: u32 = 1500;
+const DEFAULT_EVALUATOR: &str = "basic";
+
+/// Change of the engine settings, waiting for the AI to be free.
+type Setting = Box<dyn FnOnce(&mut crate::ai::AiPlayer) + Send>;
+
 struct Engine {
     board: Board,
-    ai: crate::ai::AiPlayer,
-    move_count: usize,
+    ai: Arc<Mutex<crate::ai::AiPlayer>>,
+    signals: Arc<Signals>,
+    search: Option<thread::JoinHandle<()>>,
+    color: Color,
+    // keys of the positions since the last capture or pawn move, the current one excluded
+    keys: Vec<u64>,
+    rule50: usize,
+    ponder: bool,
+    limit_strength: bool,
+    elo: u32,
+    evaluator: String,
+    params: evaluate::EvalParams,
+    // options set while a search holds the AI
+    settings: Vec<Setting>,
 }
 
 impl Engine {
     pub fn new() -> Self {
+        let mut ai = crate::ai::AiPlayer::new([42, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        ai.set_seed(42);
+        ai.on_info(|info| {
+            info!("{}", info);
+            println!("{}", info);
+        });
         Engine {
             board: Board::empty(),
-            ai: crate::ai::AiPlayer::new([42, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
-            move_count: 0,
+            signals: ai.signals(),
+            ai: Arc::new(Mutex::new(ai)),
+            search: None,
+            color: Color::White,
+            keys: Vec::new(),
+            rule50: 0,
+            ponder: false,
+            limit_strength: false,
+            elo: DEFAULT_ELO,
+            evaluator: DEFAULT_EVALUATOR.to_string(),
+            params: evaluate::EvalParams::default(),
+            settings: Vec::new(),
         }
     }
 
-    fn output<S: std::fmt::Display + AsRef<str>>(&self, out: S) {
+    fn output<S: std::fmt::Display + AsRef<str>>(out: S) {
         info!("{}", out);
-        print!("{}\n", out);
+        println!("{}", out);
     }
 
     fn parse_move<I>(it: &mut std::iter::Peekable<I>) -> Option<GenMove>
@@ -161,65 +416,349 @@ impl Engine {
         }
         if let Some(from) = crate::move_generator::Case::parse(it) {
             if let Some(to) = crate::move_generator::Case::parse(it) {
-                let n = { it.peek ().cloned() };
-                if let Some(x) = n {
-                    let promotion = match x {
-                        'k' => Some(Piece::King),
-                        'q' => Some(Piece::Queen),
-                        'r' => Some(Piece::Rook),
-                        'b' => Some(Piece::Bishop),
-                        'n' => Some(Piece::Knight),
-                        'p' => Some(Piece::Pawn),
-                        _ => None,
-                    };
-                    if promotion.is_some() {
-                        it.next();
-                    }
+                let promotion = it.peek().cloned().and_then(promotion_piece);
+                if promotion.is_some() {
+                    it.next();
                 }
-                return Some(GenMove::new(from, to, Flags::NONE)); // TODO flags
+                // the flags come from the matching legal move
+                return Some(GenMove::new(from, to, Flags::NONE).promotion(promotion));
             }
         }
         None
     }
 
+    fn set_option(&mut self, cmd: &str) {
+        let rest = cmd.trim_start_matches("setoption").trim_start();
+        let rest = rest.trim_start_matches("name").trim_start();
+        let (name, value) = match rest.find(" value ") {
+            Some(i) => (&rest[..i], rest[i + 7..].trim()),
+            None => (rest, ""),
+        };
+        match name.to_lowercase().as_str() {
+            "hash" => match value.parse() {
+                Ok(size) => self.configure(move |ai| ai.set_hash_size(size)),
+                Err(_) => error!("invalid hash size {}", value),
+            },
+            "clear hash" => self.configure(|ai| ai.clear_hash()),
+            "threads" => match value.parse() {
+                Ok(count) => self.configure(move |ai| ai.set_threads(count)),
+                Err(_) => error!("invalid thread count {}", value),
+            },
+            "multipv" => match value.parse() {
+                Ok(count) => self.configure(move |ai| ai.set_multipv(count)),
+                Err(_) => error!("invalid MultiPV {}", value),
+            },
+            "contempt" => match value.parse() {
+                Ok(contempt) => self.configure(move |ai| ai.set_contempt(contempt)),
+                Err(_) => error!("invalid contempt {}", value),
+            },
+            "ponder" => self.ponder = value == "true",
+            "uci_limitstrength" => self.limit_strength = value == "true",
+            "uci_elo" => match value.parse() {
+                Ok(elo) => self.elo = elo,
+                Err(_) => error!("invalid Elo {}", value),
+            },
+            "evaluator" => match evaluate::by_name(value, &self.params) {
+                Some(eval) => {
+                    self.evaluator = value.to_string();
+                    self.configure(move |ai| ai.set_evaluator(eval));
+                }
+                None => error!("unknown evaluator {}", value),
+            },
+            "evalfile" => {
+                let params = match value {
+                    "" | "<empty>" => Ok(evaluate::EvalParams::default()),
+                    file => evaluate::EvalParams::load(file),
+                };
+                match params {
+                    Ok(mut params) => {
+                        // keep the network unless the file names one
+                        if params.nnue_file.is_empty() {
+                            params.nnue_file = self.params.nnue_file.clone();
+                        }
+                        self.set_params(params)
+                    }
+                    Err(e) => error!("invalid eval file {}", e),
+                }
+            }
+            "nnuefile" => {
+                let params = evaluate::EvalParams {
+                    nnue_file: value.to_string(),
+                    ..self.params.clone()
+                };
+                self.set_params(params);
+            }
+            "syzygypath" => {
+                let tablebases = match value {
+                    "" | "<empty>" => None,
+                    paths => {
+                        let tablebases = syzygy::Tablebases::open(paths);
+                        Self::output(format!("info string found {} tablebases", tablebases.count()));
+                        Some(Arc::new(tablebases))
+                    }
+                };
+                self.configure(move |ai| ai.set_tablebases(tablebases));
+            }
+            "dtmpath" => {
+                let endgames = match value {
+                    "" | "<empty>" => dtm::DtmTables::builtin(),
+                    dir => Arc::new(dtm::DtmTables::open(dir)),
+                };
+                self.configure(move |ai| ai.set_endgames(Some(endgames)));
+            }
+            "syzygyprobelimit" => match value.parse() {
+                Ok(pieces) => self.configure(move |ai| ai.set_tb_probe_limit(pieces)),
+                Err(_) => error!("invalid probe limit {}", value),
+            },
+            "seed" => match value.parse() {
+                Ok(seed) => self.configure(move |ai| ai.set_seed(seed)),
+                Err(_) => error!("invalid seed {}", value),
+            },
+            _ => error!("unknown option {}", name),
+        }
+    }
+
+    /// Applies a setting to the AI, or keeps it for the next `go` while a
+    /// search is running: waiting for the AI would block the `stop` freeing it.
+    fn configure<F: FnOnce(&mut crate::ai::AiPlayer) + Send + 'static>(&mut self, setting: F) {
+        self.settings.push(Box::new(setting));
+        if let Ok(mut ai) = self.ai.try_lock() {
+            for setting in self.settings.drain(..) {
+                setting(&mut ai);
+            }
+        }
+    }
+
+    /// Replaces the evaluation weights, keeping the selected evaluator.
+    fn set_params(&mut self, params: evaluate::EvalParams) {
+        self.params = params;
+        if let Some(eval) = evaluate::by_name(&self.evaluator, &self.params) {
+            self.configure(move |ai| ai.set_evaluator(eval));
+        }
+    }
+
+    /// Prints the evaluation of the current position by term, an extension
+    /// of the protocol.
+    fn eval(&self) {
+        match evaluate::by_name(&self.evaluator, &self.params) {
+            Some(mut eval) => {
+                for line in eval.trace(&self.board).to_string().lines() {
+                    Self::output(line);
+                }
+            }
+            None => error!("unknown evaluator {}", self.evaluator),
+        }
+    }
+
+    fn parse_go(cmd: &str) -> SearchLimits {
+        let mut limits = SearchLimits::default();
+        let mut tokens = cmd.split_whitespace().skip(1).peekable();
+        while let Some(token) = tokens.next() {
+            let mut ms = || {
+                tokens
+                    .next()
+                    .and_then(|v| v.parse::<i64>().ok())
+                    .map(|v| Duration::from_millis(std::cmp::max(v, 0) as u64))
+            };
+            match token {
+                "infinite" => limits.infinite = true,
+                "ponder" => limits.ponder = true,
+                "movetime" => limits.movetime = ms(),
+                "wtime" => limits.wtime = ms(),
+                "btime" => limits.btime = ms(),
+                "winc" => limits.winc = ms(),
+                "binc" => limits.binc = ms(),
+                "movestogo" => limits.movestogo = tokens.next().and_then(|v| v.parse().ok()),
+                "depth" => limits.depth = tokens.next().and_then(|v| v.parse().ok()),
+                "nodes" => limits.nodes = tokens.next().and_then(|v| v.parse().ok()),
+                "mate" => limits.mate = tokens.next().and_then(|v| v.parse().ok()),
+                "searchmoves" => {
+                    while let Some(m) = tokens.peek().and_then(|v| v.parse::<GenMove>().ok()) {
+                        limits.searchmoves.push(m);
+                        tokens.next();
+                    }
+                }
+                _ => error!("unsupported go parameter {}", token),
+            }
+        }
+        limits
+    }
+
+    /// Starts searching the current position on a worker thread, which
+    /// prints the best move once done.
+    fn go(&mut self, cmd: &str) {
+        self.stop_search();
+        {
+            let mut ai = self.ai.lock().unwrap();
+            for setting in self.settings.drain(..) {
+                setting(&mut ai);
+            }
+            let strength = if self.limit_strength {
+                Some(Strength { elo: self.elo })
+            } else {
+                None
+            };
+            ai.set_strength(strength);
+        }
+        let limits = Self::parse_go(cmd);
+        let color = self.color;
+        let keys = self.keys.clone();
+        let rule50 = self.rule50;
+        let board = self.board.clone();
+        let ai = self.ai.clone();
+        let signals = self.signals.clone();
+        let ponder = self.ponder;
+        signals.stop.store(false, Ordering::Relaxed);
+        signals.ponder.store(limits.ponder, Ordering::Relaxed);
+        self.search = Some(thread::spawn(move || {
+            let result = {
+                let mut ai = ai.lock().unwrap();
+                ai.set_game_history(&keys, rule50);
+                ai.think(color, &board, &limits)
+            };
+            // infinite and ponder searches only report their move when told to
+            while (limits.infinite || signals.ponder.load(Ordering::Relaxed))
+                && !signals.stop.load(Ordering::Relaxed)
+            {
+                thread::sleep(Duration::from_millis(1));
+            }
+            match (result.best, result.ponder) {
+                // checkmated or stalemated, the null move of the protocol
+                (None, _) => Self::output("bestmove 0000"),
+                (Some(best), Some(reply)) if ponder => {
+                    Self::output(format!("bestmove {} ponder {}", best, reply))
+                }
+                (Some(best), _) => Self::output(format!("bestmove {}", best)),
+            }
+        }));
+    }
+
+    /// Sets up the position of `position startpos|fen <FEN> [moves ...]`.
+    fn set_position(&mut self, cmd: &str) {
+        let (setup, moves) = match cmd.find(" moves") {
+            Some(i) => (&cmd[..i], &cmd[i + 6..]),
+            None => (cmd, ""),
+        };
+        let setup = setup.trim_start_matches("position").trim();
+        if setup == "startpos" {
+            self.board = Board::new_start();
+            self.color = Color::White;
+            self.rule50 = 0;
+        } else if let Some(fen) = setup.strip_prefix("fen") {
+            let fen = fen.trim();
+            match board::parse_fen_color(&mut fen.chars()) {
+                Some((b, c)) => {
+                    self.board = b;
+                    self.color = c;
+                }
+                None => {
+                    error!("invalid fen {}", fen);
+                    return;
+                }
+            }
+            self.rule50 = fen
+                .split_whitespace()
+                .nth(4)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0);
+        } else {
+            error!("invalid position {}", cmd);
+            return;
+        }
+        self.keys.clear();
+        let mut it = moves.chars().peekable();
+        while let Some(parsed) = Self::parse_move(&mut it) {
+            let mov = match generate_legal_moves(&self.board, self.color)
+                .into_iter()
+                .find(|m| m.is_same(&parsed))
+            {
+                Some(m) => m,
+                None => {
+                    error!("illegal move {}", parsed);
+                    return;
+                }
+            };
+            let pawn = self.board.get_pc_board(Piece::Pawn, self.color) & (1 << mov.from.0) != 0;
+            let capture = self.board.at_pos(&mov.to).is_some();
+            self.keys.push(self.board.hash(self.color));
+            self.board = match self.board.apply(&mov) {
+                Some(b) => b,
+                None => {
+                    error!("invalid move {}", mov);
+                    return;
+                }
+            };
+            self.color = !self.color;
+            if pawn || capture {
+                // the positions before can't be repeated anymore
+                self.keys.clear();
+                self.rule50 = 0;
+            } else {
+                self.rule50 += 1;
+            }
+        }
+        info!("    final board\r\n {}", self.board);
+    }
+
+    /// Stops the running search, if any, and waits for its best move.
+    fn stop_search(&mut self) {
+        self.signals.stop.store(true, Ordering::Relaxed);
+        if let Some(search) = self.search.take() {
+            search.join().unwrap();
+        }
+    }
+
     pub fn process(&mut self, cmd: &str) {
         match cmd {
-            "quit" => return,
+            "quit" | "stop" => self.stop_search(),
             "uci" => {
-                self.output(format!("id name rustchess {}", "0.1"));
-                self.output("id author theor");
-                self.output("option name Clear Hash type button");
-                self.output("uciok");
+                Self::output(format!("id name rustchess {}", "0.1"));
+                Self::output("id author theor");
+                Self::output(format!(
+                    "option name Hash type spin default {} min 1 max 1024",
+                    tt::DEFAULT_SIZE_MB
+                ));
+                Self::output("option name Clear Hash type button");
+                Self::output("option name Threads type spin default 1 min 1 max 256");
+                Self::output("option name Ponder type check default false");
+                Self::output("option name MultiPV type spin default 1 min 1 max 256");
+                Self::output("option name Contempt type spin default 0 min -100 max 100");
+                Self::output("option name UCI_LimitStrength type check default false");
+                Self::output(format!(
+                    "option name UCI_Elo type spin default {} min {} max {}",
+                    DEFAULT_ELO,
+                    Strength::MIN_ELO,
+                    Strength::MAX_ELO
+                ));
+                Self::output("option name Seed type spin default 42 min 0 max 2147483647");
+                let evaluators: Vec<_> = evaluate::EVALUATORS.iter().map(|e| format!("var {}", e)).collect();
+                Self::output(format!(
+                    "option name Evaluator type combo default {} {}",
+                    DEFAULT_EVALUATOR,
+                    evaluators.join(" ")
+                ));
+                Self::output("option name EvalFile type string default <empty>");
+                Self::output("option name NnueFile type string default <empty>");
+                Self::output("option name SyzygyPath type string default <empty>");
+                Self::output(format!(
+                    "option name SyzygyProbeLimit type spin default {} min 0 max {}",
+                    syzygy::MAX_PIECES,
+                    syzygy::MAX_PIECES
+                ));
+                Self::output("option name DtmPath type string default <empty>");
+                Self::output("uciok");
             }
-            "isready" => self.output("readyok"),
+            "isready" => Self::output("readyok"),
+            "ponderhit" => self.signals.ponder.store(false, Ordering::Relaxed),
             "ucinewgame" => {}
-            "position startpos" => self.board = Board::new_start(), // reset position
-            "position fen <FEN>" => unimplemented!(),               // reset position
+            "eval" => self.eval(),
             _ => {
                 if cmd.starts_with("go") {
-                    let color = if self.move_count % 2 == 0 {
-                        Color::White
-                    } else {
-                        Color::Black
-                    };
-                    let mov = self.ai.get_move(color, &self.board);
-                    //  if self.move_count % 2 == 0 {
-                    //      if self.move_count % 4 == 0 { "b1a3" } else { "a3b1" }
-                    // } else {
-                    //     if self.move_count % 4 == 1 { "b8a6" } else { "a6b8" }
-                    // };
-                    self.output(format!("bestmove {}", mov));
-                } else if cmd.starts_with("position startpos moves") {
-                    // info!("parsing move list");
-                    let mut it = cmd.chars().skip(23).peekable();
-                    self.board = Board::new_start();
-                    self.move_count = 0;
-                    while let Some(mov) = Self::parse_move(&mut it) {
-                        // info!("  move {}", mov);
-                        self.board = self.board.apply(&mov).unwrap();
-                        self.move_count += 1;
-                    }
-                    info!("    final board\r\n {}", self.board);
+                    self.go(cmd);
+                } else if cmd.starts_with("setoption") {
+                    self.set_option(cmd);
+                } else if cmd.starts_with("position") {
+                    self.set_position(cmd);
                 } else {
                     error!("unknown command {}", cmd);
                 }
@@ -228,7 +767,115 @@ impl Engine {
     }
 }
 
-fn engine_uci(next_move: bool, fen: Option<&str>) {
+/// Texel-tunes the weights of `eval`, starting from `params`, and writes
+/// them out after every pass.
+fn tune_eval(args: &ArgMatches, eval: &str, params: &evaluate::EvalParams) {
+    let number = |name: &str| -> usize { parse_arg(args, name) };
+    let samples = match tune::load_samples(args.value_of("positions").unwrap()) {
+        Ok(samples) => samples,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let output = args.value_of("output").unwrap();
+    println!("{} positions", samples.len());
+    let mut tuner = tune::Tuner::new(eval, samples, number("threads"));
+    let k = tuner.fit_k(params);
+    println!("K {:.3} error {:.6}", k, tuner.error(params));
+    tuner.tune(params, number("passes"), number("step") as i32, |pass, error, tuned| {
+        println!("pass {} error {:.6}", pass, error);
+        if let Err(e) = tuned.save(output) {
+            eprintln!("{}", e);
+        }
+    });
+}
+
+fn parse_arg<T: std::str::FromStr>(args: &ArgMatches, name: &str) -> T {
+    let value = args.value_of(name).unwrap();
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("invalid {} {}", name, value);
+        std::process::exit(1);
+    })
+}
+
+/// Trains a network on labeled positions, writing it out after every epoch.
+fn train_network(args: &ArgMatches) {
+    let options = train::TrainOptions {
+        hidden: parse_arg(args, "hidden"),
+        epochs: parse_arg(args, "epochs"),
+        batch_size: parse_arg(args, "batch"),
+        learning_rate: parse_arg(args, "lr"),
+        optimizer: match args.value_of("optimizer") {
+            Some("sgd") => train::Optimizer::Sgd,
+            _ => train::Optimizer::Adam,
+        },
+        validation: parse_arg(args, "validation"),
+        seed: parse_arg(args, "seed"),
+    };
+    let samples = match train::load_samples(args.value_of("positions").unwrap(), parse_arg(args, "lambda")) {
+        Ok(samples) => samples,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let output = args.value_of("output").unwrap();
+    println!("{} positions", samples.len());
+    let mut trainer = train::Trainer::new(options);
+    trainer.train(samples, |trainer, stats| {
+        match stats.validation_loss {
+            Some(v) => println!("epoch {} loss {:.6} validation {:.6}", stats.epoch, stats.train_loss, v),
+            None => println!("epoch {} loss {:.6}", stats.epoch, stats.train_loss),
+        }
+        if let Err(e) = trainer.quantize().save(output) {
+            eprintln!("{}", e);
+        }
+    });
+}
+
+/// Generates the tables of the endings asked for, and those they lead to.
+fn generate_tables(args: &ArgMatches) {
+    let dir = std::path::Path::new(args.value_of("output").unwrap());
+    let mut tables = dtm::DtmTables::new();
+    for ending in args.values_of("endings").unwrap() {
+        let material = match dtm::Material::parse(ending) {
+            Ok(material) => material,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        let result = tables.generate(&material, &mut |table| {
+            let (wins, losses, draws, longest) = table.stats();
+            println!(
+                "{}: {} won, {} lost, {} drawn, longest mate {} plies",
+                table.material(),
+                wins,
+                losses,
+                draws,
+                longest
+            );
+            let path = dir.join(format!("{}.dtm", table.material()));
+            if let Err(e) = table.save(&path) {
+                eprintln!("{}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        });
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn engine_uci(
+    next_move: bool,
+    fen: Option<&str>,
+    eval: &str,
+    params: evaluate::EvalParams,
+    endgames: Arc<dtm::DtmTables>,
+) {
     use std::io;
     use std::io::prelude::*;
 
@@ -238,7 +885,13 @@ fn engine_uci(next_move: bool, fen: Option<&str>) {
             .unwrap_or_else(|| (Board::empty(), Color::White));
         println!("start color: {:?}\r\n{}", c, b);
         let mut ai = ai::AiPlayer::new([42, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-        println!("next: {}", ai.get_move(c, &b));
+        ai.set_evaluator(evaluate::by_name(eval, &params).unwrap());
+        ai.set_endgames(Some(endgames));
+        ai.on_info(|info| println!("{}", info));
+        match ai.think(c, &b, &SearchLimits::default()).best {
+            Some(m) => println!("next: {}", m),
+            None => println!("next: no legal move"),
+        }
         return;
     }
 
@@ -246,21 +899,31 @@ fn engine_uci(next_move: bool, fen: Option<&str>) {
     let stdin = io::stdin();
 
     let mut engine = Engine::new();
+    engine.set_params(params);
+    engine.set_option(&format!("setoption name Evaluator value {}", eval));
+    engine.ai.lock().unwrap().set_endgames(Some(endgames));
 
     loop {
         let mut handle = stdin.lock();
-        handle.read_line(&mut buffer).unwrap();
+        if handle.read_line(&mut buffer).unwrap() == 0 {
+            engine.stop_search();
+            break;
+        }
         {
             let cmd = buffer.trim_end();
 
             info!("{:?}", cmd);
             engine.process(cmd);
+            if cmd == "quit" {
+                break;
+            }
         }
         buffer.clear();
     }
 }
 
 #[cfg(test)]
+#[allow(unused_imports, clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -315,6 +978,75 @@ mod tests {
         assert_eq!(false, b.color_at(Color::Black, &Case::new(0, 0)));
     }
 
+    #[test]
+    fn parse_go() {
+        let limits = Engine::parse_go("go wtime 1000 btime -20 winc 10 movestogo 5");
+        assert_eq!(
+            SearchLimits {
+                wtime: Some(Duration::from_millis(1000)),
+                btime: Some(Duration::from_millis(0)),
+                winc: Some(Duration::from_millis(10)),
+                movestogo: Some(5),
+                ..SearchLimits::default()
+            },
+            limits
+        );
+        assert!(Engine::parse_go("go infinite").infinite);
+        let limits = Engine::parse_go("go searchmoves e2e4 d2d4 depth 6 nodes 5000 mate 3");
+        assert_eq!(
+            SearchLimits {
+                searchmoves: vec!["e2e4".parse().unwrap(), "d2d4".parse().unwrap()],
+                depth: Some(6),
+                nodes: Some(5000),
+                mate: Some(3),
+                ..SearchLimits::default()
+            },
+            limits
+        );
+        assert!(Engine::parse_go("go ponder wtime 10 btime 10").ponder);
+        let limits = Engine::parse_go("go searchmoves a7a8n a7a8 depth 2");
+        let push = GenMove::new(Case::new(6, 0), Case::new(7, 0), Flags::NONE);
+        assert_eq!(
+            vec![push.promotion(Some(Piece::Knight)), push],
+            limits.searchmoves
+        );
+    }
+
+    #[test]
+    fn set_position() {
+        let mut engine = Engine::new();
+        engine.process("position startpos moves g1f3 g8f6 f3g1 f6g8");
+        assert_eq!(Board::new_start(), engine.board);
+        assert_eq!(Color::White, engine.color);
+        assert_eq!(4, engine.rule50);
+        assert_eq!(Board::new_start().hash(Color::White), engine.keys[0]);
+
+        engine.process("position fen 4k3/8/8/8/8/8/4P3/R3K3 b - - 12 40 moves e8d8 e2e4 d8e8");
+        assert_eq!(Color::White, engine.color);
+        assert_eq!(1, engine.rule50);
+        assert_eq!(1, engine.keys.len());
+
+        engine.process("position fen 8/P6k/8/8/8/8/8/K7 w - - 0 1 moves a7a8q h7g7");
+        assert_eq!(board::parse_fen("Q7/6k1/8/8/8/8/8/K7 w").unwrap(), engine.board);
+        assert_eq!(1, engine.rule50);
+        engine.process("position fen 8/P6k/8/8/8/8/8/K7 w - - 0 1 moves a7a8n");
+        assert_eq!(board::parse_fen("N7/7k/8/8/8/8/8/K7 b").unwrap(), engine.board);
+        assert_eq!(Color::Black, engine.color);
+    }
+
+    #[test]
+    fn set_option_while_searching() {
+        let mut engine = Engine::new();
+        engine.process("position startpos");
+        engine.process("go infinite");
+        engine.process("setoption name Clear Hash");
+        engine.process("setoption name UCI_LimitStrength value true");
+        engine.process("stop");
+        engine.process("go depth 1");
+        engine.process("stop");
+        assert!(engine.settings.is_empty());
+    }
+
     #[test]
     fn move_bank() {
         let b = Board::new_start();
@@ -0,0 +1,98 @@
+use board::*;
+use evaluate::Tapered;
+use move_generator::{pawn_attacks, piece_attacks, CaseIterator};
+use serde::{Deserialize, Serialize};
+
+/// Mobility weights, in centipawns, by the number of safe squares a piece
+/// attacks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MobilityWeights {
+    pub knight: [Tapered; 9],
+    pub bishop: [Tapered; 14],
+    pub rook: [Tapered; 15],
+    pub queen: [Tapered; 28],
+}
+
+const fn t(mg: i32, eg: i32) -> Tapered {
+    Tapered::new(mg, eg)
+}
+
+impl Default for MobilityWeights {
+    #[rustfmt::skip]
+    fn default() -> Self {
+        MobilityWeights {
+            knight: [
+                t(-30, -40), t(-20, -28), t(-6, -14), t(-2, -6), t(2, 4), t(6, 8), t(10, 12), t(14, 14),
+                t(17, 16),
+            ],
+            bishop: [
+                t(-25, -30), t(-10, -16), t(4, -4), t(8, 4), t(12, 10), t(18, 16), t(21, 22), t(24, 25),
+                t(26, 28), t(28, 30), t(30, 32), t(33, 33), t(35, 34), t(38, 36),
+            ],
+            rook: [
+                t(-30, -40), t(-14, -12), t(-6, 2), t(-4, 10), t(-2, 16), t(0, 24), t(4, 30), t(8, 34),
+                t(12, 40), t(14, 44), t(16, 48), t(18, 52), t(20, 55), t(22, 57), t(26, 58),
+            ],
+            queen: [
+                t(-20, -24), t(-8, -10), t(-2, -4), t(0, 2), t(2, 8), t(4, 12), t(6, 16), t(8, 19),
+                t(10, 22), t(11, 25), t(12, 28), t(13, 30), t(14, 32), t(15, 34), t(16, 36), t(17, 38),
+                t(18, 40), t(19, 42), t(20, 44), t(21, 46), t(22, 48), t(23, 50), t(24, 52), t(25, 54),
+                t(26, 56), t(27, 58), t(28, 60), t(29, 62),
+            ],
+        }
+    }
+}
+
+impl MobilityWeights {
+    fn table(&self, p: Piece) -> &[Tapered] {
+        match p {
+            Piece::Knight => &self.knight,
+            Piece::Bishop => &self.bishop,
+            Piece::Rook => &self.rook,
+            Piece::Queen => &self.queen,
+            _ => &[],
+        }
+    }
+}
+
+/// Mobility of the pieces of `c`: squares they attack that are neither
+/// blocked by their own pawns or king nor attacked by an enemy pawn.
+pub fn evaluate(b: &Board, c: Color, w: &MobilityWeights) -> Tapered {
+    use crate::Piece::*;
+    let occupied = b.all();
+    let ours = b.get_player_board(c);
+    let unsafe_squares = ours.pawns | ours.king | pawn_attacks(b.get_pc_board(Pawn, !c), !c);
+    let mut score = Tapered::default();
+    for &p in [Knight, Bishop, Rook, Queen].iter() {
+        let table = w.table(p);
+        for sq in CaseIterator::new(ours.get_pc_board(p)) {
+            let count = (piece_attacks(p, sq, occupied) & !unsafe_squares).count_ones() as usize;
+            score += table[std::cmp::min(count, table.len() - 1)];
+        }
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mobility(fen: &str) -> Tapered {
+        evaluate(&parse_fen(fen).unwrap(), Color::White, &MobilityWeights::default())
+    }
+
+    #[test]
+    fn safe_squares() {
+        let w = MobilityWeights::default();
+        // a knight in the corner reaches two squares, in the center eight
+        assert_eq!(w.knight[2], mobility("4k3/8/8/8/8/8/8/N3K3 w"));
+        assert_eq!(w.knight[8], mobility("4k3/8/8/8/3N4/8/8/4K3 w"));
+        // the d7 pawn takes away c6 and e6
+        assert_eq!(w.knight[6], mobility("4k3/3p4/8/8/3N4/8/8/4K3 w"));
+        // own pawn and king block the rook, the b2 pawn covers c1
+        assert_eq!(w.rook[2], mobility("4k3/8/8/8/8/8/Pp6/R3K3 w"));
+        // squares held by the other pieces still count
+        assert_eq!(w.bishop[0] + w.rook[1] + w.queen[2], mobility("4k3/8/8/8/8/8/PPPPPPPP/1BQRK3 w"));
+    }
+}
@@ -39,17 +39,18 @@ impl Case {
     where
         I: Iterator<Item = char>,
     {
-        let fcx = it.next()?;
-        let fx = fcx as i8 - 'a' as i8;
-        let fy = it.next().expect(&format!("should find a digit after {}", fcx)).to_digit(10).expect("digit");
-        let from = Case::new((fy - 1) as u8, fx as u8);
-        Some(from)
+        let file = it.next()?;
+        let rank = it.next()?.to_digit(10)?;
+        if !('a'..='h').contains(&file) || !(1..=8).contains(&rank) {
+            return None;
+        }
+        Some(Case::new(rank as u8 - 1, file as u8 - b'a'))
     }
 
     pub fn try_offset(&self, row: i8, col: i8) -> Option<Self> {
         let nrow = self.row() as i8 + row;
         let ncol = self.col() as i8 + col;
-        if nrow >= 0 && nrow < 8 && ncol >= 0 && ncol < 8 {
+        if (0..8).contains(&nrow) && (0..8).contains(&ncol) {
             Some(Case::new(nrow as u8, ncol as u8))
         } else {
             None
@@ -79,13 +80,13 @@ impl Case {
     }
 }
 
-impl Into<Case> for u8 {
-    fn into(self) -> Case {
-        Case(self)
+impl From<u8> for Case {
+    fn from(val: u8) -> Self {
+        Case(val)
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct GenMove {
     pub from: Case,
     pub to: Case,
@@ -101,11 +102,23 @@ impl GenMove {
     pub fn promotion(self, p:Option<Piece>) -> Self {
         GenMove { promotion: p, ..self }
     }
+
+    /// Same squares and promotion, whatever the flags.
+    pub fn is_same(&self, other: &GenMove) -> bool {
+        self.from == other.from && self.to == other.to && self.promotion == other.promotion
+    }
 }
 
 impl std::fmt::Display for GenMove {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}{}{}", self.from, self.to, self.promotion.map_or("", |p| ""))
+        let promotion = match self.promotion {
+            Some(Piece::Queen) => "q",
+            Some(Piece::Rook) => "r",
+            Some(Piece::Bishop) => "b",
+            Some(Piece::Knight) => "n",
+            _ => "",
+        };
+        write!(f, "{}{}{}", self.from, self.to, promotion)
     }
 }
 
@@ -114,14 +127,14 @@ impl FromStr for GenMove {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut it = s.chars();
-        let f = Case::parse(&mut it).unwrap();
-        let t = Case::parse(&mut it).unwrap();
-        Ok(GenMove::new(f, t, Flags::NONE))
+        let f = Case::parse(&mut it).ok_or(ParseError::Error)?;
+        let t = Case::parse(&mut it).ok_or(ParseError::Error)?;
+        let promotion = it.next().and_then(promotion_piece);
+        Ok(GenMove::new(f, t, Flags::NONE).promotion(promotion))
     }
 }
 
 
-use std::{char::ParseCharError, num::ParseIntError};
 
 #[derive(Debug)]
 pub enum ParseError {
@@ -143,7 +156,7 @@ pub enum ParseError {
 // }
 
 
-struct CaseIterator {
+pub struct CaseIterator {
     bitboard: u64,
     last: i8,
 }
@@ -165,7 +178,7 @@ impl Iterator for CaseIterator {
                 self.last = 64;
                 Some(Case(63))
             } else {
-                self.bitboard = self.bitboard >> (t + 1);
+                self.bitboard >>= t + 1 ;
                 self.last += t as i8 + 1;
                 Some(Case(self.last as u8))
             }
@@ -180,7 +193,7 @@ impl CaseIterator {
 }
 
 pub fn generate_knight_moves(
-    color: Color,
+    _color: Color,
     player: &PartialBoard,
     other: &PartialBoard,
     moves: &mut Vec<GenMove>,
@@ -205,6 +218,20 @@ pub fn generate_knight_moves(
     }
 }
 
+/// Pieces a pawn can promote to, the strongest first.
+pub const PROMOTIONS: [Piece; 4] = [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight];
+
+/// Promotion piece of its UCI letter, such as `q` in `e7e8q`.
+pub fn promotion_piece(letter: char) -> Option<Piece> {
+    match letter {
+        'q' => Some(Piece::Queen),
+        'r' => Some(Piece::Rook),
+        'b' => Some(Piece::Bishop),
+        'n' => Some(Piece::Knight),
+        _ => None,
+    }
+}
+
 pub fn generate_pawn_moves(
     color: Color,
     player: &PartialBoard,
@@ -212,10 +239,18 @@ pub fn generate_pawn_moves(
     moves: &mut Vec<GenMove>,
 ) {
     // TODO en passant
-    // TODO promotion
 
+    let last_rank = color.map(7, 0);
     for piece in CaseIterator::new(player.get_pc_board(Piece::Pawn)) {
-        let mut capture = false;
+        let mut push = |dest: Case, flags: Flags| {
+            if dest.row() == last_rank {
+                for &p in PROMOTIONS.iter() {
+                    moves.push(GenMove::new(piece, dest, flags).promotion(Some(p)));
+                }
+            } else {
+                moves.push(GenMove::new(piece, dest, flags));
+            }
+        };
 
         let cached_captures = color.map(
             PAWN_MOVES_WHITE_CAPTURES[piece.0 as usize],
@@ -232,32 +267,60 @@ pub fn generate_pawn_moves(
             //     other.all() & dest.board()
             // );
             if other.all() & dest.board() != 0 {
-                moves.push(GenMove::new(piece, dest, Flags::CAPTURE));
-                capture = true;
+                push(dest, Flags::CAPTURE);
             }
         }
-        if !capture {
-            let cached = color.map(
-                PAWN_MOVES_WHITE[piece.0 as usize],
-                PAWN_MOVES_BLACK[piece.0 as usize],
-            );
-
-            for dest in CaseIterator::new(cached) {
-                if player.all() & dest.board() == 0 && other.all() & dest.board() == 0 {
-                    let p = if (color == Color::White && dest.row() == 7)
-                        || (color == Color::Black && dest.row() == 0)
-                    {
-                        Some(Piece::Queen)
-                    } else {
-                        None
-                    };
-                    moves.push(GenMove::new(piece, dest, Flags::NONE).promotion(p))
-                }
+
+        let cached = color.map(
+            PAWN_MOVES_WHITE[piece.0 as usize],
+            PAWN_MOVES_BLACK[piece.0 as usize],
+        );
+
+        let occupied = player.all() | other.all();
+        for dest in CaseIterator::new(cached) {
+            // a double push also needs the square it crosses
+            let crossed = if dest.0.abs_diff(piece.0) == 16 {
+                Case((dest.0 + piece.0) / 2).board()
+            } else {
+                0
+            };
+            if occupied & (dest.board() | crossed) == 0 {
+                push(dest, Flags::NONE);
             }
         }
     }
 }
 
+pub const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (-1, 1), (1, -1), (-1, -1)];
+pub const ROOK_DIRECTIONS: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+pub const QUEEN_DIRECTIONS: [(i8, i8); 8] = [
+    (1, 1),
+    (-1, 1),
+    (1, -1),
+    (-1, -1),
+    (1, 0),
+    (-1, 0),
+    (0, 1),
+    (0, -1),
+];
+
+/// Squares reached from `from` along `offsets`, each ray stopping on the
+/// first occupied square (included). Rays are one step long if `single_step`.
+pub fn sliding_attacks(from: Case, occupied: u64, offsets: &[(i8, i8)], single_step: bool) -> u64 {
+    let mut attacks = 0u64;
+    for offset in offsets {
+        let mut cur = from;
+        while let Some(dest) = cur.try_offset(offset.0, offset.1) {
+            cur = dest;
+            attacks |= dest.board();
+            if single_step || occupied & dest.board() != 0 {
+                break;
+            }
+        }
+    }
+    attacks
+}
+
 fn generate_sliding_moves(
     pieces: u64,
     player: &PartialBoard,
@@ -266,105 +329,52 @@ fn generate_sliding_moves(
     offsets: &[(i8, i8)],
     is_king: bool,
 ) {
-    for rook in CaseIterator::new(pieces) {
-        for offset in offsets {
-            let mut cur = rook.clone();
-            while let Some(dest) = cur.try_offset(offset.0, offset.1) {
-                cur = dest;
-                if player.all() & cur.board() != 0 {
-                    break;
-                } else if other.all() & cur.board() != 0 {
-                    moves.push(GenMove::new(rook, dest, Flags::CAPTURE));
-                    break;
-                }
-                moves.push(GenMove::new(rook, dest, Flags::NONE));
-                if is_king {
-                    break;
-                }
-            }
+    for piece in CaseIterator::new(pieces) {
+        let attacks = sliding_attacks(piece, player.all() | other.all(), offsets, is_king);
+        for dest in CaseIterator::new(attacks & !player.all()) {
+            let flags = if other.all() & dest.board() == 0 {
+                Flags::NONE
+            } else {
+                Flags::CAPTURE
+            };
+            moves.push(GenMove::new(piece, dest, flags));
         }
     }
 }
 
 pub fn generate_bishop_moves(
-    color: Color,
+    _color: Color,
     player: &PartialBoard,
     other: &PartialBoard,
     moves: &mut Vec<GenMove>,
 ) {
-    generate_sliding_moves(
-        player.bishops,
-        player,
-        other,
-        moves,
-        &[(1, 1), (-1, 1), (1, -1), (-1, -1)],
-        false,
-    )
+    generate_sliding_moves(player.bishops, player, other, moves, &BISHOP_DIRECTIONS, false)
 }
 pub fn generate_rook_moves(
-    color: Color,
+    _color: Color,
     player: &PartialBoard,
     other: &PartialBoard,
     moves: &mut Vec<GenMove>,
 ) {
-    generate_sliding_moves(
-        player.rooks,
-        player,
-        other,
-        moves,
-        &[(1, 0), (-1, 0), (0, 1), (0, -1)],
-        false,
-    )
+    generate_sliding_moves(player.rooks, player, other, moves, &ROOK_DIRECTIONS, false)
 }
 
 pub fn generate_queen_moves(
-    color: Color,
+    _color: Color,
     player: &PartialBoard,
     other: &PartialBoard,
     moves: &mut Vec<GenMove>,
 ) {
-    generate_sliding_moves(
-        player.queens,
-        player,
-        other,
-        moves,
-        &[
-            (1, 1),
-            (-1, 1),
-            (1, -1),
-            (-1, -1),
-            (1, 0),
-            (-1, 0),
-            (0, 1),
-            (0, -1),
-        ],
-        false,
-    )
+    generate_sliding_moves(player.queens, player, other, moves, &QUEEN_DIRECTIONS, false)
 }
 
 pub fn generate_king_moves(
-    color: Color,
+    _color: Color,
     player: &PartialBoard,
     other: &PartialBoard,
     moves: &mut Vec<GenMove>,
 ) {
-    generate_sliding_moves(
-        player.king,
-        player,
-        other,
-        moves,
-        &[
-            (1, 1),
-            (-1, 1),
-            (1, -1),
-            (-1, -1),
-            (1, 0),
-            (-1, 0),
-            (0, 1),
-            (0, -1),
-        ],
-        true,
-    )
+    generate_sliding_moves(player.king, player, other, moves, &QUEEN_DIRECTIONS, true)
 }
 
 pub fn generate_all_moves(
@@ -373,23 +383,85 @@ pub fn generate_all_moves(
     other: &PartialBoard,
     moves: &mut Vec<GenMove>,
 ) {
-    generate_knight_moves(player, &this, &other, moves);
-    generate_queen_moves(player, &this, &other, moves);
-    generate_king_moves(player, &this, &other, moves);
-    generate_rook_moves(player, &this, &other, moves);
-    generate_bishop_moves(player, &this, &other, moves);
-    generate_pawn_moves(player, &this, &other, moves);
+    generate_knight_moves(player, this, other, moves);
+    generate_queen_moves(player, this, other, moves);
+    generate_king_moves(player, this, other, moves);
+    generate_rook_moves(player, this, other, moves);
+    generate_bishop_moves(player, this, other, moves);
+    generate_pawn_moves(player, this, other, moves);
 }
 
 pub fn generate_moves(b: &Board, player: Color) -> Vec<GenMove> {
     let mut moves = Vec::new();
     let (this, other) = (b.get_player_board(player), b.get_player_board(!player));
 
-    generate_all_moves(player, &this, &other, &mut moves);
+    generate_all_moves(player, this, other, &mut moves);
 
     moves
 }
 
+/// Moves of `player` that don't leave its own king attacked.
+pub fn generate_legal_moves(b: &Board, player: Color) -> Vec<GenMove> {
+    generate_moves(b, player)
+        .into_iter()
+        .filter(|m| !in_check(&b.apply(m).unwrap(), player))
+        .collect()
+}
+
+pub const FILE_A: u64 = 0x0101_0101_0101_0101;
+pub const FILE_H: u64 = 0x8080_8080_8080_8080;
+
+/// Squares attacked by the given pawns of `color`.
+pub fn pawn_attacks(pawns: u64, color: Color) -> u64 {
+    match color {
+        Color::White => ((pawns << 7) & !FILE_H) | ((pawns << 9) & !FILE_A),
+        Color::Black => ((pawns >> 9) & !FILE_H) | ((pawns >> 7) & !FILE_A),
+    }
+}
+
+pub fn knight_attacks(from: Case) -> u64 {
+    KNIGHT_MOVES[from.0 as usize]
+}
+
+pub fn king_attacks(from: Case) -> u64 {
+    KING_MOVES[from.0 as usize]
+}
+
+/// Squares attacked by a knight, bishop, rook, queen or king on `from`.
+pub fn piece_attacks(p: Piece, from: Case, occupied: u64) -> u64 {
+    match p {
+        Piece::Pawn => panic!("pawn attacks depend on the color"),
+        Piece::Knight => knight_attacks(from),
+        Piece::Bishop => sliding_attacks(from, occupied, &BISHOP_DIRECTIONS, false),
+        Piece::Rook => sliding_attacks(from, occupied, &ROOK_DIRECTIONS, false),
+        Piece::Queen => sliding_attacks(from, occupied, &QUEEN_DIRECTIONS, false),
+        Piece::King => king_attacks(from),
+    }
+}
+
+/// Pieces of color `by` attacking `target`, considering only the pieces in
+/// `occupied` (removing pieces from it uncovers x-ray attackers).
+pub fn attackers_to(b: &Board, target: Case, by: Color, occupied: u64) -> u64 {
+    let them = b.get_player_board(by);
+    let diagonal = them.bishops | them.queens;
+    let straight = them.rooks | them.queens;
+    let attackers = (pawn_attacks(target.board(), !by) & them.pawns)
+        | (knight_attacks(target) & them.knights)
+        | (king_attacks(target) & them.king)
+        | (sliding_attacks(target, occupied, &BISHOP_DIRECTIONS, false) & diagonal)
+        | (sliding_attacks(target, occupied, &ROOK_DIRECTIONS, false) & straight);
+    attackers & occupied
+}
+
+pub fn is_attacked(b: &Board, target: Case, by: Color) -> bool {
+    attackers_to(b, target, by, b.all()) != 0
+}
+
+pub fn in_check(b: &Board, c: Color) -> bool {
+    let king = b.get_player_board(c).king;
+    king != 0 && is_attacked(b, Case(king.trailing_zeros() as u8), !c)
+}
+
 fn generate_pawn_boards(row_double: u8, factor: i8) -> [u64; 64] {
     let mut a = [0u64; 64];
     for c in 0..64 {
@@ -409,7 +481,7 @@ fn generate_pawn_boards_capture(factor: i8) -> [u64; 64] {
     for c in 0..64 {
         let case: Case = c.into();
         match case.row() {
-            1...6 => {
+            1..=6 => {
                 if case.col() < 7 {
                     a[c as usize] |= case.offset(factor, 1).board();
                 }
@@ -428,6 +500,13 @@ lazy_static! {
     static ref PAWN_MOVES_BLACK: [u64; 64] = generate_pawn_boards(6, -1);
     static ref PAWN_MOVES_WHITE_CAPTURES: [u64; 64] = generate_pawn_boards_capture(1);
     static ref PAWN_MOVES_BLACK_CAPTURES: [u64; 64] = generate_pawn_boards_capture(-1);
+    static ref KING_MOVES: [u64; 64] = {
+        let mut a = [0u64; 64];
+        for (c, moves) in a.iter_mut().enumerate() {
+            *moves = sliding_attacks(Case(c as u8), 0, &QUEEN_DIRECTIONS, true);
+        }
+        a
+    };
     static ref KNIGHT_MOVES: [u64; 64] = {
         let mut a = [0u64; 64];
         for c in 0..64 {
@@ -454,13 +533,14 @@ lazy_static! {
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_borrow)]
 mod tests {
     use super::*;
     use galvanic_assert::matchers::collection::*;
     use galvanic_assert::matchers::*;
 
     fn m(s: &str) -> GenMove {
-        assert!(s.len() == 4 || s.len() == 5);
+        assert!((4..=6).contains(&s.len()));
         let mut chars = s.chars().peekable();
         let from = Case::parse(&mut chars).unwrap();
         let flags = match chars.peek() {
@@ -471,8 +551,15 @@ mod tests {
             _ => Flags::NONE,
         };
         let to = Case::parse(&mut chars).unwrap();
+        let promotion = chars.next().map(|p| match p {
+            'q' => Piece::Queen,
+            'r' => Piece::Rook,
+            'b' => Piece::Bishop,
+            'n' => Piece::Knight,
+            _ => panic!("bad promotion {}", p),
+        });
 
-        GenMove::new(from, to, flags)
+        GenMove::new(from, to, flags).promotion(promotion)
     }
 
     fn parse_case(s: &str) -> Case {
@@ -634,7 +721,20 @@ mod tests {
                 (Color::White, Piece::Pawn, "e2"),
                 (Color::Black, Piece::Knight, "f3"),
             ],
-            vec!["e2xf3"],
+            vec!["e2xf3", "e2e3", "e2e4"],
+            generate_pawn_moves,
+        )
+    }
+
+    #[test]
+    fn genmoves_pawn_white_blocked() {
+        test_moves_f(
+            Color::White,
+            vec![
+                (Color::White, Piece::Pawn, "e2"),
+                (Color::Black, Piece::Knight, "e3"),
+            ],
+            Vec::<&str>::new(),
             generate_pawn_moves,
         )
     }
@@ -647,7 +747,36 @@ mod tests {
                 (Color::Black, Piece::Pawn, "f3"),
                 (Color::White, Piece::Knight, "e2"),
             ],
-            vec!["f3xe2"],
+            vec!["f3xe2", "f3f2"],
+            generate_pawn_moves,
+        )
+    }
+
+    #[test]
+    fn genmoves_pawn_white_promotion() {
+        test_moves_f(
+            Color::White,
+            vec![
+                (Color::White, Piece::Pawn, "e7"),
+                (Color::Black, Piece::Knight, "d8"),
+            ],
+            vec![
+                "e7e8q", "e7e8r", "e7e8b", "e7e8n", "e7xd8q", "e7xd8r", "e7xd8b", "e7xd8n",
+            ],
+            generate_pawn_moves,
+        )
+    }
+
+    #[test]
+    fn genmoves_pawn_black_promotion() {
+        test_moves_f(
+            Color::Black,
+            vec![
+                (Color::Black, Piece::Pawn, "a2"),
+                (Color::White, Piece::Rook, "a1"),
+                (Color::White, Piece::Rook, "b1"),
+            ],
+            vec!["a2xb1q", "a2xb1r", "a2xb1b", "a2xb1n"],
             generate_pawn_moves,
         )
     }
@@ -723,6 +852,55 @@ mod tests {
         )
     }
 
+    #[test]
+    fn pawn_attacks_edges() {
+        let b = parse_fen("8/8/8/8/8/8/P6P/8 w").unwrap();
+        assert_eq!(
+            parse_case("b3").board() | parse_case("g3").board(),
+            pawn_attacks(b.white.pawns, Color::White)
+        );
+        assert_eq!(
+            parse_case("b1").board() | parse_case("g1").board(),
+            pawn_attacks(b.white.pawns, Color::Black)
+        );
+    }
+
+    #[test]
+    fn attackers_with_xray() {
+        let b = parse_fen("3r4/3r4/8/8/1b1N4/2P5/8/6K1 b").unwrap();
+        let target = parse_case("d4");
+        assert_eq!(
+            parse_case("d7").board(),
+            attackers_to(&b, target, Color::Black, b.all())
+        );
+        // removing the front rook uncovers the one behind it
+        let occupied = b.all() ^ parse_case("d7").board();
+        assert_eq!(
+            parse_case("d8").board(),
+            attackers_to(&b, target, Color::Black, occupied)
+        );
+        assert_eq!(
+            parse_case("c3").board(),
+            attackers_to(&b, target, Color::White, b.all())
+        );
+    }
+
+    #[test]
+    fn legal_moves_in_check() {
+        // the king has to step out of the rook's file, the knight can't help
+        let b = parse_fen("4r2k/8/8/8/8/8/1N6/4K3 w").unwrap();
+        assert!(in_check(&b, Color::White));
+        let expected = vec![m("e1d1"), m("e1d2"), m("e1f1"), m("e1f2")];
+        assert_that!(&generate_legal_moves(&b, Color::White), contains_in_any_order(expected));
+    }
+
+    #[test]
+    fn legal_moves_pinned_pawn_push() {
+        // the pawn can't leave the file to take the bishop, but it can push
+        let b = parse_fen("r6k/8/8/8/1b2b3/P7/7r/K7 w").unwrap();
+        assert_eq!(vec![m("a3a4")], generate_legal_moves(&b, Color::White));
+    }
+
     // to test: illegal black d5d4
     // r1bqkbnr/pp6/2n3p1/3ppp1p/2Pp1P1P/1P4P1/P1N1P3/R1BQKBNR b KQkq - 1 12
 }
@@ -0,0 +1,514 @@
+use board::*;
+use endgame;
+use evaluate::{Evaluator, Trace};
+use move_generator::{Case, CaseIterator, GenMove};
+use see::SEE_VALUES;
+use std::path::Path;
+use std::sync::Arc;
+
+/// HalfKA inputs: the square of the king of the side the accumulator is for,
+/// times a piece of either color (kings included), times its square.
+pub const INPUTS: usize = 64 * 12 * 64;
+/// Ceiling of the clipped ReLU: the scale of the quantized activations.
+pub const QA: i32 = 127;
+/// Scale of the quantized output weights.
+pub const QB: i32 = 64;
+/// Centipawns of an output of 1.0.
+pub const OUTPUT_SCALE: i32 = 400;
+
+const MAGIC: &[u8; 4] = b"RCNN";
+const VERSION: u32 = 1;
+
+/// Index of the input of piece `p` of color `c` on `sq`, seen by `pov`
+/// whose king is on `king`. Black sees the board flipped, so that both
+/// sides share the weights.
+pub fn feature(pov: Color, king: Case, p: Piece, c: Color, sq: Case) -> usize {
+    let orient = |s: Case| pov.map(s.0, s.0 ^ 56) as usize;
+    let piece = p as usize + if c == pov { 0 } else { 6 };
+    (orient(king) * 12 + piece) * 64 + orient(sq)
+}
+
+/// Inputs active on `b` for `pov`.
+pub fn features(b: &Board, pov: Color) -> Vec<usize> {
+    use crate::Piece::*;
+    let king = match CaseIterator::new(b.get_pc_board(King, pov)).next() {
+        Some(king) => king,
+        None => return Vec::new(),
+    };
+    let mut features = Vec::with_capacity(32);
+    for &c in [Color::White, Color::Black].iter() {
+        for &p in [Pawn, Knight, Bishop, Rook, Queen, King].iter() {
+            for sq in CaseIterator::new(b.get_pc_board(p, c)) {
+                features.push(feature(pov, king, p, c, sq));
+            }
+        }
+    }
+    features
+}
+
+/// Quantized network: HalfKA inputs to a hidden layer per side, through a
+/// clipped ReLU to a single output, the side to move's half first.
+///
+/// The file holds, little endian: the magic `RCNN`, the version and the
+/// hidden size as u32, then the input weights (i16, one row of `hidden`
+/// per input), the hidden biases (i16), the output weights (i8, `2 *
+/// hidden`) and the output bias (i32).
+#[derive(Clone, PartialEq)]
+pub struct Network {
+    pub hidden: usize,
+    pub ft_weights: Vec<i16>,
+    pub ft_bias: Vec<i16>,
+    pub out_weights: Vec<i8>,
+    pub out_bias: i32,
+}
+
+impl std::fmt::Debug for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Network {{ hidden: {} }}", self.hidden)
+    }
+}
+
+fn read_u32(bytes: &[u8], pos: &mut usize) -> Option<u32> {
+    let v = bytes.get(*pos..*pos + 4)?;
+    *pos += 4;
+    Some(u32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+}
+
+fn read_i16s(bytes: &[u8], pos: &mut usize, count: usize) -> Option<Vec<i16>> {
+    let v = bytes.get(*pos..*pos + 2 * count)?;
+    *pos += 2 * count;
+    Some(v.chunks(2).map(|x| i16::from_le_bytes([x[0], x[1]])).collect())
+}
+
+impl Network {
+    pub fn zeros(hidden: usize) -> Self {
+        Network {
+            hidden,
+            ft_weights: vec![0; INPUTS * hidden],
+            ft_bias: vec![0; hidden],
+            out_weights: vec![0; 2 * hidden],
+            out_bias: 0,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.get(..4)? != MAGIC {
+            return None;
+        }
+        let mut pos = 4;
+        if read_u32(bytes, &mut pos)? != VERSION {
+            return None;
+        }
+        let hidden = read_u32(bytes, &mut pos)? as usize;
+        let ft_weights = read_i16s(bytes, &mut pos, INPUTS.checked_mul(hidden)?)?;
+        let ft_bias = read_i16s(bytes, &mut pos, hidden)?;
+        let out_weights = bytes.get(pos..pos + 2 * hidden)?.iter().map(|&x| x as i8).collect();
+        pos += 2 * hidden;
+        let out_bias = read_u32(bytes, &mut pos)? as i32;
+        if pos != bytes.len() {
+            return None;
+        }
+        Some(Network {
+            hidden,
+            ft_weights,
+            ft_bias,
+            out_weights,
+            out_bias,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16 + 2 * self.ft_weights.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.hidden as u32).to_le_bytes());
+        for w in self.ft_weights.iter().chain(self.ft_bias.iter()) {
+            bytes.extend_from_slice(&w.to_le_bytes());
+        }
+        bytes.extend(self.out_weights.iter().map(|&w| w as u8));
+        bytes.extend_from_slice(&self.out_bias.to_le_bytes());
+        bytes
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Network::from_bytes(&bytes).ok_or_else(|| format!("{}: not a network file", path.display()))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_bytes()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn row(&self, feature: usize) -> &[i16] {
+        &self.ft_weights[feature * self.hidden..(feature + 1) * self.hidden]
+    }
+
+    /// Hidden layer of `pov` computed from scratch.
+    fn refresh(&self, b: &Board, pov: Color, acc: &mut [i16]) {
+        acc.copy_from_slice(&self.ft_bias);
+        for f in features(b, pov) {
+            simd::add(acc, self.row(f));
+        }
+    }
+
+    /// Output in centipawns, from the hidden layers of the side to move and
+    /// of the other side.
+    pub fn output(&self, us: &[i16], them: &[i16]) -> i32 {
+        let (w_us, w_them) = self.out_weights.split_at(self.hidden);
+        let sum = simd::dot(us, w_us) + simd::dot(them, w_them) + self.out_bias;
+        (sum as i64 * OUTPUT_SCALE as i64 / (QA * QB) as i64) as i32
+    }
+}
+
+/// Vector kernels, with AVX2 versions picked at run time.
+pub mod simd {
+    use super::QA;
+
+    pub fn add_scalar(acc: &mut [i16], row: &[i16]) {
+        for (a, &w) in acc.iter_mut().zip(row) {
+            *a = a.wrapping_add(w);
+        }
+    }
+
+    pub fn sub_scalar(acc: &mut [i16], row: &[i16]) {
+        for (a, &w) in acc.iter_mut().zip(row) {
+            *a = a.wrapping_sub(w);
+        }
+    }
+
+    /// Clipped ReLU of `acc` dotted with `weights`.
+    pub fn dot_scalar(acc: &[i16], weights: &[i8]) -> i32 {
+        acc.iter()
+            .zip(weights)
+            .map(|(&a, &w)| (a as i32).clamp(0, QA) * w as i32)
+            .sum()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    mod avx2 {
+        use super::QA;
+        use std::arch::x86_64::*;
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn add(acc: &mut [i16], row: &[i16], sub: bool) {
+            let n = acc.len() / 16 * 16;
+            for i in (0..n).step_by(16) {
+                let a = _mm256_loadu_si256(acc.as_ptr().add(i) as *const __m256i);
+                let w = _mm256_loadu_si256(row.as_ptr().add(i) as *const __m256i);
+                let r = if sub { _mm256_sub_epi16(a, w) } else { _mm256_add_epi16(a, w) };
+                _mm256_storeu_si256(acc.as_mut_ptr().add(i) as *mut __m256i, r);
+            }
+            if sub {
+                super::sub_scalar(&mut acc[n..], &row[n..]);
+            } else {
+                super::add_scalar(&mut acc[n..], &row[n..]);
+            }
+        }
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn dot(acc: &[i16], weights: &[i8]) -> i32 {
+            let n = acc.len() / 16 * 16;
+            let zero = _mm256_setzero_si256();
+            let ceiling = _mm256_set1_epi16(QA as i16);
+            let mut sum = _mm256_setzero_si256();
+            for i in (0..n).step_by(16) {
+                let a = _mm256_loadu_si256(acc.as_ptr().add(i) as *const __m256i);
+                let a = _mm256_min_epi16(_mm256_max_epi16(a, zero), ceiling);
+                let w = _mm256_cvtepi8_epi16(_mm_loadu_si128(weights.as_ptr().add(i) as *const __m128i));
+                sum = _mm256_add_epi32(sum, _mm256_madd_epi16(a, w));
+            }
+            let mut lanes = [0i32; 8];
+            _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, sum);
+            lanes.iter().sum::<i32>() + super::dot_scalar(&acc[n..], &weights[n..])
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn has_avx2() -> bool {
+        is_x86_feature_detected!("avx2")
+    }
+
+    pub fn add(acc: &mut [i16], row: &[i16]) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if has_avx2() {
+                return unsafe { avx2::add(acc, row, false) };
+            }
+        }
+        add_scalar(acc, row)
+    }
+
+    pub fn sub(acc: &mut [i16], row: &[i16]) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if has_avx2() {
+                return unsafe { avx2::add(acc, row, true) };
+            }
+        }
+        sub_scalar(acc, row)
+    }
+
+    pub fn dot(acc: &[i16], weights: &[i8]) -> i32 {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if has_avx2() {
+                return unsafe { avx2::dot(acc, weights) };
+            }
+        }
+        dot_scalar(acc, weights)
+    }
+}
+
+/// Inputs changed by a move: the moved piece leaves its square, a captured
+/// piece disappears, and the moved or promoted piece lands.
+#[derive(Debug, Clone, Copy, Default)]
+struct Update {
+    removed: [Option<(Piece, Color, Case)>; 2],
+    added: Option<(Piece, Color, Case)>,
+    /// Side whose king moved, whose inputs all change.
+    king_moved: Option<Color>,
+}
+
+impl Update {
+    fn new(b: &Board, m: &GenMove) -> Self {
+        let (p, c) = match b.at_pos(&m.from) {
+            Some(x) => x,
+            None => return Update::default(),
+        };
+        Update {
+            removed: [Some((p, c, m.from)), b.at_pos(&m.to).map(|(q, d)| (q, d, m.to))],
+            added: Some((m.promotion.unwrap_or(p), c, m.to)),
+            king_moved: if p == Piece::King { Some(c) } else { None },
+        }
+    }
+}
+
+/// Accumulators of a position: the hidden layers of both sides, computed
+/// lazily from the parent's.
+struct Entry {
+    board: Board,
+    acc: [Vec<i16>; 2],
+    computed: bool,
+    update: Update,
+}
+
+/// Evaluation by a `Network`, its hidden layers updated incrementally along
+/// the moves of the search instead of being recomputed at every node.
+pub struct NnueEvaluator {
+    net: Arc<Network>,
+    stack: Vec<Entry>,
+    len: usize,
+}
+
+impl NnueEvaluator {
+    pub fn new(net: Arc<Network>) -> Self {
+        NnueEvaluator {
+            net,
+            stack: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, board: &Board, update: Update) {
+        if self.len == self.stack.len() {
+            let hidden = self.net.hidden;
+            self.stack.push(Entry {
+                board: board.clone(),
+                acc: [vec![0; hidden], vec![0; hidden]],
+                computed: false,
+                update,
+            });
+        } else {
+            let entry = &mut self.stack[self.len];
+            entry.board.clone_from(board);
+            entry.computed = false;
+            entry.update = update;
+        }
+        self.len += 1;
+    }
+
+    /// Makes `b` the top of the stack, starting over unless it already is.
+    fn sync(&mut self, b: &Board) {
+        if self.len == 0 || self.stack[self.len - 1].board != *b {
+            self.len = 0;
+            self.push(b, Update::default());
+        }
+    }
+
+    /// Computes the top accumulators from the closest computed ancestor,
+    /// refreshing a side when its king moved.
+    fn compute(&mut self) {
+        let top = self.len - 1;
+        let mut first = top;
+        while first > 0 && !self.stack[first].computed {
+            first -= 1;
+        }
+        let net = &self.net;
+        if !self.stack[first].computed {
+            let entry = &mut self.stack[first];
+            for &pov in [Color::White, Color::Black].iter() {
+                net.refresh(&entry.board, pov, &mut entry.acc[pov as usize]);
+            }
+            entry.computed = true;
+        }
+        for i in first + 1..=top {
+            let (done, rest) = self.stack.split_at_mut(i);
+            let parent = &done[i - 1];
+            let entry = &mut rest[0];
+            let update = entry.update;
+            for &pov in [Color::White, Color::Black].iter() {
+                let acc = &mut entry.acc[pov as usize];
+                if update.king_moved == Some(pov) {
+                    net.refresh(&entry.board, pov, acc);
+                    continue;
+                }
+                let king = match CaseIterator::new(entry.board.get_pc_board(Piece::King, pov)).next() {
+                    Some(king) => king,
+                    None => {
+                        net.refresh(&entry.board, pov, acc);
+                        continue;
+                    }
+                };
+                acc.copy_from_slice(&parent.acc[pov as usize]);
+                for &(p, c, sq) in update.removed.iter().flatten() {
+                    simd::sub(acc, net.row(feature(pov, king, p, c, sq)));
+                }
+                if let Some((p, c, sq)) = update.added {
+                    simd::add(acc, net.row(feature(pov, king, p, c, sq)));
+                }
+            }
+            entry.computed = true;
+        }
+    }
+
+    /// Output of the network for `b`, seen by `player`.
+    fn output(&mut self, b: &Board, player: Color) -> i32 {
+        self.sync(b);
+        self.compute();
+        let acc = &self.stack[self.len - 1].acc;
+        self.net.output(&acc[player as usize], &acc[!player as usize])
+    }
+}
+
+impl Evaluator for NnueEvaluator {
+    fn evaluate(&mut self, b: &Board, player: Color) -> i32 {
+        // the output is in centipawns
+        endgame::evaluate(b, player, SEE_VALUES[Piece::Pawn as usize], || self.output(b, player))
+    }
+
+    fn make_move(&mut self, b: &Board, child: &Board, m: &GenMove) {
+        self.sync(b);
+        self.push(child, Update::new(b, m));
+    }
+
+    fn unmake_move(&mut self) {
+        self.len = self.len.saturating_sub(1);
+    }
+
+    fn trace(&mut self, b: &Board) -> Trace {
+        // the network has no separate terms
+        let mut trace = Trace::new(b);
+        let score = self.output(b, Color::White);
+        trace.set_score(b, SEE_VALUES[Piece::Pawn as usize], score);
+        trace
+    }
+
+    fn box_clone(&self) -> Box<dyn Evaluator> {
+        Box::new(NnueEvaluator::new(self.net.clone()))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Network with small random weights.
+    pub fn random_network(hidden: usize, seed: u64) -> Network {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut net = Network::zeros(hidden);
+        net.ft_weights.iter_mut().for_each(|w| *w = rng.gen_range(-8, 9));
+        net.ft_bias.iter_mut().for_each(|w| *w = rng.gen_range(0, 64));
+        net.out_weights.iter_mut().for_each(|w| *w = rng.gen_range(-64, 65));
+        net.out_bias = rng.gen_range(-1000, 1000);
+        net
+    }
+
+    /// Evaluation of `b` for `player` computed from scratch.
+    /// Output of `net` for `b`, computed from scratch.
+    pub fn full_eval(net: &Network, b: &Board, player: Color) -> i32 {
+        let mut us = vec![0; net.hidden];
+        let mut them = vec![0; net.hidden];
+        net.refresh(b, player, &mut us);
+        net.refresh(b, !player, &mut them);
+        net.output(&us, &them)
+    }
+
+    #[test]
+    fn network_file() {
+        let net = random_network(8, 1);
+        assert_eq!(Some(net.clone()), Network::from_bytes(&net.to_bytes()));
+        let path = std::env::temp_dir().join("chess-network.nnue");
+        net.save(&path).unwrap();
+        assert_eq!(net, Network::load(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(Network::from_bytes(b"RCNN").is_none());
+        assert!(Network::from_bytes(&net.to_bytes()[..100]).is_none());
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        let mut rng = SmallRng::seed_from_u64(2);
+        // a length that isn't a multiple of the vector width
+        let acc: Vec<i16> = (0..37).map(|_| rng.gen_range(-300, 300)).collect();
+        let row: Vec<i16> = (0..37).map(|_| rng.gen_range(-300, 300)).collect();
+        let weights: Vec<i8> = (0..37).map(|_| rng.gen_range(-128, 127)).collect();
+        assert_eq!(simd::dot_scalar(&acc, &weights), simd::dot(&acc, &weights));
+        let (mut a, mut b) = (acc.clone(), acc.clone());
+        simd::add(&mut a, &row);
+        simd::add_scalar(&mut b, &row);
+        assert_eq!(a, b);
+        simd::sub(&mut a, &row);
+        assert_eq!(acc, a);
+    }
+
+    #[test]
+    fn incremental_matches_refresh() {
+        let net = Arc::new(random_network(24, 3));
+        let mut e = NnueEvaluator::new(net.clone());
+        let mut b = parse_fen("r3k2r/1P3ppp/8/3pP3/8/8/5PPP/R3K2R w").unwrap();
+        let mut boards = vec![b.clone()];
+        // quiet moves, king moves, promotion with capture, capture
+        for (i, m) in ["e5e6", "d5d4", "e1d2", "e8d8", "b7a8", "d8c7", "e6f7"].iter().enumerate() {
+            let m: GenMove = m.parse().unwrap();
+            let m = if i == 4 { m.promotion(Some(Piece::Queen)) } else { m };
+            let child = b.apply(&m).unwrap();
+            e.make_move(&b, &child, &m);
+            let player = if i % 2 == 0 { Color::Black } else { Color::White };
+            assert_eq!(full_eval(&net, &child, player), e.evaluate(&child, player), "after {}", m);
+            boards.push(child.clone());
+            b = child;
+        }
+        // unwinding reuses the computed accumulators
+        while boards.len() > 1 {
+            e.unmake_move();
+            boards.pop();
+            let b = boards.last().unwrap();
+            assert_eq!(full_eval(&net, b, Color::White), e.evaluate(b, Color::White));
+            assert_eq!(e.len, boards.len());
+        }
+    }
+
+    #[test]
+    fn symmetric() {
+        let net = Arc::new(random_network(16, 4));
+        let mut e = NnueEvaluator::new(net);
+        let b = parse_fen("4k3/2p5/8/3P4/8/8/5N2/4K3 w").unwrap();
+        let flipped = parse_fen("4k3/5n2/8/8/3p4/8/2P5/4K3 b").unwrap();
+        assert_eq!(e.evaluate(&b, Color::White), e.evaluate(&flipped, Color::Black));
+        assert_eq!(e.evaluate(&b, Color::White), e.trace(&b).score);
+    }
+}
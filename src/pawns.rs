@@ -0,0 +1,251 @@
+use board::*;
+use evaluate::Tapered;
+use move_generator::{pawn_attacks, CaseIterator, FILE_A, FILE_H};
+use serde::{Deserialize, Serialize};
+
+/// Pawn structure weights, in centipawns, indexed by rank from the pawn's
+/// side where they depend on it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PawnWeights {
+    pub doubled: Tapered,
+    pub isolated: Tapered,
+    pub backward: Tapered,
+    /// Pawn defended by another pawn.
+    pub chain: Tapered,
+    /// Pawn next to another pawn on the same rank.
+    pub phalanx: Tapered,
+    pub passed: [Tapered; 8],
+    pub passed_supported: Tapered,
+    /// Penalty of a passed pawn with a piece in front of it.
+    pub passed_blocked: [Tapered; 8],
+    /// Pawn on a half-open file with as many supporters as sentries.
+    pub candidate: [Tapered; 8],
+}
+
+const fn t(mg: i32, eg: i32) -> Tapered {
+    Tapered::new(mg, eg)
+}
+
+impl Default for PawnWeights {
+    fn default() -> Self {
+        PawnWeights {
+            doubled: t(-10, -25),
+            isolated: t(-10, -15),
+            backward: t(-8, -10),
+            chain: t(8, 6),
+            phalanx: t(5, 5),
+            passed: [t(0, 0), t(0, 10), t(5, 15), t(10, 25), t(20, 45), t(35, 70), t(60, 110), t(0, 0)],
+            passed_supported: t(5, 15),
+            passed_blocked: [t(0, 0), t(0, -5), t(-2, -7), t(-5, -12), t(-10, -22), t(-17, -35), t(-30, -55), t(0, 0)],
+            candidate: [t(0, 0), t(0, 5), t(2, 7), t(5, 12), t(10, 22), t(17, 35), t(0, 0), t(0, 0)],
+        }
+    }
+}
+
+/// Files next to the file `col`.
+pub fn adjacent_files(col: u8) -> u64 {
+    let file = FILE_A << col;
+    ((file << 1) & !FILE_A) | ((file >> 1) & !FILE_H)
+}
+
+/// Ranks strictly in front of `row`, from `c`'s point of view.
+pub fn ranks_ahead(row: u8, c: Color) -> u64 {
+    match c {
+        Color::White if row < 7 => !0 << (8 * (row + 1)),
+        Color::White => 0,
+        Color::Black => (1 << (8 * row)) - 1,
+    }
+}
+
+/// Rank of `row` counted from the side of `c`, 0 for its first rank.
+pub fn relative_rank(row: u8, c: Color) -> usize {
+    c.map(row, 7 - row) as usize
+}
+
+/// Passed pawns of `c`: no enemy pawn in front of them, on their file or the
+/// adjacent ones. Of doubled pawns, only the front one counts.
+pub fn passed_pawns(b: &Board, c: Color) -> u64 {
+    let ours = b.get_pc_board(Piece::Pawn, c);
+    let theirs = b.get_pc_board(Piece::Pawn, !c);
+    CaseIterator::new(ours)
+        .filter(|sq| {
+            let file = FILE_A << sq.col();
+            let ahead = ranks_ahead(sq.row(), c);
+            (ours & file & ahead) | (theirs & (file | adjacent_files(sq.col())) & ahead) == 0
+        })
+        .fold(0, |acc, sq| acc | sq.board())
+}
+
+/// Score of the pawn structure of `c`, only depending on the pawns.
+fn evaluate_structure(b: &Board, c: Color, w: &PawnWeights) -> Tapered {
+    let ours = b.get_pc_board(Piece::Pawn, c);
+    let theirs = b.get_pc_board(Piece::Pawn, !c);
+    let defended = pawn_attacks(ours, c);
+    let their_attacks = pawn_attacks(theirs, !c);
+    let passed = passed_pawns(b, c);
+    let mut score = Tapered::default();
+    for sq in CaseIterator::new(ours) {
+        let file = FILE_A << sq.col();
+        let adjacent = adjacent_files(sq.col());
+        let ahead = ranks_ahead(sq.row(), c);
+        let rank = relative_rank(sq.row(), c);
+        let supported = defended & sq.board() != 0;
+        if ours & file & ahead != 0 {
+            score += w.doubled;
+        }
+        if ours & adjacent == 0 {
+            score += w.isolated;
+        } else if ours & adjacent & !ahead == 0 {
+            // no pawn beside or behind can support it, and it can't advance safely
+            let stop = c.map(sq.board() << 8, sq.board() >> 8);
+            if their_attacks & stop != 0 {
+                score += w.backward;
+            }
+        }
+        if supported {
+            score += w.chain;
+        }
+        if ours & adjacent & (0xff << (8 * sq.row())) != 0 {
+            score += w.phalanx;
+        }
+        if passed & sq.board() != 0 {
+            score += w.passed[rank];
+            if supported {
+                score += w.passed_supported;
+            }
+        } else if (ours | theirs) & file & ahead == 0 {
+            let supporters = ours & adjacent & !ahead;
+            let sentries = theirs & adjacent & ahead;
+            if supporters.count_ones() >= sentries.count_ones() {
+                score += w.candidate[rank];
+            }
+        }
+    }
+    score
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PawnEntry {
+    key: u64,
+    /// White's structure minus Black's.
+    score: Tapered,
+    passed: [u64; 2],
+}
+
+/// Cache of the pawn structure scores, indexed by `Board::pawn_hash`. Pawn
+/// structures change rarely in the search, so most lookups hit.
+pub struct PawnTable {
+    entries: Vec<Option<PawnEntry>>,
+}
+
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+impl PawnTable {
+    pub fn new() -> Self {
+        PawnTable {
+            entries: vec![None; PAWN_TABLE_SIZE],
+        }
+    }
+
+    fn probe(&mut self, b: &Board, w: &PawnWeights) -> PawnEntry {
+        let key = b.pawn_hash();
+        let slot = &mut self.entries[key as usize % PAWN_TABLE_SIZE];
+        match *slot {
+            Some(entry) if entry.key == key => entry,
+            _ => {
+                let entry = PawnEntry {
+                    key,
+                    score: evaluate_structure(b, Color::White, w) - evaluate_structure(b, Color::Black, w),
+                    passed: [passed_pawns(b, Color::White), passed_pawns(b, Color::Black)],
+                };
+                *slot = Some(entry);
+                entry
+            }
+        }
+    }
+}
+
+impl Default for PawnTable {
+    fn default() -> Self {
+        PawnTable::new()
+    }
+}
+
+/// Penalty of the passed pawns of `c` among `passed` whose stop square is
+/// occupied.
+fn blocked_passers(b: &Board, c: Color, passed: u64, w: &PawnWeights) -> Tapered {
+    let occupied = b.all();
+    let mut score = Tapered::default();
+    for sq in CaseIterator::new(passed) {
+        let stop = c.map(sq.board() << 8, sq.board() >> 8);
+        if occupied & stop != 0 {
+            score += w.passed_blocked[relative_rank(sq.row(), c)];
+        }
+    }
+    score
+}
+
+/// Pawn structure, White's minus Black's. Only the blockade of the passed
+/// pawns depends on the pieces, the rest comes from `table`.
+pub fn evaluate(b: &Board, w: &PawnWeights, table: &mut PawnTable) -> Tapered {
+    let entry = table.probe(b, w);
+    entry.score + blocked_passers(b, Color::White, entry.passed[0], w)
+        - blocked_passers(b, Color::Black, entry.passed[1], w)
+}
+
+/// Pawn structure of `c` alone, bypassing the cache.
+pub fn evaluate_side(b: &Board, c: Color, w: &PawnWeights) -> Tapered {
+    evaluate_structure(b, c, w) + blocked_passers(b, c, passed_pawns(b, c), w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use move_generator::Case;
+
+    fn structure(fen: &str) -> Tapered {
+        let b = parse_fen(fen).unwrap();
+        evaluate_structure(&b, Color::White, &PawnWeights::default())
+    }
+
+    #[test]
+    fn pawn_terms() {
+        let w = PawnWeights::default();
+        // doubled and isolated: both pawns are isolated, the rear one doubled
+        assert_eq!(w.isolated * 2 + w.doubled + w.passed[3], structure("4k3/8/8/8/3P4/3P4/8/4K3 w"));
+        // the c3 pawn defends d4: chain, and d4 is passed
+        assert_eq!(
+            w.chain + w.passed[3] + w.passed_supported + w.passed[2],
+            structure("4k3/8/8/8/3P4/2P5/8/4K3 w")
+        );
+        // phalanx on the 4th rank, both passed
+        assert_eq!((w.phalanx + w.passed[3]) * 2, structure("4k3/8/8/8/3PP3/8/8/4K3 w"));
+        // c2 can't be supported by d3, and c3 is covered by the b4 pawn
+        assert_eq!(
+            w.backward + w.chain + w.passed[2] + w.passed_supported,
+            structure("4k3/8/8/8/1p6/3P4/2P5/4K3 w")
+        );
+        // a half-open file with a supporter for the only sentry
+        assert_eq!(w.candidate[3] + w.phalanx * 2 + w.passed[3], structure("4k3/8/1p6/8/2PP4/8/8/4K3 w"));
+    }
+
+    #[test]
+    fn passed_pawns_and_cache() {
+        let b = parse_fen("4k3/8/2p5/8/3P4/8/7P/4K3 w").unwrap();
+        assert_eq!(Case::new(1, 7).board(), passed_pawns(&b, Color::White));
+        assert_eq!(0, passed_pawns(&b, Color::Black));
+
+        let w = PawnWeights::default();
+        let mut table = PawnTable::new();
+        let score = evaluate(&b, &w, &mut table);
+        assert_eq!(score, evaluate(&b, &w, &mut table));
+        // a piece in front of the h2 passer blocks it
+        let blocked = parse_fen("4k3/8/2p5/8/3P4/7n/7P/4K3 w").unwrap();
+        assert_eq!(score + w.passed_blocked[1], evaluate(&blocked, &w, &mut table));
+        assert_eq!(
+            evaluate(&blocked, &w, &mut table),
+            evaluate_side(&blocked, Color::White, &w) - evaluate_side(&blocked, Color::Black, &w)
+        );
+    }
+}
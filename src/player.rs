@@ -1,12 +1,39 @@
-use move_generator::{GenMove, Case, Flags};
+use move_generator::{generate_legal_moves, GenMove, Case, Flags};
+use crate::ai::AiPlayer;
 use crate::board::*;
+use crate::dtm::DtmTables;
+use std::sync::Arc;
 
 pub trait Player {
     fn get_move(&mut self, c: Color, b: &Board) -> GenMove;
 }
-pub struct IOPlayer {}
+/// Reads the moves from the standard input, where `hint` suggests one.
+pub struct IOPlayer {
+    endgames: Arc<DtmTables>,
+}
+
+impl IOPlayer {
+    pub fn new(endgames: Arc<DtmTables>) -> Self {
+        IOPlayer { endgames }
+    }
+
+    /// Suggested move for `c`: perfect in the endings of the distance to
+    /// mate tables, else the engine's.
+    pub fn hint(&self, c: Color, b: &Board) -> String {
+        if generate_legal_moves(b, c).is_empty() {
+            return "no legal move".to_string();
+        }
+        if let Some((moves, dtm)) = self.endgames.best_moves(b, c) {
+            return format!("{} ({})", moves[0], dtm);
+        }
+        let mut ai = AiPlayer::new([0; 16]);
+        ai.set_endgames(Some(self.endgames.clone()));
+        ai.get_move(c, b).to_string()
+    }
+}
+
 impl Player for IOPlayer {
-    fn get_move(&mut self, _c: Color, _b: &Board) -> GenMove {
+    fn get_move(&mut self, c: Color, b: &Board) -> GenMove {
         use std::io;
         use std::io::prelude::*;
 
@@ -16,6 +43,11 @@ impl Player for IOPlayer {
         
         loop {
             handle.read_line(&mut buffer).unwrap();
+            if buffer.trim() == "hint" {
+                println!("hint: {}", self.hint(c, b));
+                buffer.clear();
+                continue;
+            }
 
             let p = buffer.parse::<GenMove>();
             if let Ok(m) = p {
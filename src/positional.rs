@@ -0,0 +1,190 @@
+use board::*;
+use evaluate::Tapered;
+use move_generator::{pawn_attacks, piece_attacks, CaseIterator, FILE_A};
+use pawns::{adjacent_files, ranks_ahead, relative_rank};
+use see::SEE_VALUES;
+use serde::{Deserialize, Serialize};
+
+/// Weights of the piece placement terms, in centipawns.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PositionalWeights {
+    pub bishop_pair: Tapered,
+    pub rook_open_file: Tapered,
+    pub rook_half_open_file: Tapered,
+    pub queen_open_file: Tapered,
+    pub queen_half_open_file: Tapered,
+    /// Rook on the 7th rank, cutting off the king or attacking pawns.
+    pub rook_on_seventh: Tapered,
+    /// Knight or bishop on the 4th to 6th rank, defended by a pawn and out of
+    /// reach of the enemy pawns.
+    pub knight_outpost: Tapered,
+    pub bishop_outpost: Tapered,
+    /// Knight or bishop right behind a pawn, shielded from frontal attacks.
+    pub minor_behind_pawn: Tapered,
+    /// Enemy piece attacked by a pawn.
+    pub threat_by_pawn: Tapered,
+    /// Enemy piece attacked by a piece of lower value.
+    pub threat_by_lower: Tapered,
+    /// Undefended enemy piece under attack.
+    pub hanging: Tapered,
+}
+
+const fn t(mg: i32, eg: i32) -> Tapered {
+    Tapered::new(mg, eg)
+}
+
+impl Default for PositionalWeights {
+    fn default() -> Self {
+        PositionalWeights {
+            bishop_pair: t(30, 50),
+            rook_open_file: t(40, 20),
+            rook_half_open_file: t(20, 10),
+            queen_open_file: t(10, 5),
+            queen_half_open_file: t(5, 5),
+            rook_on_seventh: t(20, 40),
+            knight_outpost: t(30, 20),
+            bishop_outpost: t(15, 10),
+            minor_behind_pawn: t(10, 0),
+            threat_by_pawn: t(60, 40),
+            threat_by_lower: t(40, 30),
+            hanging: t(30, 20),
+        }
+    }
+}
+
+/// Squares attacked by each piece type of `c`.
+fn attack_maps(b: &Board, c: Color) -> [u64; 6] {
+    use crate::Piece::*;
+    let occupied = b.all();
+    let mut maps = [0; 6];
+    maps[Pawn as usize] = pawn_attacks(b.get_pc_board(Pawn, c), c);
+    for &p in [Knight, Bishop, Rook, Queen, King].iter() {
+        for sq in CaseIterator::new(b.get_pc_board(p, c)) {
+            maps[p as usize] |= piece_attacks(p, sq, occupied);
+        }
+    }
+    maps
+}
+
+fn files_and_ranks(b: &Board, c: Color, w: &PositionalWeights) -> Tapered {
+    use crate::Piece::*;
+    let ours = b.get_pc_board(Pawn, c);
+    let theirs = b.get_pc_board(Pawn, !c);
+    let mut score = Tapered::default();
+    for &(p, open, half_open) in [
+        (Rook, w.rook_open_file, w.rook_half_open_file),
+        (Queen, w.queen_open_file, w.queen_half_open_file),
+    ]
+    .iter()
+    {
+        for sq in CaseIterator::new(b.get_pc_board(p, c)) {
+            let file = FILE_A << sq.col();
+            if (ours | theirs) & file == 0 {
+                score += open;
+            } else if ours & file == 0 {
+                score += half_open;
+            }
+        }
+    }
+    let seventh = 0xff << (8 * c.map(6, 1));
+    let eighth = 0xff << (8 * c.map(7, 0));
+    if b.get_pc_board(King, !c) & eighth != 0 || theirs & seventh != 0 {
+        let rooks = b.get_pc_board(Rook, c) & seventh;
+        score += w.rook_on_seventh * rooks.count_ones() as i32;
+    }
+    score
+}
+
+fn minor_pieces(b: &Board, c: Color, w: &PositionalWeights) -> Tapered {
+    use crate::Piece::*;
+    let ours = b.get_pc_board(Pawn, c);
+    let theirs = b.get_pc_board(Pawn, !c);
+    let pawns = ours | theirs;
+    let defended = pawn_attacks(ours, c);
+    let mut score = Tapered::default();
+    if b.get_pc_board(Bishop, c).count_ones() >= 2 {
+        score += w.bishop_pair;
+    }
+    for &(p, outpost) in [(Knight, w.knight_outpost), (Bishop, w.bishop_outpost)].iter() {
+        for sq in CaseIterator::new(b.get_pc_board(p, c)) {
+            let rank = relative_rank(sq.row(), c);
+            let attackers = theirs & adjacent_files(sq.col()) & ranks_ahead(sq.row(), c);
+            if (3..=5).contains(&rank) && defended & sq.board() != 0 && attackers == 0 {
+                score += outpost;
+            }
+            let front = c.map(sq.board() << 8, sq.board() >> 8);
+            if pawns & front != 0 {
+                score += w.minor_behind_pawn;
+            }
+        }
+    }
+    score
+}
+
+/// Enemy pieces attacked by pawns or by lower valued pieces, and enemy
+/// pieces attacked but not defended. Kings and pawns are not targets.
+fn threats(b: &Board, c: Color, w: &PositionalWeights) -> Tapered {
+    use crate::Piece::*;
+    let ours = attack_maps(b, c);
+    let theirs = attack_maps(b, !c);
+    let attacked = ours.iter().fold(0, |acc, m| acc | m);
+    let defended = theirs.iter().fold(0, |acc, m| acc | m);
+    let mut score = Tapered::default();
+    for &target in [Knight, Bishop, Rook, Queen].iter() {
+        for sq in CaseIterator::new(b.get_pc_board(target, !c)) {
+            let square = sq.board();
+            if ours[Pawn as usize] & square != 0 {
+                score += w.threat_by_pawn;
+            } else if [Knight, Bishop, Rook].iter().any(|&p| {
+                SEE_VALUES[p as usize] < SEE_VALUES[target as usize] && ours[p as usize] & square != 0
+            }) {
+                score += w.threat_by_lower;
+            }
+            if attacked & square != 0 && defended & square == 0 {
+                score += w.hanging;
+            }
+        }
+    }
+    score
+}
+
+/// Piece placement and threats of `c`.
+pub fn evaluate(b: &Board, c: Color, w: &PositionalWeights) -> Tapered {
+    files_and_ranks(b, c, w) + minor_pieces(b, c, w) + threats(b, c, w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positional(fen: &str) -> Tapered {
+        evaluate(&parse_fen(fen).unwrap(), Color::White, &PositionalWeights::default())
+    }
+
+    #[test]
+    fn positional_terms() {
+        let w = PositionalWeights::default();
+        assert_eq!(w.bishop_pair, positional("4k3/8/8/8/8/8/8/2B1KB2 w"));
+        // open file for the rook, half-open for the queen
+        assert_eq!(w.rook_open_file + w.queen_half_open_file, positional("4k3/3p4/8/8/8/8/8/R2QK3 w"));
+        // the rook on the 7th hits the pawns there
+        assert_eq!(w.rook_on_seventh + w.rook_open_file, positional("4k3/R5pp/8/8/8/8/8/4K3 w"));
+        // a knight on d5 defended by e4, no black pawn can chase it
+        assert_eq!(w.knight_outpost, positional("4k3/8/8/3N4/4P3/8/8/4K3 w"));
+        // the c6 pawn controls d5
+        assert_eq!(Tapered::default(), positional("4k3/8/2p5/3N4/4P3/8/8/4K3 w"));
+        assert_eq!(w.minor_behind_pawn, positional("4k3/8/8/8/8/3P4/3B4/4K3 w"));
+    }
+
+    #[test]
+    fn threat_terms() {
+        let w = PositionalWeights::default();
+        // the e4 pawn forks undefended knight and bishop
+        assert_eq!((w.threat_by_pawn + w.hanging) * 2, positional("4k3/8/8/3n1b2/4P3/8/8/4K3 w"));
+        // a knight hits a rook defended by a pawn
+        assert_eq!(w.threat_by_lower, positional("4k3/6p1/5r2/8/4N3/8/8/4K3 w"));
+        // a rook hits a knight that nothing defends
+        assert_eq!(w.hanging + w.rook_open_file, positional("7k/8/8/3n4/8/8/8/3RK3 w"));
+    }
+}
@@ -0,0 +1,291 @@
+use board::*;
+use endgame;
+use evaluate::*;
+use king_safety::{self, KingWeights};
+use mobility::{self, MobilityWeights};
+use move_generator::CaseIterator;
+use pawns::{self, PawnTable, PawnWeights};
+use positional::{self, PositionalWeights};
+use serde::{Deserialize, Serialize};
+
+/// Phase weight of each piece type: 24 with all the minor and major pieces
+/// on the board, 0 with only kings and pawns left.
+const PHASE_WEIGHTS: [i32; 6] = [0, 1, 1, 2, 4, 0];
+pub const MAX_PHASE: i32 = 24;
+
+/// Weights of the tapered evaluation, in centipawns.
+///
+/// Tables are laid out as seen from White, rank 8 on the first row, and
+/// mirrored for Black.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PstWeights {
+    pub material: [Tapered; 6],
+    pub mg: [[[i32; 8]; 8]; 6],
+    pub eg: [[[i32; 8]; 8]; 6],
+}
+
+#[rustfmt::skip]
+const PAWN_MG: [[i32; 8]; 8] = [
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [ 50,  50,  50,  50,  50,  50,  50,  50],
+    [ 10,  10,  20,  30,  30,  20,  10,  10],
+    [  5,   5,  10,  25,  25,  10,   5,   5],
+    [  0,   0,   0,  20,  20,   0,   0,   0],
+    [  5,  -5, -10,   0,   0, -10,  -5,   5],
+    [  5,  10,  10, -20, -20,  10,  10,   5],
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+];
+
+#[rustfmt::skip]
+const PAWN_EG: [[i32; 8]; 8] = [
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [ 80,  80,  80,  80,  80,  80,  80,  80],
+    [ 50,  50,  50,  50,  50,  50,  50,  50],
+    [ 30,  30,  30,  30,  30,  30,  30,  30],
+    [ 15,  15,  15,  15,  15,  15,  15,  15],
+    [  5,   5,   5,   5,   5,   5,   5,   5],
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+];
+
+#[rustfmt::skip]
+const KNIGHT: [[i32; 8]; 8] = [
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+    [-40, -20,   0,   0,   0,   0, -20, -40],
+    [-30,   0,  10,  15,  15,  10,   0, -30],
+    [-30,   5,  15,  20,  20,  15,   5, -30],
+    [-30,   0,  15,  20,  20,  15,   0, -30],
+    [-30,   5,  10,  15,  15,  10,   5, -30],
+    [-40, -20,   0,   5,   5,   0, -20, -40],
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+];
+
+#[rustfmt::skip]
+const BISHOP: [[i32; 8]; 8] = [
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+    [-10,   0,   0,   0,   0,   0,   0, -10],
+    [-10,   0,   5,  10,  10,   5,   0, -10],
+    [-10,   5,   5,  10,  10,   5,   5, -10],
+    [-10,   0,  10,  10,  10,  10,   0, -10],
+    [-10,  10,  10,  10,  10,  10,  10, -10],
+    [-10,   5,   0,   0,   0,   0,   5, -10],
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+];
+
+#[rustfmt::skip]
+const ROOK_MG: [[i32; 8]; 8] = [
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [  5,  10,  10,  10,  10,  10,  10,   5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [  0,   0,   0,   5,   5,   0,   0,   0],
+];
+
+#[rustfmt::skip]
+const ROOK_EG: [[i32; 8]; 8] = [
+    [  5,   5,   5,   5,   5,   5,   5,   5],
+    [ 10,  10,  10,  10,  10,  10,  10,  10],
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+];
+
+#[rustfmt::skip]
+const QUEEN: [[i32; 8]; 8] = [
+    [-20, -10, -10,  -5,  -5, -10, -10, -20],
+    [-10,   0,   0,   0,   0,   0,   0, -10],
+    [-10,   0,   5,   5,   5,   5,   0, -10],
+    [ -5,   0,   5,   5,   5,   5,   0,  -5],
+    [  0,   0,   5,   5,   5,   5,   0,  -5],
+    [-10,   5,   5,   5,   5,   5,   0, -10],
+    [-10,   0,   5,   0,   0,   0,   0, -10],
+    [-20, -10, -10,  -5,  -5, -10, -10, -20],
+];
+
+#[rustfmt::skip]
+const KING_MG: [[i32; 8]; 8] = [
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-20, -30, -30, -40, -40, -30, -30, -20],
+    [-10, -20, -20, -20, -20, -20, -20, -10],
+    [ 20,  20,   0,   0,   0,   0,  20,  20],
+    [ 20,  30,  10,   0,   0,  10,  30,  20],
+];
+
+#[rustfmt::skip]
+const KING_EG: [[i32; 8]; 8] = [
+    [-50, -40, -30, -20, -20, -30, -40, -50],
+    [-30, -20, -10,   0,   0, -10, -20, -30],
+    [-30, -10,  20,  30,  30,  20, -10, -30],
+    [-30, -10,  30,  40,  40,  30, -10, -30],
+    [-30, -10,  30,  40,  40,  30, -10, -30],
+    [-30, -10,  20,  30,  30,  20, -10, -30],
+    [-30, -30,   0,   0,   0,   0, -30, -30],
+    [-50, -30, -30, -30, -30, -30, -30, -50],
+];
+
+impl Default for PstWeights {
+    fn default() -> Self {
+        PstWeights {
+            material: [
+                Tapered::new(82, 94),
+                Tapered::new(337, 281),
+                Tapered::new(365, 297),
+                Tapered::new(477, 512),
+                Tapered::new(1025, 936),
+                Tapered::new(0, 0),
+            ],
+            mg: [PAWN_MG, KNIGHT, BISHOP, ROOK_MG, QUEEN, KING_MG],
+            eg: [PAWN_EG, KNIGHT, BISHOP, ROOK_EG, QUEEN, KING_EG],
+        }
+    }
+}
+
+/// Game phase from the remaining material, from `MAX_PHASE` in the opening
+/// down to 0 in pawn endings.
+pub fn phase(b: &Board) -> i32 {
+    use crate::Piece::*;
+    let phase: i32 = [Knight, Bishop, Rook, Queen]
+        .iter()
+        .map(|&p| {
+            let count = b.get_pc_board(p, Color::White) | b.get_pc_board(p, Color::Black);
+            count.count_ones() as i32 * PHASE_WEIGHTS[p as usize]
+        })
+        .sum();
+    std::cmp::min(phase, MAX_PHASE)
+}
+
+/// Material of `c`.
+pub fn material(b: &Board, c: Color, w: &PstWeights) -> Tapered {
+    use crate::Piece::*;
+    [Pawn, Knight, Bishop, Rook, Queen, King]
+        .iter()
+        .fold(Tapered::default(), |acc, &p| acc + w.material[p as usize] * b.get_pc_board(p, c).count_ones() as i32)
+}
+
+/// Material and piece placement of `c`.
+pub fn evaluate_pst(b: &Board, c: Color, w: &PstWeights) -> Tapered {
+    use crate::Piece::*;
+    let mut score = Tapered::default();
+    for &p in [Pawn, Knight, Bishop, Rook, Queen, King].iter() {
+        let i = p as usize;
+        for sq in CaseIterator::new(b.get_pc_board(p, c)) {
+            // first row of the tables is the last rank of the player
+            let row = c.map(7 - sq.row(), sq.row()) as usize;
+            let col = sq.col() as usize;
+            score += w.material[i] + Tapered::new(w.mg[i][row][col], w.eg[i][row][col]);
+        }
+    }
+    score
+}
+
+/// Material, piece-square tables, pawn structure, king safety, mobility and
+/// piece placement, interpolated between their middlegame and endgame values by the game phase.
+#[derive(Default)]
+pub struct TaperedEvaluator {
+    pub weights: PstWeights,
+    pub pawn_weights: PawnWeights,
+    pub king_weights: KingWeights,
+    pub mobility_weights: MobilityWeights,
+    pub positional_weights: PositionalWeights,
+    pawn_table: PawnTable,
+}
+
+impl TaperedEvaluator {
+    pub fn new(params: &EvalParams) -> Self {
+        TaperedEvaluator {
+            weights: params.pst.clone(),
+            pawn_weights: params.pawns.clone(),
+            king_weights: params.king.clone(),
+            mobility_weights: params.mobility.clone(),
+            positional_weights: params.positional.clone(),
+            pawn_table: PawnTable::new(),
+        }
+    }
+}
+
+impl Evaluator for TaperedEvaluator {
+    fn evaluate(&mut self, b: &Board, player: Color) -> i32 {
+        let pawn = self.weights.material[Piece::Pawn as usize].eg;
+        endgame::evaluate(b, player, pawn, || {
+            let mut score = evaluate_pst(b, player, &self.weights) - evaluate_pst(b, !player, &self.weights);
+            let pawns = pawns::evaluate(b, &self.pawn_weights, &mut self.pawn_table);
+            score += player.map(pawns, -pawns);
+            score += king_safety::evaluate(b, player, &self.king_weights)
+                - king_safety::evaluate(b, !player, &self.king_weights);
+            score += mobility::evaluate(b, player, &self.mobility_weights)
+                - mobility::evaluate(b, !player, &self.mobility_weights);
+            score += positional::evaluate(b, player, &self.positional_weights)
+                - positional::evaluate(b, !player, &self.positional_weights);
+            score.interpolate(phase(b))
+        })
+    }
+
+    fn trace(&mut self, b: &Board) -> Trace {
+        use crate::Color::*;
+        let mut trace = Trace::new(b);
+        let side = |c| (material(b, c, &self.weights), evaluate_pst(b, c, &self.weights));
+        let ((white_material, white_pst), (black_material, black_pst)) = (side(White), side(Black));
+        trace.add("material", white_material, black_material);
+        trace.add("pst", white_pst - white_material, black_pst - black_material);
+        let w = &self.pawn_weights;
+        trace.add("pawns", pawns::evaluate_side(b, White, w), pawns::evaluate_side(b, Black, w));
+        let w = &self.king_weights;
+        trace.add("king safety", king_safety::evaluate(b, White, w), king_safety::evaluate(b, Black, w));
+        let w = &self.mobility_weights;
+        trace.add("mobility", mobility::evaluate(b, White, w), mobility::evaluate(b, Black, w));
+        let w = &self.positional_weights;
+        trace.add("positional", positional::evaluate(b, White, w), positional::evaluate(b, Black, w));
+        let score = trace.total().interpolate(trace.phase);
+        trace.set_score(b, self.weights.material[Piece::Pawn as usize].eg, score);
+        trace
+    }
+
+    fn box_clone(&self) -> Box<dyn Evaluator> {
+        // each thread gets its own pawn table
+        Box::new(TaperedEvaluator {
+            weights: self.weights.clone(),
+            pawn_weights: self.pawn_weights.clone(),
+            king_weights: self.king_weights.clone(),
+            mobility_weights: self.mobility_weights.clone(),
+            positional_weights: self.positional_weights.clone(),
+            pawn_table: PawnTable::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tapered_eval() {
+        let mut e = TaperedEvaluator::default();
+        let start = Board::new_start();
+        assert_eq!(MAX_PHASE, phase(&start));
+        assert_eq!(0, e.evaluate(&start, Color::White));
+
+        // symmetric: the same position with colors swapped scores the same
+        let b = parse_fen("4k3/2p5/8/3P4/8/8/5N2/4K3 w").unwrap();
+        let flipped = parse_fen("4k3/5n2/8/8/3p4/8/2P5/4K3 b").unwrap();
+        assert_eq!(e.evaluate(&b, Color::White), e.evaluate(&flipped, Color::Black));
+        assert_eq!(1, phase(&b));
+
+        // the king belongs in the corner in the middlegame, in the center in pawn endings
+        let corner = parse_fen("r2qk2r/8/8/8/8/8/8/R2Q1RK1 w").unwrap();
+        let center = parse_fen("r2qk2r/8/8/8/4K3/8/8/R2Q3R w").unwrap();
+        assert!(e.evaluate(&corner, Color::White) > e.evaluate(&center, Color::White));
+        let corner = parse_fen("4k3/pp6/8/8/8/8/PP6/6K1 w").unwrap();
+        let center = parse_fen("4k3/pp6/8/8/4K3/8/PP6/8 w").unwrap();
+        assert!(e.evaluate(&corner, Color::White) < e.evaluate(&center, Color::White));
+    }
+}
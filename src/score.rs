@@ -0,0 +1,148 @@
+use std::fmt;
+use std::ops::{Add, Neg, Sub};
+
+/// Search score from the point of view of the side to move.
+///
+/// Scores beyond `MATE_BOUND` encode a forced mate: `MATE - n` is a mate
+/// delivered in `n` plies from the root, `-MATE + n` being mated in `n` plies.
+/// Right below them, `TB_WIN - n` is a tablebase win reached in `n` plies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Score(pub i32);
+
+const MATE: i32 = 31000;
+// leaves room for mates up to 1000 plies away
+const MATE_BOUND: i32 = MATE - 1000;
+const TB_WIN: i32 = MATE_BOUND - 1;
+// evaluations stay below the tablebase wins
+const TB_BOUND: i32 = TB_WIN - 1000;
+
+impl Score {
+    pub const INFINITY: Score = Score(32000);
+    pub const DRAW: Score = Score(0);
+
+    /// Static evaluation, clamped so it can't be mistaken for a mate or a
+    /// tablebase score.
+    pub fn cp(v: i32) -> Score {
+        Score(v.clamp(-TB_BOUND + 1, TB_BOUND - 1))
+    }
+
+    pub fn mate_in(ply: usize) -> Score {
+        Score(MATE - ply as i32)
+    }
+
+    pub fn mated_in(ply: usize) -> Score {
+        Score(-MATE + ply as i32)
+    }
+
+    /// Tablebase win proven `ply` plies from the root.
+    pub fn tb_win_in(ply: usize) -> Score {
+        Score(TB_WIN - ply as i32)
+    }
+
+    pub fn tb_loss_in(ply: usize) -> Score {
+        Score(-TB_WIN + ply as i32)
+    }
+
+    pub fn is_mate(self) -> bool {
+        self.0.abs() >= MATE_BOUND && self.0.abs() <= MATE
+    }
+
+    /// Moves until mate as reported by UCI: positive when the side to move
+    /// mates, negative when it gets mated.
+    pub fn mate_moves(self) -> Option<i32> {
+        if !self.is_mate() {
+            None
+        } else if self.0 > 0 {
+            Some((MATE - self.0 + 1) / 2)
+        } else {
+            Some(-(MATE + self.0) / 2)
+        }
+    }
+
+    /// Converts a mate score from distance-to-root to distance-to-node, as
+    /// stored in the transposition table.
+    pub fn to_tt(self, ply: usize) -> Score {
+        if self.0 >= TB_BOUND {
+            Score(self.0 + ply as i32)
+        } else if self.0 <= -TB_BOUND {
+            Score(self.0 - ply as i32)
+        } else {
+            self
+        }
+    }
+
+    /// Inverse of `to_tt`.
+    pub fn from_tt(stored: Score, ply: usize) -> Score {
+        if stored.0 >= TB_BOUND {
+            Score(stored.0 - ply as i32)
+        } else if stored.0 <= -TB_BOUND {
+            Score(stored.0 + ply as i32)
+        } else {
+            stored
+        }
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+
+    fn neg(self) -> Score {
+        Score(-self.0)
+    }
+}
+
+impl Add<i32> for Score {
+    type Output = Score;
+
+    fn add(self, rhs: i32) -> Score {
+        Score(self.0 + rhs)
+    }
+}
+
+impl Sub<i32> for Score {
+    type Output = Score;
+
+    fn sub(self, rhs: i32) -> Score {
+        Score(self.0 - rhs)
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mate_moves() {
+            Some(n) => write!(f, "mate {}", n),
+            None => write!(f, "cp {}", self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mate_moves() {
+        assert_eq!(Some(1), Score::mate_in(1).mate_moves());
+        assert_eq!(Some(2), Score::mate_in(3).mate_moves());
+        assert_eq!(Some(-1), Score::mated_in(2).mate_moves());
+        assert_eq!(Some(0), Score::mated_in(0).mate_moves());
+        assert_eq!(None, Score::cp(150).mate_moves());
+        assert_eq!("mate -1", Score::mated_in(2).to_string());
+        assert_eq!("cp -35", Score::cp(-35).to_string());
+    }
+
+    #[test]
+    fn tt_conversion() {
+        // mate in 5 plies from the root, found at ply 2: mate in 3 from the node
+        let s = Score::mate_in(5);
+        assert_eq!(Score::mate_in(3), s.to_tt(2));
+        assert_eq!(s, Score::from_tt(s.to_tt(2), 2));
+        // the same node reached at ply 4 is a mate in 7 from the root
+        assert_eq!(Score::mate_in(7), Score::from_tt(s.to_tt(2), 4));
+        assert_eq!(Score::mated_in(7), Score::from_tt(Score::mated_in(5).to_tt(2), 4));
+        assert_eq!(Score::cp(12), Score::from_tt(Score::cp(12).to_tt(3), 7));
+        assert_eq!(Score::tb_win_in(6), Score::from_tt(Score::tb_win_in(4).to_tt(2), 4));
+        assert!(Score::tb_win_in(3) < Score::mate_in(100) && !Score::tb_win_in(3).is_mate());
+        assert!(Score::tb_loss_in(3) < Score::cp(100_000));
+    }
+}
@@ -0,0 +1,107 @@
+use crate::board::*;
+use crate::move_generator::*;
+
+/// Piece values used by the exchange evaluation, in centipawns.
+pub const SEE_VALUES: [i32; 6] = [100, 320, 330, 500, 900, 20000];
+
+fn value(p: Piece) -> i32 {
+    SEE_VALUES[p as usize]
+}
+
+/// Least valuable piece of `c` among `attackers`.
+fn least_valuable(b: &Board, c: Color, attackers: u64) -> Option<(Piece, u64)> {
+    use crate::Piece::*;
+    [Pawn, Knight, Bishop, Rook, Queen, King].iter().find_map(|&p| {
+        let pieces = b.get_pc_board(p, c) & attackers;
+        if pieces != 0 {
+            Some((p, pieces & pieces.wrapping_neg()))
+        } else {
+            None
+        }
+    })
+}
+
+/// Static exchange evaluation: material won by the side playing `m` once
+/// both sides have recaptured on the target square for as long as it pays,
+/// always with their least valuable piece. Pieces lined up behind the
+/// attackers join in as the ones in front leave.
+pub fn see(b: &Board, m: &GenMove) -> i32 {
+    let (piece, us) = match b.at_pos(&m.from) {
+        Some(x) => x,
+        None => return 0,
+    };
+    let target = m.to;
+    let last_rank = target.row() == 0 || target.row() == 7;
+    let mut gain = [0; 32];
+    gain[0] = b.at_pos(&target).map_or(0, |(p, _)| value(p));
+    let mut on_target = match m.promotion {
+        Some(p) => {
+            gain[0] += value(p) - value(Piece::Pawn);
+            value(p)
+        }
+        None => value(piece),
+    };
+    let mut occupied = b.all() ^ m.from.board();
+    let mut side = !us;
+    let mut d = 0;
+    while d + 1 < gain.len() {
+        let attackers = attackers_to(b, target, side, occupied);
+        let (p, from) = match least_valuable(b, side, attackers) {
+            Some(x) => x,
+            None => break,
+        };
+        // the king can't capture a defended piece
+        if p == Piece::King && attackers_to(b, target, !side, occupied ^ from) != 0 {
+            break;
+        }
+        d += 1;
+        gain[d] = on_target - gain[d - 1];
+        on_target = value(p);
+        if p == Piece::Pawn && last_rank {
+            gain[d] += value(Piece::Queen) - value(Piece::Pawn);
+            on_target = value(Piece::Queen);
+        }
+        occupied ^= from;
+        side = !side;
+    }
+    // each side may stop capturing when going on loses material
+    while d > 0 {
+        gain[d - 1] = -std::cmp::max(-gain[d - 1], gain[d]);
+        d -= 1;
+    }
+    gain[0]
+}
+
+/// Whether `m` wins at least `threshold` centipawns in the exchange.
+pub fn see_ge(b: &Board, m: &GenMove, threshold: i32) -> bool {
+    see(b, m) >= threshold
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn see_of(fen: &str, m: &str) -> i32 {
+        let b = parse_fen(fen).unwrap();
+        see(&b, &m.parse().unwrap())
+    }
+
+    #[test]
+    fn exchanges() {
+        // undefended pawn
+        assert_eq!(100, see_of("4k3/8/8/3p4/8/8/8/3RK3 w", "d1d5"));
+        // pawn defended by a pawn: the rook is lost
+        assert_eq!(-400, see_of("4k3/8/4p3/3p4/8/8/8/3RK3 w", "d1d5"));
+        // the queen behind the rook x-rays through it
+        assert_eq!(100, see_of("3rk3/8/8/3p4/8/8/3R4/3QK3 w", "d2d5"));
+        assert_eq!(-400, see_of("3rk3/3r4/8/3p4/8/8/3R4/3QK3 w", "d2d5"));
+        // a knight takes a defended knight, trading evenly
+        assert_eq!(0, see_of("4k3/8/2p5/3n4/8/4N3/8/4K3 w", "e3d5"));
+        // quiet move to an attacked square
+        assert_eq!(-320, see_of("4k3/8/2p5/8/8/4N3/8/4K3 w", "e3d5"));
+        // the king can't recapture a defended piece
+        assert_eq!(500, see_of("8/8/8/8/8/2k5/3r4/3QK3 w", "d1d2"));
+        assert_eq!(-400, see_of("8/8/8/8/8/2k5/3r4/3Q3K w", "d1d2"));
+        assert!(see_ge(&parse_fen("4k3/8/8/3p4/8/8/8/3RK3 w").unwrap(), &"d1d5".parse().unwrap(), 100));
+    }
+}
@@ -0,0 +1,1830 @@
+use board::*;
+use endgame::signature;
+use move_generator::{generate_legal_moves, in_check, king_attacks, Case, CaseIterator, Flags, GenMove};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+// flags of the file
+const SPLIT: u8 = 1;
+const HAS_PAWNS: u8 = 2;
+
+// flags of each table of a file
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+/// Most pieces of a Syzygy table, kings included.
+pub const MAX_PIECES: usize = 7;
+
+// leaves of the Huffman tree have no right child
+const LEAF: u16 = 0xfff;
+// size of the index of 3 unique pieces in the a1-d1-d4 triangle
+const UNIQUE_SIZE: u64 = 31332;
+// size of the index of the two kings
+const KK_SIZE: u64 = 462;
+
+/// Game theoretical value of a position for the side to move, the cursed
+/// wins and blessed losses being draws under the 50-move rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(v: i32) -> Wdl {
+        match v {
+            i32::MIN..=-2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+}
+
+impl std::ops::Neg for Wdl {
+    type Output = Wdl;
+
+    fn neg(self) -> Wdl {
+        Wdl::from_value(-(self as i32))
+    }
+}
+
+/// Distance to zeroing of the move reaching a position of value `wdl` by a
+/// capture or a pawn move.
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+        Wdl::Draw => 0,
+    }
+}
+
+fn off_diagonal(sq: usize) -> i32 {
+    (sq / 8) as i32 - (sq % 8) as i32
+}
+
+/// Encoding tables of the piece placements.
+struct Maps {
+    // squares below the a1-h8 diagonal to 0..28
+    b1h1h7: [u64; 64],
+    // squares of the a1-d1-d4 triangle to 0..10, the diagonal last
+    a1d1d4: [u64; 64],
+    // legal placements of two kings, the first one in the a1-d1-d4 triangle, to 0..462
+    kk: [[u64; 64]; 10],
+    binomial: [[u64; 64]; MAX_PIECES],
+    // pawn squares to 0..48, the higher the closer to the edge and the first rank
+    pawns: [u64; 64],
+    lead_pawn_idx: [[u64; 64]; MAX_PIECES],
+    lead_pawns_size: [[u64; 4]; MAX_PIECES],
+}
+
+impl Maps {
+    fn new() -> Self {
+        let mut m = Maps {
+            b1h1h7: [0; 64],
+            a1d1d4: [0; 64],
+            kk: [[0; 64]; 10],
+            binomial: [[0; 64]; MAX_PIECES],
+            pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; MAX_PIECES],
+            lead_pawns_size: [[0; 4]; MAX_PIECES],
+        };
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_diagonal(sq) < 0 {
+                m.b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        let mut diagonal = Vec::new();
+        code = 0;
+        for sq in 0..28 {
+            if off_diagonal(sq) < 0 && sq % 8 <= 3 {
+                m.a1d1d4[sq] = code;
+                code += 1;
+            } else if off_diagonal(sq) == 0 && sq % 8 <= 3 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            m.a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        let mut both_on_diagonal = Vec::new();
+        code = 0;
+        for idx in 0..10 {
+            // b1 is mapped to 0, as are the squares out of the triangle
+            let first: Vec<usize> = (0..28)
+                .filter(|&s| m.a1d1d4[s] == idx as u64 && (idx != 0 || s == 1))
+                .collect();
+            for s1 in first {
+                for s2 in 0..64 {
+                    let first = off_diagonal(s1);
+                    let adjacent = (king_attacks(Case(s1 as u8)) | 1 << s1) & 1 << s2 != 0;
+                    if adjacent || (first == 0 && off_diagonal(s2) > 0) {
+                        continue;
+                    } else if first == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        m.kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            m.kk[idx][s2] = code;
+            code += 1;
+        }
+
+        m.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..std::cmp::min(MAX_PIECES, n + 1) {
+                m.binomial[k][n] = if k > 0 { m.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { m.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        let mut available = 48;
+        for count in 1..MAX_PIECES - 1 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if count == 1 {
+                        m.pawns[sq] = available - 1;
+                        m.pawns[sq ^ 7] = available - 2;
+                        available -= 2;
+                    }
+                    m.lead_pawn_idx[count][sq] = idx;
+                    idx += m.binomial[count - 1][m.pawns[sq] as usize];
+                }
+                m.lead_pawns_size[count][file] = idx;
+            }
+        }
+        m
+    }
+}
+
+lazy_static! {
+    static ref MAPS: Maps = Maps::new();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Wdl,
+    Dtz,
+}
+
+impl Kind {
+    fn magic(self) -> [u8; 4] {
+        match self {
+            Kind::Wdl => WDL_MAGIC,
+            Kind::Dtz => DTZ_MAGIC,
+        }
+    }
+}
+
+/// Compressed values of one table: a file holds one per pawn file of the
+/// leading pawn, and for WDL one per side to move.
+///
+/// The values are Huffman coded symbols, each standing for a sequence of
+/// values by recursive pairing, stored in blocks of fixed size. A sparse
+/// index gives the block of every `span`-th value.
+#[derive(Debug, Clone, Default)]
+struct PairsData {
+    flags: u8,
+    block_size: usize,
+    span: u64,
+    num_blocks: usize,
+    // the value of the whole table for SINGLE_VALUE
+    min_sym_len: usize,
+    lowest_sym: Vec<u16>,
+    base64: Vec<u64>,
+    // children of the pairs, the value for the leaves
+    btree: Vec<(u16, u16)>,
+    // number of values of each symbol, minus one
+    symlen: Vec<u32>,
+    // offsets in the file
+    sparse_index: usize,
+    sparse_index_size: usize,
+    block_length: usize,
+    block_length_size: usize,
+    data: usize,
+    // pieces in the order of the encoding, as color << 3 | piece + 1
+    pieces: Vec<u8>,
+    group_idx: [u64; MAX_PIECES + 1],
+    group_len: [usize; MAX_PIECES + 1],
+    map_idx: [usize; 4],
+}
+
+fn read_le(bytes: &[u8], pos: usize, n: usize) -> Option<u64> {
+    let b = bytes.get(pos..pos + n)?;
+    Some(b.iter().rev().fold(0, |acc, &x| acc << 8 | x as u64))
+}
+
+// the compressed data is read past the end of the blocks
+fn read_be(bytes: &[u8], pos: usize, n: usize) -> u64 {
+    (pos..pos + n).fold(0, |acc, i| acc << 8 | *bytes.get(i).unwrap_or(&0) as u64)
+}
+
+impl PairsData {
+    fn set_symlen(&mut self, s: usize, visited: &mut [bool]) -> Option<()> {
+        visited[s] = true;
+        let (left, right) = *self.btree.get(s)?;
+        if right == LEAF {
+            return Some(());
+        }
+        let (left, right) = (left as usize, right as usize);
+        for &child in [left, right].iter() {
+            if !*visited.get(child)? {
+                self.set_symlen(child, visited)?;
+            }
+        }
+        self.symlen[s] = self.symlen[left] + self.symlen[right] + 1;
+        Some(())
+    }
+
+    /// Reads the Huffman code and the symbols from `pos`, returning the
+    /// position past them.
+    fn set_sizes(&mut self, bytes: &[u8], mut pos: usize) -> Option<usize> {
+        self.flags = *bytes.get(pos)?;
+        if self.flags & SINGLE_VALUE != 0 {
+            self.min_sym_len = *bytes.get(pos + 1)? as usize;
+            return Some(pos + 2);
+        }
+        let groups = self.group_len.iter().position(|&l| l == 0)?;
+        let size = self.group_idx[groups];
+        self.block_size = 1 << *bytes.get(pos + 1)?;
+        self.span = 1 << *bytes.get(pos + 2)?;
+        self.sparse_index_size = size.div_ceil(self.span) as usize;
+        let padding = *bytes.get(pos + 3)? as usize;
+        self.num_blocks = read_le(bytes, pos + 4, 4)? as usize;
+        self.block_length_size = self.num_blocks + padding;
+        let max_sym_len = *bytes.get(pos + 8)? as usize;
+        self.min_sym_len = *bytes.get(pos + 9)? as usize;
+        pos += 10;
+        if self.min_sym_len == 0 || max_sym_len < self.min_sym_len || max_sym_len > 32 {
+            return None;
+        }
+        let lengths = max_sym_len - self.min_sym_len + 1;
+        self.lowest_sym = (0..lengths)
+            .map(|i| read_le(bytes, pos + 2 * i, 2).map(|s| s as u16))
+            .collect::<Option<_>>()?;
+        pos += 2 * lengths;
+
+        // canonical code: the longer symbols have the lower codes, base64[i]
+        // being the lowest code of length min_sym_len + i, padded to 64 bits
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            self.base64[i] = (self.base64[i + 1] + self.lowest_sym[i] as u64)
+                .wrapping_sub(self.lowest_sym[i + 1] as u64)
+                / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base <<= 64 - i - self.min_sym_len;
+        }
+
+        let symbols = read_le(bytes, pos, 2)? as usize;
+        pos += 2;
+        let tree = bytes.get(pos..pos + 3 * symbols)?;
+        self.btree = tree
+            .chunks(3)
+            .map(|lr| {
+                let left = (lr[1] as u16 & 0xf) << 8 | lr[0] as u16;
+                let right = (lr[2] as u16) << 4 | (lr[1] as u16) >> 4;
+                (left, right)
+            })
+            .collect();
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for s in 0..symbols {
+            if !visited[s] {
+                self.set_symlen(s, &mut visited)?;
+            }
+        }
+        Some(pos + 3 * symbols + (symbols & 1))
+    }
+
+    /// Value at `idx`, found from the sparse index entry of its span.
+    fn decompress(&self, bytes: &[u8], idx: u64) -> u16 {
+        if self.flags & SINGLE_VALUE != 0 {
+            return self.min_sym_len as u16;
+        }
+        let read = |pos, n| read_le(bytes, pos, n).unwrap_or(0);
+        let entry = self.sparse_index + 6 * (idx / self.span) as usize;
+        let mut block = read(entry, 4) as usize;
+        let mut offset = read(entry + 4, 2) as i64;
+        // the entry is for the middle of the span
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+        let block_length = |block: usize| read(self.block_length + 2 * block, 2) as i64;
+        while offset < 0 {
+            block -= 1;
+            offset += block_length(block) + 1;
+        }
+        while offset > block_length(block) {
+            offset -= block_length(block) + 1;
+            block += 1;
+        }
+
+        let mut ptr = self.data + block * self.block_size;
+        let mut buf = read_be(bytes, ptr, 8);
+        ptr += 8;
+        let mut buf_size = 64;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf < self.base64[len] {
+                len += 1;
+            }
+            sym = ((buf - self.base64[len]) >> (64 - len - self.min_sym_len)) as usize;
+            sym += self.lowest_sym[len] as usize;
+            let count = self.symlen[sym] as i64 + 1;
+            if offset < count {
+                break;
+            }
+            offset -= count;
+            len += self.min_sym_len;
+            buf <<= len;
+            buf_size -= len;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf |= read_be(bytes, ptr, 4) << (64 - buf_size);
+                ptr += 4;
+            }
+        }
+
+        // expand the pairs down to the value
+        while self.symlen[sym] != 0 {
+            let (left, right) = self.btree[sym];
+            let left_count = self.symlen[left as usize] as i64 + 1;
+            if offset < left_count {
+                sym = left as usize;
+            } else {
+                offset -= left_count;
+                sym = right as usize;
+            }
+        }
+        self.btree[sym].0
+    }
+}
+
+/// Loaded file of a table.
+struct TableData {
+    bytes: Vec<u8>,
+    // [pawn file][side to move]
+    items: Vec<Vec<PairsData>>,
+    // offset of the DTZ value maps
+    map: usize,
+}
+
+impl TableData {
+    fn get(&self, stm: usize, file: usize) -> &PairsData {
+        let sides = &self.items[std::cmp::min(file, self.items.len() - 1)];
+        &sides[stm % sides.len()]
+    }
+}
+
+/// Piece of `c` as stored in the tables.
+fn piece_code(p: Piece, c: Color) -> u8 {
+    c.map(0, 8) | (p as u8 + 1)
+}
+
+fn piece_color(code: u8) -> Color {
+    if code & 8 == 0 {
+        Color::White
+    } else {
+        Color::Black
+    }
+}
+
+/// Material of a tablebase file such as `KRPvKR`, and its files.
+struct Table {
+    // signatures of the white and black pieces of the stronger side as white
+    key: (String, String),
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    // pawns of the leading color, and of the other color
+    pawn_count: [usize; 2],
+    wdl_path: PathBuf,
+    dtz_path: Option<PathBuf>,
+    wdl: OnceLock<Option<TableData>>,
+    dtz: OnceLock<Option<TableData>>,
+}
+
+impl Table {
+    fn new(name: &str, wdl_path: PathBuf, dtz_path: Option<PathBuf>) -> Option<Table> {
+        let mut sides = name.split('v');
+        let (white, black) = (sides.next()?, sides.next()?);
+        let valid = |s: &str| s.starts_with('K') && s[1..].chars().all(|c| "QRBNP".contains(c));
+        if sides.next().is_some() || !valid(white) || !valid(black) {
+            return None;
+        }
+        let count = |s: &str, p: char| s.chars().filter(|&c| c == p).count();
+        let pawns = (count(white, 'P'), count(black, 'P'));
+        // the leading color has the fewer pawns, which compresses better
+        let white_leads = pawns.1 == 0 || (pawns.0 > 0 && pawns.1 >= pawns.0);
+        Some(Table {
+            key: (white.to_string(), black.to_string()),
+            piece_count: white.len() + black.len(),
+            has_pawns: pawns.0 + pawns.1 > 0,
+            has_unique_pieces: [white, black]
+                .iter()
+                .any(|s| "QRBNP".chars().any(|p| count(s, p) == 1)),
+            pawn_count: if white_leads {
+                [pawns.0, pawns.1]
+            } else {
+                [pawns.1, pawns.0]
+            },
+            wdl_path,
+            dtz_path,
+            wdl: OnceLock::new(),
+            dtz: OnceLock::new(),
+        })
+    }
+
+    fn symmetric(&self) -> bool {
+        self.key.0 == self.key.1
+    }
+
+    fn data(&self, kind: Kind) -> Option<&TableData> {
+        let (cell, path) = match kind {
+            Kind::Wdl => (&self.wdl, Some(&self.wdl_path)),
+            Kind::Dtz => (&self.dtz, self.dtz_path.as_ref()),
+        };
+        cell.get_or_init(|| {
+            let path = path?;
+            let data = std::fs::read(path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| self.parse(bytes, kind).ok_or_else(|| "invalid table".to_string()));
+            match data {
+                Ok(data) => Some(data),
+                Err(e) => {
+                    error!("{}: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .as_ref()
+    }
+
+    /// Number of values of each group, and the factor of its index: the
+    /// pieces in `d.pieces` are grouped, and the groups encoded in `order`.
+    fn set_groups(&self, d: &mut PairsData, order: [usize; 2], file: usize) {
+        let mut n = 0;
+        let mut first_len: i32 = if self.has_pawns {
+            0
+        } else if self.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        d.group_len[0] = 1;
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        let pp = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = if pp { 2 } else { 1 };
+        let mut free_squares = 64 - d.group_len[0] - if pp { d.group_len[1] } else { 0 };
+        let mut idx = 1;
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                d.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    MAPS.lead_pawns_size[d.group_len[0]][file]
+                } else if self.has_unique_pieces {
+                    UNIQUE_SIZE
+                } else {
+                    KK_SIZE
+                };
+            } else if k == order[1] {
+                d.group_idx[1] = idx;
+                idx *= MAPS.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= MAPS.binomial[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+    }
+
+    /// Reads the headers of the pieces, then the tables.
+    fn parse(&self, bytes: Vec<u8>, kind: Kind) -> Option<TableData> {
+        if bytes.len() % 64 != 16 || bytes[..4] != kind.magic() {
+            return None;
+        }
+        let flags = bytes[4];
+        if (flags & HAS_PAWNS != 0) != self.has_pawns || (flags & SPLIT != 0) == self.symmetric() {
+            return None;
+        }
+        let sides = if kind == Kind::Wdl && !self.symmetric() { 2 } else { 1 };
+        let files = if self.has_pawns { 4 } else { 1 };
+        let pp = self.has_pawns && self.pawn_count[1] > 0;
+        let mut items = vec![vec![PairsData::default(); sides]; files];
+        let mut pos = 5;
+        for (file, tables) in items.iter_mut().enumerate() {
+            let first = *bytes.get(pos)? as usize;
+            let second = if pp { *bytes.get(pos + 1)? as usize } else { 0xff };
+            let order = [[first & 0xf, second & 0xf], [first >> 4, second >> 4]];
+            pos += 1 + pp as usize;
+            for _ in 0..self.piece_count {
+                let code = *bytes.get(pos)?;
+                for (i, d) in tables.iter_mut().enumerate() {
+                    d.pieces.push(if i == 0 { code & 0xf } else { code >> 4 });
+                }
+                pos += 1;
+            }
+            for (d, &order) in tables.iter_mut().zip(order.iter()) {
+                self.set_groups(d, order, file);
+            }
+        }
+        pos += pos & 1;
+
+        for d in items.iter_mut().flatten() {
+            pos = d.set_sizes(&bytes, pos)?;
+        }
+
+        let map = pos;
+        if kind == Kind::Dtz {
+            for d in items.iter_mut().flatten() {
+                if d.flags & MAPPED == 0 {
+                    continue;
+                }
+                for i in 0..4 {
+                    if d.flags & WIDE != 0 {
+                        pos += pos & 1;
+                        d.map_idx[i] = (pos - map) / 2 + 1;
+                        pos += 2 * read_le(&bytes, pos, 2)? as usize + 2;
+                    } else {
+                        d.map_idx[i] = pos - map + 1;
+                        pos += *bytes.get(pos)? as usize + 1;
+                    }
+                }
+            }
+            pos += pos & 1;
+        }
+
+        for d in items.iter_mut().flatten() {
+            d.sparse_index = pos;
+            pos += 6 * d.sparse_index_size;
+        }
+        for d in items.iter_mut().flatten() {
+            d.block_length = pos;
+            pos += 2 * d.block_length_size;
+        }
+        for d in items.iter_mut().flatten() {
+            pos = (pos + 0x3f) & !0x3f;
+            d.data = pos;
+            pos += d.num_blocks * d.block_size;
+        }
+        if pos > bytes.len() {
+            return None;
+        }
+        Some(TableData { bytes, items, map })
+    }
+}
+
+/// Index of `b` in the tables `items` of `table`, `c` being to move: the
+/// side to move and the pawn file of the table, then the index in it.
+fn encode(table: &Table, items: &[Vec<PairsData>], b: &Board, c: Color) -> (usize, usize, u64) {
+    use crate::Piece::*;
+    let maps = &*MAPS;
+    // the tables are for the stronger side as White, and only for White to
+    // move when both sides have the same pieces
+    let key = (signature(b, Color::White), signature(b, Color::Black));
+    let flip = key != table.key || (table.symmetric() && c == Color::Black);
+    let (flip_color, flip_squares) = if flip { (8, 56) } else { (0, 0) };
+    let stm = (flip as usize) ^ (c == Color::Black) as usize;
+
+    let mut squares = [0usize; MAX_PIECES];
+    let mut pieces = [0u8; MAX_PIECES];
+    let mut size = 0;
+    let mut lead_pawns = 0;
+    let mut file = 0;
+    if table.has_pawns {
+        // the pawns come first, in the reference color
+        let pawn = items[0][0].pieces[0] ^ flip_color;
+        lead_pawns = b.get_pc_board(Pawn, piece_color(pawn));
+        for sq in CaseIterator::new(lead_pawns) {
+            squares[size] = sq.0 as usize ^ flip_squares;
+            pieces[size] = items[0][0].pieces[0];
+            size += 1;
+        }
+        let lead = (0..size).max_by_key(|&i| maps.pawns[squares[i]]).unwrap();
+        squares.swap(0, lead);
+        file = std::cmp::min(squares[0] % 8, 7 - squares[0] % 8);
+    }
+    let lead_count = size;
+    for sq in CaseIterator::new(b.all() ^ lead_pawns) {
+        let (p, pc) = b.at_pos(&sq).unwrap();
+        squares[size] = sq.0 as usize ^ flip_squares;
+        pieces[size] = piece_code(p, pc) ^ flip_color;
+        size += 1;
+    }
+
+    let d = &items[file][stm % items[file].len()];
+    // same order as the pieces of the table
+    for i in lead_count..size - 1 {
+        for j in i + 1..size {
+            if d.pieces[i] == pieces[j] {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+                break;
+            }
+        }
+    }
+
+    // the leading piece in the a1-d1-d4 triangle
+    if squares[0] % 8 > 3 {
+        for sq in squares[..size].iter_mut() {
+            *sq ^= 7;
+        }
+    }
+    let mut idx;
+    if table.has_pawns {
+        idx = maps.lead_pawn_idx[lead_count][squares[0]];
+        squares[1..lead_count].sort_by_key(|&sq| maps.pawns[sq]);
+        for (i, &sq) in squares[1..lead_count].iter().enumerate() {
+            idx += maps.binomial[i + 1][maps.pawns[sq] as usize];
+        }
+    } else {
+        if squares[0] / 8 > 3 {
+            for sq in squares[..size].iter_mut() {
+                *sq ^= 56;
+            }
+        }
+        // the first piece of the leading group off the diagonal below it
+        for i in 0..d.group_len[0] {
+            let off = off_diagonal(squares[i]);
+            if off == 0 {
+                continue;
+            }
+            if off > 0 {
+                for sq in squares[i..size].iter_mut() {
+                    *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                }
+            }
+            break;
+        }
+        let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+        let rank = |sq: usize| (sq / 8) as u64;
+        if table.has_unique_pieces {
+            let adjust1 = (s1 > s0) as u64;
+            let adjust2 = (s2 > s0) as u64 + (s2 > s1) as u64;
+            idx = if off_diagonal(s0) != 0 {
+                (maps.a1d1d4[s0] * 63 + (s1 as u64 - adjust1)) * 62 + s2 as u64 - adjust2
+            } else if off_diagonal(s1) != 0 {
+                (6 * 63 + rank(s0) * 28 + maps.b1h1h7[s1]) * 62 + s2 as u64 - adjust2
+            } else if off_diagonal(s2) != 0 {
+                6 * 63 * 62 + 4 * 28 * 62 + rank(s0) * 7 * 28 + (rank(s1) - adjust1) * 28 + maps.b1h1h7[s2]
+            } else {
+                6 * 63 * 62
+                    + 4 * 28 * 62
+                    + 4 * 7 * 28
+                    + rank(s0) * 7 * 6
+                    + (rank(s1) - adjust1) * 6
+                    + (rank(s2) - adjust2)
+            };
+        } else {
+            idx = maps.kk[maps.a1d1d4[s0] as usize][s1];
+        }
+    }
+
+    // the other groups, each as a combination of the squares left
+    idx *= d.group_idx[0];
+    let mut group = d.group_len[0];
+    let mut remaining_pawns = table.has_pawns && table.pawn_count[1] > 0;
+    let mut next = 1;
+    while d.group_len[next] != 0 {
+        let len = d.group_len[next];
+        squares[group..group + len].sort_unstable();
+        let mut n = 0;
+        for i in 0..len {
+            let sq = squares[group + i];
+            let adjust = squares[..group].iter().filter(|&&s| sq > s).count();
+            n += maps.binomial[i + 1][sq - adjust - 8 * remaining_pawns as usize];
+        }
+        remaining_pawns = false;
+        idx += n * d.group_idx[next];
+        group += len;
+        next += 1;
+    }
+    (stm, file, idx)
+}
+
+enum Lookup {
+    Value(i32),
+    // the DTZ table is for the other side to move
+    OtherSide,
+    Missing,
+}
+
+/// Syzygy tablebases found in a set of directories, the files being loaded
+/// on their first probe.
+///
+/// WDL probes give the result of a position, DTZ probes the distance in
+/// plies to the next capture or pawn move (or mate) of the optimal line.
+/// Neither castling nor en passant captures are taken into account.
+pub struct Tablebases {
+    tables: HashMap<(String, String), Arc<Table>>,
+    max_pieces: usize,
+}
+
+impl Tablebases {
+    /// Finds the tables in `paths`, a list of directories separated as in
+    /// the `PATH` variable.
+    pub fn open(paths: &str) -> Tablebases {
+        let mut tb = Tablebases {
+            tables: HashMap::new(),
+            max_pieces: 0,
+        };
+        for dir in std::env::split_paths(paths) {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    error!("{}: {}", dir.display(), e);
+                    continue;
+                }
+            };
+            let mut files: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+            files.sort();
+            for path in files {
+                if path.extension().is_some_and(|e| e == "rtbw") {
+                    tb.add(&path);
+                }
+            }
+        }
+        tb
+    }
+
+    fn add(&mut self, wdl: &Path) {
+        let name = match wdl.file_stem().and_then(|s| s.to_str()) {
+            Some(name) => name,
+            None => return,
+        };
+        let dtz = wdl.with_extension("rtbz");
+        let dtz = if dtz.exists() { Some(dtz) } else { None };
+        let table = match Table::new(name, wdl.to_path_buf(), dtz) {
+            Some(table) if table.piece_count <= MAX_PIECES => Arc::new(table),
+            _ => return,
+        };
+        if self.tables.contains_key(&table.key) {
+            return;
+        }
+        self.max_pieces = std::cmp::max(self.max_pieces, table.piece_count);
+        let (white, black) = table.key.clone();
+        self.tables.insert((black.clone(), white.clone()), table.clone());
+        self.tables.insert((white, black), table);
+    }
+
+    /// Number of tables, each counted once for both colors.
+    pub fn count(&self) -> usize {
+        self.tables.values().filter(|t| !t.symmetric()).count() / 2
+            + self.tables.values().filter(|t| t.symmetric()).count()
+    }
+
+    /// Most pieces of a table found.
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    fn probe_table(&self, b: &Board, c: Color, kind: Kind, wdl: Wdl) -> Lookup {
+        let key = (signature(b, Color::White), signature(b, Color::Black));
+        let data = match self.tables.get(&key) {
+            Some(table) => table.data(kind).map(|data| (table, data)),
+            None => None,
+        };
+        let (table, data) = match data {
+            Some(found) => found,
+            None => return Lookup::Missing,
+        };
+        let (stm, file, idx) = encode(table, &data.items, b, c);
+        let d = data.get(stm, file);
+        if kind == Kind::Dtz
+            && (d.flags & STM) as usize != stm
+            && (!table.symmetric() || table.has_pawns)
+        {
+            return Lookup::OtherSide;
+        }
+        let value = d.decompress(&data.bytes, idx) as i32;
+        if kind == Kind::Wdl {
+            return Lookup::Value(value - 2);
+        }
+
+        // maps of the values by result, in the order win, loss, cursed win, blessed loss
+        let map = match wdl {
+            Wdl::Win | Wdl::Draw => 0,
+            Wdl::Loss => 1,
+            Wdl::CursedWin => 2,
+            Wdl::BlessedLoss => 3,
+        };
+        let mut value = value as usize;
+        if d.flags & MAPPED != 0 {
+            let i = d.map_idx[map] + value;
+            value = if d.flags & WIDE != 0 {
+                read_le(&data.bytes, data.map + 2 * i, 2).unwrap_or(0) as usize
+            } else {
+                data.bytes.get(data.map + i).cloned().unwrap_or(0) as usize
+            };
+        }
+        let mut dtz = value as i32;
+        // stored in moves rather than plies
+        if (wdl == Wdl::Win && d.flags & WIN_PLIES == 0)
+            || (wdl == Wdl::Loss && d.flags & LOSS_PLIES == 0)
+            || wdl == Wdl::CursedWin
+            || wdl == Wdl::BlessedLoss
+        {
+            dtz *= 2;
+        }
+        Lookup::Value(dtz + 1)
+    }
+
+    fn probe_wdl_table(&self, b: &Board, c: Color) -> Option<Wdl> {
+        if b.all().count_ones() == 2 {
+            return Some(Wdl::Draw);
+        }
+        match self.probe_table(b, c, Kind::Wdl, Wdl::Draw) {
+            Lookup::Value(v) => Some(Wdl::from_value(v)),
+            _ => None,
+        }
+    }
+
+    /// Result of `b` from its captures (and pawn moves with
+    /// `check_zeroing`) and from the table, which may hold any value when a
+    /// capture is better. The flag tells that the best move is one of those
+    /// moves, so that the DTZ table can't be trusted.
+    fn search(&self, b: &Board, c: Color, check_zeroing: bool) -> Option<(Wdl, bool)> {
+        let moves = generate_legal_moves(b, c);
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+        for m in moves.iter() {
+            let zeroing = check_zeroing && is_pawn_move(b, c, m);
+            if !m.flags.contains(Flags::CAPTURE) && !zeroing {
+                continue;
+            }
+            searched += 1;
+            let (value, _) = self.search(&b.apply(m).unwrap(), !c, false)?;
+            let value = -value;
+            if value > best {
+                best = value;
+                if value >= Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+        let all_searched = searched > 0 && searched == moves.len();
+        let value = if all_searched {
+            best
+        } else {
+            self.probe_wdl_table(b, c)?
+        };
+        if best >= value {
+            Some((best, best > Wdl::Draw || all_searched))
+        } else {
+            Some((value, false))
+        }
+    }
+
+    /// Result of `b`, `c` being to move, `None` if its table is missing.
+    pub fn probe_wdl(&self, b: &Board, c: Color) -> Option<Wdl> {
+        self.search(b, c, false).map(|(wdl, _)| wdl)
+    }
+
+    /// Plies to the next capture or pawn move of the optimal line, positive
+    /// for a win and negative for a loss, 0 for a draw. Cursed wins and
+    /// blessed losses are 100 plies further.
+    pub fn probe_dtz(&self, b: &Board, c: Color) -> Option<i32> {
+        let (wdl, zeroing) = self.search(b, c, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        let cursed = wdl == Wdl::CursedWin || wdl == Wdl::BlessedLoss;
+        match self.probe_table(b, c, Kind::Dtz, wdl) {
+            Lookup::Value(dtz) => return Some((dtz + 100 * cursed as i32) * (wdl as i32).signum()),
+            Lookup::Missing => return None,
+            Lookup::OtherSide => {}
+        }
+
+        // the best move from the values after each move
+        let mut min = i32::MAX;
+        for m in generate_legal_moves(b, c).iter() {
+            let zeroing = m.flags.contains(Flags::CAPTURE) || is_pawn_move(b, c, m);
+            let child = b.apply(m).unwrap();
+            let mut dtz = if zeroing {
+                -dtz_before_zeroing(self.search(&child, !c, false)?.0)
+            } else {
+                -self.probe_dtz(&child, !c)?
+            };
+            if dtz == 1 && in_check(&child, !c) && generate_legal_moves(&child, !c).is_empty() {
+                min = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min && dtz.signum() == (wdl as i32).signum() {
+                min = dtz;
+            }
+        }
+        // no legal move: mated
+        Some(if min == i32::MAX { -1 } else { min })
+    }
+
+    /// Keeps the root `moves` of `b` that preserve its best result, `rule50`
+    /// being the plies since the last capture or pawn move: with the DTZ
+    /// tables, the wins closest to their next capture or pawn move, so that
+    /// the win is converted, and the losses furthest from it. The result is
+    /// the value of `b` and whether the DTZ tables were available, `None` if
+    /// a table is missing.
+    pub fn filter_root_moves(
+        &self,
+        b: &Board,
+        c: Color,
+        rule50: usize,
+        moves: &mut Vec<GenMove>,
+    ) -> Option<(Wdl, bool)> {
+        let rule50 = rule50 as i32;
+        let dtz_ranks = moves
+            .iter()
+            .map(|m| {
+                let child = b.apply(m).unwrap();
+                let mut dtz = if m.flags.contains(Flags::CAPTURE) || is_pawn_move(b, c, m) {
+                    dtz_before_zeroing(-self.probe_wdl(&child, !c)?)
+                } else {
+                    let dtz = -self.probe_dtz(&child, !c)?;
+                    dtz + dtz.signum()
+                };
+                if dtz == 2 && in_check(&child, !c) && generate_legal_moves(&child, !c).is_empty() {
+                    dtz = 1;
+                }
+                // wins and losses beyond the 50-move rule rank between the others and the draws
+                let within_rule50 = dtz.abs() + rule50 <= 99;
+                let (wdl, rank) = match dtz.signum() {
+                    1 if within_rule50 => (Wdl::Win, (4, -dtz)),
+                    1 => (Wdl::CursedWin, (3, -dtz)),
+                    0 => (Wdl::Draw, (2, 0)),
+                    _ if within_rule50 => (Wdl::Loss, (0, -dtz)),
+                    _ => (Wdl::BlessedLoss, (1, -dtz)),
+                };
+                Some((wdl, rank))
+            })
+            .collect::<Option<Vec<_>>>();
+        let (ranks, dtz) = match dtz_ranks {
+            Some(ranks) => (ranks, true),
+            None => {
+                let ranks = moves
+                    .iter()
+                    .map(|m| {
+                        let wdl = -self.probe_wdl(&b.apply(m).unwrap(), !c)?;
+                        Some((wdl, (wdl as i32, 0)))
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                (ranks, false)
+            }
+        };
+        let (wdl, best) = *ranks.iter().max_by_key(|(_, rank)| *rank)?;
+        let mut ranks = ranks.iter();
+        moves.retain(|_| ranks.next().unwrap().1 == best);
+        Some((wdl, dtz))
+    }
+}
+
+fn is_pawn_move(b: &Board, c: Color, m: &GenMove) -> bool {
+    b.get_pc_board(Piece::Pawn, c) & m.from.board() != 0
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use dtm::{Dtm, DtmTables};
+    use endgame;
+    use move_generator::pawn_attacks;
+
+    const BLOCK_SIZE_LOG: u8 = 6;
+    const SPAN_LOG: u8 = 7;
+
+    fn board(pieces: &[(Piece, Color, usize)]) -> Board {
+        let mut b = Board::empty();
+        for &(p, c, sq) in pieces {
+            *b.get_pc_board_mut(p, c) |= 1 << sq;
+        }
+        b
+    }
+
+    /// Whether `b` can be reached with `c` to move.
+    fn valid(b: &Board, c: Color) -> bool {
+        let pawns = b.white.pawns | b.black.pawns;
+        b.all().count_ones() as usize == [&b.white, &b.black].iter().map(|p| count(p)).sum::<usize>()
+            && pawns & 0xff00_0000_0000_00ff == 0
+            && !in_check(b, !c)
+    }
+
+    fn count(p: &PartialBoard) -> usize {
+        [p.pawns, p.knights, p.bishops, p.rooks, p.queens, p.king]
+            .iter()
+            .map(|bb| bb.count_ones() as usize)
+            .sum()
+    }
+
+    /// Huffman code lengths of symbols used `freq` times.
+    fn code_lengths(freq: &[u64]) -> Vec<u8> {
+        let mut nodes: Vec<(u64, Vec<usize>)> = freq
+            .iter()
+            .enumerate()
+            .map(|(s, &f)| (std::cmp::max(f, 1), vec![s]))
+            .collect();
+        let mut lengths = vec![0; freq.len()];
+        while nodes.len() > 1 {
+            nodes.sort_by_key(|n| std::cmp::Reverse(n.0));
+            let (wa, a) = nodes.pop().unwrap();
+            let (wb, b) = nodes.pop().unwrap();
+            for &s in a.iter().chain(b.iter()) {
+                lengths[s] += 1;
+            }
+            nodes.push((wa + wb, [a, b].concat()));
+        }
+        lengths
+    }
+
+    /// One table of a file, compressed with the leaves, a single pair of
+    /// the most frequent adjacent values and a canonical Huffman code.
+    struct Compressed {
+        header: Vec<u8>,
+        sparse_index: Vec<u8>,
+        block_lengths: Vec<u8>,
+        blocks: Vec<u8>,
+    }
+
+    fn compress(values: &[u16], flags: u8) -> Compressed {
+        let mut leaves = values.to_vec();
+        leaves.sort_unstable();
+        leaves.dedup();
+        if leaves.len() == 1 {
+            return Compressed {
+                header: vec![flags | SINGLE_VALUE, leaves[0] as u8],
+                sparse_index: Vec::new(),
+                block_lengths: Vec::new(),
+                blocks: Vec::new(),
+            };
+        }
+        let leaf = |v: u16| leaves.binary_search(&v).unwrap();
+        let mut pairs = HashMap::new();
+        for w in values.windows(2) {
+            *pairs.entry((leaf(w[0]), leaf(w[1]))).or_insert(0) += 1;
+        }
+        let pair = *pairs.iter().max_by_key(|&(&p, &n)| (n, p)).unwrap().0;
+        let pair_sym = leaves.len();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < values.len() {
+            if i + 1 < values.len() && (leaf(values[i]), leaf(values[i + 1])) == pair {
+                tokens.push(pair_sym);
+                i += 2;
+            } else {
+                tokens.push(leaf(values[i]));
+                i += 1;
+            }
+        }
+
+        let mut freq = vec![0; leaves.len() + 1];
+        for &t in tokens.iter() {
+            freq[t] += 1;
+        }
+        let lengths = code_lengths(&freq);
+        let (min_len, max_len) = (*lengths.iter().min().unwrap(), *lengths.iter().max().unwrap());
+        // the longest codes first
+        let mut order: Vec<usize> = (0..freq.len()).collect();
+        order.sort_by_key(|&s| std::cmp::Reverse(lengths[s]));
+        let mut id = vec![0; freq.len()];
+        for (i, &s) in order.iter().enumerate() {
+            id[s] = i;
+        }
+        let lens = (max_len - min_len + 1) as usize;
+        let count = |l: usize| lengths.iter().filter(|&&x| x as usize == l).count() as u64;
+        let mut lowest = vec![0u64; lens];
+        let mut base = vec![0u64; lens];
+        for i in (0..lens - 1).rev() {
+            let longer = count(min_len as usize + i + 1);
+            lowest[i] = lowest[i + 1] + longer;
+            base[i] = (base[i + 1] + longer) / 2;
+        }
+        let code = |s: usize| {
+            let i = (lengths[s] - min_len) as usize;
+            (base[i] + id[s] as u64 - lowest[i], lengths[s] as usize)
+        };
+
+        let mut header = vec![flags, BLOCK_SIZE_LOG, SPAN_LOG, 0];
+        let block_bits = 8 << BLOCK_SIZE_LOG;
+        let mut blocks: Vec<Vec<(u64, usize)>> = vec![Vec::new()];
+        let mut block_values = vec![0usize];
+        let mut bits = 0;
+        for &t in tokens.iter() {
+            let (c, len) = code(t);
+            let n = if t == pair_sym { 2 } else { 1 };
+            if bits + len > block_bits || *block_values.last().unwrap() + n > 65536 {
+                blocks.push(Vec::new());
+                block_values.push(0);
+                bits = 0;
+            }
+            blocks.last_mut().unwrap().push((c, len));
+            *block_values.last_mut().unwrap() += n;
+            bits += len;
+        }
+        header.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+        header.push(max_len);
+        header.push(min_len);
+        for &l in lowest.iter() {
+            header.extend_from_slice(&(l as u16).to_le_bytes());
+        }
+        header.extend_from_slice(&(freq.len() as u16).to_le_bytes());
+        for (s, _) in order.iter().map(|&s| (s, ())) {
+            let (left, right) = if s == pair_sym {
+                (id[pair.0] as u16, id[pair.1] as u16)
+            } else {
+                (leaves[s], LEAF)
+            };
+            header.push(left as u8);
+            header.push((left >> 8) as u8 | (right as u8 & 0xf) << 4);
+            header.push((right >> 4) as u8);
+        }
+        if freq.len() % 2 == 1 {
+            header.push(0);
+        }
+
+        let mut data = Vec::new();
+        for block in blocks.iter() {
+            let mut bytes = vec![0u8; 1 << BLOCK_SIZE_LOG];
+            let mut pos = 0;
+            for &(c, len) in block.iter() {
+                for bit in (0..len).rev() {
+                    if c >> bit & 1 != 0 {
+                        bytes[pos / 8] |= 0x80 >> (pos % 8);
+                    }
+                    pos += 1;
+                }
+            }
+            data.extend(bytes);
+        }
+        let mut block_lengths = Vec::new();
+        for &n in block_values.iter() {
+            block_lengths.extend_from_slice(&((n - 1) as u16).to_le_bytes());
+        }
+        // block and offset in it of the middle of each span
+        let span = 1 << SPAN_LOG;
+        let mut sparse_index = Vec::new();
+        for k in 0..values.len().div_ceil(span) {
+            let mut idx = k * span + span / 2;
+            let mut block = 0;
+            while block + 1 < block_values.len() && idx >= block_values[block] {
+                idx -= block_values[block];
+                block += 1;
+            }
+            sparse_index.extend_from_slice(&(block as u32).to_le_bytes());
+            sparse_index.extend_from_slice(&(idx as u16).to_le_bytes());
+        }
+        Compressed {
+            header,
+            sparse_index,
+            block_lengths,
+            blocks: data,
+        }
+    }
+
+    /// Pieces and groups of the tables of a file, all of them with the
+    /// pieces in the order of `pieces` and the leading group first.
+    fn skeleton(table: &Table, kind: Kind, pieces: &[u8]) -> Vec<Vec<PairsData>> {
+        let sides = if kind == Kind::Wdl && !table.symmetric() { 2 } else { 1 };
+        let files = if table.has_pawns { 4 } else { 1 };
+        (0..files)
+            .map(|file| {
+                (0..sides)
+                    .map(|_| {
+                        let mut d = PairsData {
+                            pieces: pieces.to_vec(),
+                            ..PairsData::default()
+                        };
+                        table.set_groups(&mut d, [0, 0xf], file);
+                        d
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Values of the tables of a file, computed by `value` for the
+    /// positions of `pieces`, the first one on `first` squares. The other
+    /// positions hold the most frequent value.
+    fn fill<F: FnMut(&Board, Color) -> Option<u16>>(
+        table: &Table,
+        items: &[Vec<PairsData>],
+        pieces: &[(Piece, Color)],
+        first: &[usize],
+        mut value: F,
+    ) -> Vec<Vec<Vec<u16>>> {
+        let size = |d: &PairsData| d.group_idx[d.group_len.iter().position(|&l| l == 0).unwrap()];
+        let mut values: Vec<Vec<Vec<Option<u16>>>> = items
+            .iter()
+            .map(|sides| sides.iter().map(|d| vec![None; size(d) as usize]).collect())
+            .collect();
+        let mut squares = vec![0; pieces.len()];
+        let positions = first.len() * 64usize.pow(pieces.len() as u32 - 1);
+        for n in 0..positions {
+            squares[0] = first[n % first.len()];
+            let mut rest = n / first.len();
+            for sq in squares[1..].iter_mut() {
+                *sq = rest % 64;
+                rest /= 64;
+            }
+            let placed: Vec<_> = pieces.iter().zip(squares.iter()).map(|(&(p, c), &sq)| (p, c, sq)).collect();
+            let b = board(&placed);
+            for &c in [Color::White, Color::Black].iter() {
+                if !valid(&b, c) {
+                    continue;
+                }
+                let (stm, file, idx) = encode(table, items, &b, c);
+                let v = match value(&b, c) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let slot = &mut values[file][stm % items[file].len()][idx as usize];
+                assert!(slot.is_none_or(|s| s == v), "{} and {:?} share index {}", b, c, idx);
+                *slot = Some(v);
+            }
+        }
+        values
+            .into_iter()
+            .map(|sides| {
+                sides
+                    .into_iter()
+                    .map(|values| {
+                        let mut counts = HashMap::new();
+                        for v in values.iter().flatten() {
+                            *counts.entry(*v).or_insert(0) += 1;
+                        }
+                        let common = counts.iter().max_by_key(|&(&v, &n)| (n, v)).map_or(0, |(&v, _)| v);
+                        values.iter().map(|v| v.unwrap_or(common)).collect()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Writes a file of `kind` for `table`, with the tables `values` and
+    /// their `flags`, and for DTZ the value maps of each pawn file.
+    fn write_file(
+        path: &Path,
+        kind: Kind,
+        table: &Table,
+        pieces: &[u8],
+        values: &[Vec<Vec<u16>>],
+        flags: u8,
+        maps: &[[Vec<u8>; 4]],
+    ) {
+        let mut bytes = kind.magic().to_vec();
+        bytes.push(if table.symmetric() { 0 } else { SPLIT } | if table.has_pawns { HAS_PAWNS } else { 0 });
+        let pp = table.has_pawns && table.pawn_count[1] > 0;
+        for _ in values.iter() {
+            bytes.push(0);
+            if pp {
+                bytes.push(0xff);
+            }
+            bytes.extend(pieces.iter().map(|&p| p | p << 4));
+        }
+        if bytes.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        let compressed: Vec<Compressed> = values.iter().flatten().map(|v| compress(v, flags)).collect();
+        for c in compressed.iter() {
+            bytes.extend_from_slice(&c.header);
+        }
+        if flags & MAPPED != 0 {
+            for file_maps in maps.iter() {
+                for map in file_maps.iter() {
+                    bytes.push(map.len() as u8);
+                    bytes.extend_from_slice(map);
+                }
+            }
+        }
+        if bytes.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        for c in compressed.iter() {
+            bytes.extend_from_slice(&c.sparse_index);
+        }
+        for c in compressed.iter() {
+            bytes.extend_from_slice(&c.block_lengths);
+        }
+        for c in compressed.iter() {
+            bytes.resize((bytes.len() + 0x3f) & !0x3f, 0);
+            bytes.extend_from_slice(&c.blocks);
+        }
+        bytes.resize((bytes.len() + 0x3f) & !0x3f, 0);
+        bytes.resize(bytes.len() + 16, 0);
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    fn wdl_value(wdl: Wdl) -> Option<u16> {
+        Some((wdl as i32 + 2) as u16)
+    }
+
+    /// King and queen or rook against king: won with White to move, lost
+    /// with Black to move unless the piece can be taken or Black is
+    /// stalemated.
+    fn major_wdl(b: &Board, c: Color) -> Wdl {
+        if c == Color::White {
+            return Wdl::Win;
+        }
+        let moves = generate_legal_moves(b, c);
+        if moves.is_empty() {
+            if in_check(b, c) {
+                Wdl::Loss
+            } else {
+                Wdl::Draw
+            }
+        } else if moves.iter().any(|m| m.flags.contains(Flags::CAPTURE)) {
+            Wdl::Draw
+        } else {
+            Wdl::Loss
+        }
+    }
+
+    /// Result of the positions of the generated tables, White being the
+    /// stronger side.
+    fn truth(b: &Board, c: Color) -> Wdl {
+        match (signature(b, Color::White).as_str(), signature(b, Color::Black).as_str()) {
+            ("KQ", "K") | ("KR", "K") => major_wdl(b, c),
+            ("KP", "K") => {
+                let win = endgame::probe(b, c).is_some_and(|k| k.score > 0);
+                match (win, c) {
+                    (false, _) => Wdl::Draw,
+                    (true, Color::White) => Wdl::Win,
+                    (true, Color::Black) => Wdl::Loss,
+                }
+            }
+            _ => Wdl::Draw,
+        }
+    }
+
+    const TRIANGLE: [usize; 10] = [0, 1, 2, 3, 9, 10, 11, 18, 19, 27];
+
+    fn pawn_squares() -> Vec<usize> {
+        (8..56).filter(|sq| sq % 8 < 4).collect()
+    }
+
+    /// KPvK positions with White to move indexed by pawn, white king and
+    /// black king squares.
+    fn kpk_index(pawn: usize, wk: usize, bk: usize) -> usize {
+        (pawn * 64 + wk) * 64 + bk
+    }
+
+    /// Distance to zeroing of the won KPvK positions, White to move, by
+    /// retrograde analysis of the king moves: 1 when a pawn move wins,
+    /// else one more than the quickest black loss reached, each black loss
+    /// being one more than its longest white win.
+    pub fn kpk_dtz() -> Vec<i32> {
+        let size = 64 * 64 * 64;
+        let mut white = vec![0; size];
+        let mut black = vec![-1; size];
+        let mut white_wins = vec![false; size];
+        let mut black_loses = vec![false; size];
+        let mut positions = Vec::new();
+        for pawn in pawn_squares() {
+            for wk in 0..64 {
+                for bk in 0..64 {
+                    let b = board(&[(Piece::Pawn, Color::White, pawn), (Piece::King, Color::White, wk), (Piece::King, Color::Black, bk)]);
+                    let i = kpk_index(pawn, wk, bk);
+                    if valid(&b, Color::White) && truth(&b, Color::White) == Wdl::Win {
+                        white_wins[i] = true;
+                        let zeroing = generate_legal_moves(&b, Color::White).iter().any(|m| {
+                            is_pawn_move(&b, Color::White, m) && truth(&b.apply(m).unwrap(), Color::Black) == Wdl::Loss
+                        });
+                        if zeroing {
+                            white[i] = 1;
+                        }
+                    }
+                    if valid(&b, Color::Black) && truth(&b, Color::Black) == Wdl::Loss {
+                        black_loses[i] = true;
+                        if generate_legal_moves(&b, Color::Black).is_empty() {
+                            black[i] = 0;
+                        }
+                    }
+                    positions.push((pawn, wk, bk));
+                }
+            }
+        }
+        loop {
+            let mut changed = false;
+            for &(pawn, wk, bk) in positions.iter() {
+                let i = kpk_index(pawn, wk, bk);
+                if !white_wins[i] || white[i] != 0 {
+                    continue;
+                }
+                let moves = king_attacks(Case(wk as u8)) & !king_attacks(Case(bk as u8)) & !(1 << pawn);
+                let best = CaseIterator::new(moves)
+                    .map(|to| black[kpk_index(pawn, to.0 as usize, bk)])
+                    .filter(|&d| d >= 0)
+                    .min();
+                if let Some(d) = best {
+                    white[i] = d + 1;
+                    changed = true;
+                }
+            }
+            for &(pawn, wk, bk) in positions.iter() {
+                let i = kpk_index(pawn, wk, bk);
+                if !black_loses[i] || black[i] >= 0 {
+                    continue;
+                }
+                let attacked = king_attacks(Case(wk as u8)) | pawn_attacks(1 << pawn, Color::White) | 1 << pawn;
+                let moves = king_attacks(Case(bk as u8)) & !attacked;
+                let children: Vec<_> = CaseIterator::new(moves)
+                    .map(|to| white[kpk_index(pawn, wk, to.0 as usize)])
+                    .collect();
+                if children.iter().all(|&d| d > 0) {
+                    black[i] = children.iter().max().unwrap() + 1;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        white
+    }
+
+    fn generate(dir: &Path) {
+        use crate::Piece::*;
+        let (white, black) = (Color::White, Color::Black);
+        std::fs::create_dir_all(dir).unwrap();
+        for &(name, p) in [("KQvK", Queen), ("KRvK", Rook)].iter() {
+            let table = Table::new(name, PathBuf::new(), None).unwrap();
+            let pieces = [piece_code(p, white), piece_code(King, white), piece_code(King, black)];
+            let items = skeleton(&table, Kind::Wdl, &pieces);
+            let values = fill(&table, &items, &[(p, white), (King, white), (King, black)], &TRIANGLE, |b, c| {
+                wdl_value(major_wdl(b, c))
+            });
+            write_file(&dir.join(format!("{}.rtbw", name)), Kind::Wdl, &table, &pieces, &values, 0, &[]);
+        }
+        for &(name, p) in [("KBvK", Bishop), ("KNvK", Knight)].iter() {
+            let table = Table::new(name, PathBuf::new(), None).unwrap();
+            let pieces = [piece_code(p, white), piece_code(King, white), piece_code(King, black)];
+            let values = vec![vec![vec![2], vec![2]]];
+            write_file(&dir.join(format!("{}.rtbw", name)), Kind::Wdl, &table, &pieces, &values, 0, &[]);
+        }
+
+        let table = Table::new("KPvK", PathBuf::new(), None).unwrap();
+        let pieces = [piece_code(Pawn, white), piece_code(King, white), piece_code(King, black)];
+        let placed = [(Pawn, white), (King, white), (King, black)];
+        let items = skeleton(&table, Kind::Wdl, &pieces);
+        let values = fill(&table, &items, &placed, &pawn_squares(), |b, c| wdl_value(truth(b, c)));
+        write_file(&dir.join("KPvK.rtbw"), Kind::Wdl, &table, &pieces, &values, 0, &[]);
+
+        // DTZ in plies for White to move, through a map of the values
+        let dtz = kpk_dtz();
+        let mut distances: Vec<i32> = dtz.iter().cloned().filter(|&d| d > 0).collect();
+        distances.sort_unstable();
+        distances.dedup();
+        let items = skeleton(&table, Kind::Dtz, &pieces);
+        let values = fill(&table, &items, &placed, &pawn_squares(), |b, c| {
+            let square = |bb: u64| bb.trailing_zeros() as usize;
+            let d = dtz[kpk_index(square(b.white.pawns), square(b.white.king), square(b.black.king))];
+            if c == Color::White && d > 0 {
+                Some(distances.binary_search(&d).unwrap() as u16)
+            } else {
+                None
+            }
+        });
+        let map: Vec<u8> = distances.iter().map(|&d| (d - 1) as u8).collect();
+        let maps = vec![[map, Vec::new(), Vec::new(), Vec::new()]; 4];
+        let path = dir.join("KPvK.rtbz");
+        write_file(&path, Kind::Dtz, &table, &pieces, &values, MAPPED | WIN_PLIES, &maps);
+    }
+
+    /// Tablebases of up to 3 pieces written by `generate`, once for all the tests.
+    pub fn tablebases() -> Arc<Tablebases> {
+        static TABLEBASES: OnceLock<Arc<Tablebases>> = OnceLock::new();
+        TABLEBASES
+            .get_or_init(|| {
+                let dir = TempDir::new("chess-syzygy");
+                generate(&dir.0);
+                let tb = Tablebases::open(dir.0.to_str().unwrap());
+                // read before the files are removed
+                for table in tb.tables.values() {
+                    table.data(Kind::Wdl);
+                    table.data(Kind::Dtz);
+                }
+                Arc::new(tb)
+            })
+            .clone()
+    }
+
+    /// Empty directory of this test process, so that concurrent runs don't
+    /// share it, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn parse(fen: &str) -> (Board, Color) {
+        parse_fen_color(&mut fen.chars()).unwrap()
+    }
+
+    #[test]
+    fn encoding_maps() {
+        let kk: Vec<u64> = (0..10)
+            .flat_map(|i| (0..64).map(move |sq| (i, sq)))
+            .filter(|&(i, sq)| {
+                let first = TRIANGLE.iter().find(|&&s| MAPS.a1d1d4[s] == i as u64).unwrap();
+                (king_attacks(Case(*first as u8)) | 1 << first) & 1 << sq == 0
+                    && (off_diagonal(*first) != 0 || off_diagonal(sq) <= 0)
+            })
+            .map(|(i, sq)| MAPS.kk[i][sq])
+            .collect();
+        assert_eq!(KK_SIZE as usize, kk.len());
+        assert_eq!((0..KK_SIZE).collect::<Vec<_>>(), {
+            let mut kk = kk.clone();
+            kk.sort_unstable();
+            kk
+        });
+        assert_eq!(6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + 4 * 7 * 6, UNIQUE_SIZE);
+        assert_eq!(24, MAPS.lead_pawns_size[1].iter().sum::<u64>());
+        assert_eq!(47, MAPS.pawns[8]);
+        assert_eq!(0, MAPS.pawns[52]);
+        assert_eq!(15, MAPS.binomial[2][6]);
+        assert_eq!(Wdl::Loss, -Wdl::Win);
+        assert_eq!(Wdl::CursedWin, -Wdl::BlessedLoss);
+    }
+
+    #[test]
+    fn huffman_code() {
+        let values: Vec<u16> = (0..5000).map(|i| [4, 4, 2, 4, 0, 2, 4][i % 7] + (i % 997 == 0) as u16).collect();
+        let lengths = code_lengths(&[1, 5, 20, 3]);
+        assert_eq!(vec![3, 2, 1, 3], lengths);
+        let table = Table::new("KQvK", PathBuf::new(), None).unwrap();
+        let mut items = skeleton(&table, Kind::Wdl, &[5, 6, 14]);
+        let c = compress(&values, 0);
+        let mut bytes = c.header.clone();
+        let d = &mut items[0][0];
+        d.group_idx[0] = values.len() as u64;
+        d.group_len = [1, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(Some(bytes.len()), d.set_sizes(&bytes, 0));
+        d.sparse_index = bytes.len();
+        bytes.extend_from_slice(&c.sparse_index);
+        d.block_length = bytes.len();
+        bytes.extend_from_slice(&c.block_lengths);
+        d.data = bytes.len();
+        bytes.extend_from_slice(&c.blocks);
+        assert!(d.num_blocks > 10);
+        for (i, &v) in values.iter().enumerate() {
+            assert_eq!(v, d.decompress(&bytes, i as u64), "value {}", i);
+        }
+    }
+
+    #[test]
+    fn wdl_probes() {
+        let tb = tablebases();
+        assert_eq!(5, tb.count());
+        assert_eq!(3, tb.max_pieces());
+        let wdl = |fen: &str| {
+            let (b, c) = parse(fen);
+            tb.probe_wdl(&b, c)
+        };
+        assert_eq!(Some(Wdl::Draw), wdl("8/8/8/8/8/2k5/8/K7 w"));
+        assert_eq!(Some(Wdl::Win), wdl("8/8/8/8/8/2k5/8/K6Q w"));
+        assert_eq!(Some(Wdl::Loss), wdl("8/8/8/8/8/2k5/8/K6Q b"));
+        // the queen is taken
+        assert_eq!(Some(Wdl::Draw), wdl("8/8/8/8/8/2k5/2Q5/7K b"));
+        assert_eq!(Some(Wdl::Draw), wdl("k7/2Q5/1K6/8/8/8/8/8 b"));
+        assert_eq!(Some(Wdl::Loss), wdl("k7/8/1K6/8/8/8/8/6r1 w"));
+        assert_eq!(Some(Wdl::Draw), wdl("8/8/8/8/8/2k5/8/KN6 b"));
+        assert_eq!(Some(Wdl::Draw), wdl("8/8/8/8/8/2k5/8/Kb6 w"));
+        assert_eq!(None, wdl("8/8/8/8/8/2k5/8/KNN5 w"));
+        assert_eq!(None, wdl("8/8/8/8/3q4/2k5/8/K6Q w"));
+
+        // every other KPvK position, with the colors swapped and mirrored
+        let mirror = |b: &Board| {
+            let flip = |p: &PartialBoard| PartialBoard {
+                pawns: p.pawns.swap_bytes(),
+                knights: p.knights.swap_bytes(),
+                bishops: p.bishops.swap_bytes(),
+                rooks: p.rooks.swap_bytes(),
+                queens: p.queens.swap_bytes(),
+                king: p.king.swap_bytes(),
+            };
+            Board {
+                white: flip(&b.black),
+                black: flip(&b.white),
+            }
+        };
+        for n in (0..48 * 64 * 64).step_by(37) {
+            let (pawn, wk, bk) = (8 + n / 4096, n / 64 % 64, n % 64);
+            let b = board(&[(Piece::Pawn, Color::White, pawn), (Piece::King, Color::White, wk), (Piece::King, Color::Black, bk)]);
+            for &c in [Color::White, Color::Black].iter() {
+                if valid(&b, c) {
+                    let expected = Some(truth(&b, c));
+                    assert_eq!(expected, tb.probe_wdl(&b, c), "{}", b);
+                    assert_eq!(expected, tb.probe_wdl(&mirror(&b), !c), "{}", b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dtz_probes() {
+        let tb = tablebases();
+        let dtz = kpk_dtz();
+        let mut checked = 0;
+        for pawn in pawn_squares() {
+            for n in (0..64 * 64).step_by(53) {
+                let (wk, bk) = (n / 64, n % 64);
+                let b = board(&[(Piece::Pawn, Color::White, pawn), (Piece::King, Color::White, wk), (Piece::King, Color::Black, bk)]);
+                if !valid(&b, Color::White) {
+                    continue;
+                }
+                let expected = match truth(&b, Color::White) {
+                    Wdl::Win => dtz[kpk_index(pawn, wk, bk)],
+                    _ => 0,
+                };
+                assert_eq!(Some(expected), tb.probe_dtz(&b, Color::White), "{}", b);
+                checked += 1;
+            }
+        }
+        assert!(checked > 1000);
+
+        let probe = |fen: &str| {
+            let (b, c) = parse(fen);
+            tb.probe_dtz(&b, c)
+        };
+        // the pawn is pushed at once
+        assert_eq!(Some(1), probe("8/8/8/8/8/2k5/6P1/6K1 w"));
+        assert_eq!(Some(0), probe("8/4k3/8/4K3/4P3/8/8/8 w"));
+        // Black to move: the longest white win after each king move
+        let (b, c) = parse("8/4k3/8/4K3/4P3/8/8/8 b");
+        let longest = generate_legal_moves(&b, c)
+            .iter()
+            .map(|m| tb.probe_dtz(&b.apply(m).unwrap(), !c).unwrap())
+            .max()
+            .unwrap();
+        assert!(longest > 1);
+        assert_eq!(Some(-longest - 1), tb.probe_dtz(&b, c));
+        assert_eq!(Some(1), probe("8/1P6/8/8/8/k7/8/1K6 w"));
+        // no DTZ file for KQvK
+        assert_eq!(None, probe("k7/8/1K6/8/8/8/8/7Q w"));
+        // the black pawn mirrors the white one
+        assert_eq!(probe("8/8/8/8/8/2k5/6P1/6K1 w"), probe("6k1/6p1/2K5/8/8/8/8/8 b"));
+        assert_eq!(None, probe("8/8/8/8/8/2k5/8/KNN5 w"));
+    }
+
+    #[test]
+    fn root_moves() {
+        let tb = tablebases();
+        let (b, c) = parse("4k3/8/8/8/8/8/4P3/4K3 w");
+        let mut moves = generate_legal_moves(&b, c);
+        let all = moves.len();
+        assert_eq!(Some((Wdl::Win, true)), tb.filter_root_moves(&b, c, 0, &mut moves));
+        assert!(!moves.is_empty() && moves.len() < all);
+        let dtz: Vec<_> = moves
+            .iter()
+            .map(|m| tb.probe_dtz(&b.apply(m).unwrap(), !c).unwrap())
+            .collect();
+        // the kept moves are the quickest to zero among the winning ones
+        for m in generate_legal_moves(&b, c).iter() {
+            let child = tb.probe_dtz(&b.apply(m).unwrap(), !c).unwrap();
+            if !moves.contains(m) && !is_pawn_move(&b, c, m) {
+                assert!(child >= 0 || child < dtz[0], "{} {}", m, child);
+            }
+        }
+        assert!(dtz.iter().all(|&d| d == dtz[0] && d < 0));
+
+        // a draw keeps all the drawing moves
+        let (b, c) = parse("8/8/8/3k4/8/8/3P4/3K4 w");
+        let mut moves = generate_legal_moves(&b, c);
+        assert_eq!(Some((Wdl::Draw, true)), tb.filter_root_moves(&b, c, 0, &mut moves));
+        assert_eq!(6, moves.len());
+        // the win is too far with the 50-move rule about to draw
+        let (b, c) = parse("4k3/8/8/8/8/8/4P3/4K3 w");
+        let mut moves = generate_legal_moves(&b, c);
+        assert_eq!(Some((Wdl::CursedWin, true)), tb.filter_root_moves(&b, c, 99, &mut moves));
+
+        let (b, c) = parse("8/8/8/3k4/8/8/3P4/3KN3 w");
+        let mut moves = generate_legal_moves(&b, c);
+        assert_eq!(None, tb.filter_root_moves(&b, c, 0, &mut moves));
+    }
+
+    /// Real KQvK, KRvK and KPvK tables from `SYZYGY_PATH`, checked against
+    /// known values, the rules of `truth`, the distance to mate tables and
+    /// the KPvK retrograde analysis, so that a misreading of the format
+    /// shared with `generate` can't pass:
+    /// `SYZYGY_PATH=<dir> cargo test real_tables -- --ignored`.
+    #[test]
+    #[ignore]
+    fn real_tables() {
+        let path = std::env::var("SYZYGY_PATH").expect("SYZYGY_PATH should name the real tables");
+        let tb = Tablebases::open(&path);
+        let probe = |fen: &str| {
+            let (b, c) = parse(fen);
+            (tb.probe_wdl(&b, c), tb.probe_dtz(&b, c))
+        };
+        // Qg7#
+        assert_eq!((Some(Wdl::Win), Some(1)), probe("7k/8/5K2/8/8/8/8/6Q1 w"));
+        assert_eq!((Some(Wdl::Draw), Some(0)), probe("k7/2Q5/1K6/8/8/8/8/8 b"));
+        assert_eq!((Some(Wdl::Win), Some(1)), probe("8/8/8/8/8/2k5/6P1/6K1 w"));
+        // Black has the opposition
+        assert_eq!((Some(Wdl::Draw), Some(0)), probe("8/4k3/8/4K3/4P3/8/8/8 w"));
+        assert_eq!(Some(Wdl::Loss), probe("8/4k3/8/4K3/4P3/8/8/8 b").0);
+        // b8=Q stalemates, b8=R wins
+        assert_eq!((Some(Wdl::Win), Some(1)), probe("8/1P6/k7/8/K7/8/8/8 w"));
+
+        let endgames = DtmTables::builtin();
+        let kpk = kpk_dtz();
+        for &piece in [Piece::Queen, Piece::Rook, Piece::Pawn].iter() {
+            let mut checked = 0;
+            for sq in 0..64 {
+                for n in 0..64 * 64 {
+                    let (wk, bk) = (n / 64, n % 64);
+                    let b = board(&[(piece, Color::White, sq), (Piece::King, Color::White, wk), (Piece::King, Color::Black, bk)]);
+                    for &c in [Color::White, Color::Black].iter() {
+                        if !valid(&b, c) {
+                            continue;
+                        }
+                        let wdl = truth(&b, c);
+                        assert_eq!(Some(wdl), tb.probe_wdl(&b, c), "{} {:?}", b, c);
+                        checked += 1;
+                        let dtz = match (piece, c, wdl) {
+                            (_, _, Wdl::Draw) => 0,
+                            (Piece::Pawn, Color::White, _) if pawn_squares().contains(&sq) => {
+                                kpk[kpk_index(sq, wk, bk)]
+                            }
+                            // the mate is the only zeroing of the pawnless tables
+                            (Piece::Queen, Color::White, _) | (Piece::Rook, Color::White, _) => {
+                                match endgames.probe(&b, c) {
+                                    Some(Dtm::Win(plies)) => plies as i32,
+                                    dtm => panic!("{} {:?}", b, dtm),
+                                }
+                            }
+                            _ => continue,
+                        };
+                        assert_eq!(Some(dtz), tb.probe_dtz(&b, c), "{} {:?}", b, c);
+                    }
+                }
+            }
+            assert!(checked > 10000, "{:?}", piece);
+        }
+    }
+
+    #[test]
+    fn invalid_files() {
+        let temp = TempDir::new("chess-syzygy-invalid");
+        let dir = &temp.0;
+        std::fs::write(dir.join("KQvK.rtbw"), [0x71, 0xe8, 0x23, 0x5d, 0]).unwrap();
+        std::fs::write(dir.join("notes.txt"), "KRvK").unwrap();
+        let tb = Tablebases::open(dir.to_str().unwrap());
+        assert_eq!(1, tb.count());
+        let (b, c) = parse("8/8/8/8/8/2k5/8/K6Q w");
+        assert_eq!(None, tb.probe_wdl(&b, c));
+        assert_eq!(0, Tablebases::open("/nonexistent/chess-syzygy").count());
+    }
+}
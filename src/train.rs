@@ -0,0 +1,408 @@
+use board::*;
+use nnue::{features, Network, INPUTS, OUTPUT_SCALE, QA, QB};
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::path::Path;
+use tune::parse_result;
+
+/// Position to train on: the inputs of the side to move and of the other
+/// side, and the expected result for the side to move.
+pub struct TrainSample {
+    features: [Vec<u32>; 2],
+    target: f32,
+}
+
+/// Expected result of a position evaluated at `cp` centipawns.
+fn sigmoid(cp: f32) -> f32 {
+    1.0 / (1.0 + 10f32.powf(-cp / 400.0))
+}
+
+/// Parses `<FEN> <score> <result>`, the score in centipawns and the result
+/// as in `tune`, both for White. The target blends the result expected from
+/// the score, weighted by `lambda`, with the actual result.
+fn parse_sample(line: &str, lambda: f32) -> Option<TrainSample> {
+    let tokens: Vec<_> = line.split(|c: char| c.is_whitespace() || c == '|').filter(|t| !t.is_empty()).collect();
+    if tokens.len() < 4 {
+        return None;
+    }
+    let result = parse_result(tokens[tokens.len() - 1])? as f32;
+    let score: f32 = tokens[tokens.len() - 2].parse().ok()?;
+    let placement = tokens[0];
+    if !placement.chars().all(|c| "/12345678pnbrqkPNBRQK".contains(c)) {
+        return None;
+    }
+    let fen = format!("{} {}", placement, tokens[1]);
+    let (b, c) = parse_fen_color(&mut fen.chars())?;
+    let target = lambda * sigmoid(score) + (1.0 - lambda) * result;
+    let to_u32 = |f: Vec<usize>| f.into_iter().map(|x| x as u32).collect();
+    Some(TrainSample {
+        features: [to_u32(features(&b, c)), to_u32(features(&b, !c))],
+        target: c.map(target, 1.0 - target),
+    })
+}
+
+pub fn load_samples<P: AsRef<Path>>(path: P, lambda: f32) -> Result<Vec<TrainSample>, String> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut samples = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_sample(line, lambda) {
+            Some(sample) => samples.push(sample),
+            None => warn!("{}:{}: invalid position {}", path.display(), i + 1, line),
+        }
+    }
+    Ok(samples)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Optimizer {
+    Sgd,
+    Adam,
+}
+
+#[derive(Debug, Clone)]
+pub struct TrainOptions {
+    pub hidden: usize,
+    pub epochs: usize,
+    pub batch_size: usize,
+    pub learning_rate: f32,
+    pub optimizer: Optimizer,
+    /// Share of the positions kept aside to measure the validation loss.
+    pub validation: f32,
+    pub seed: u64,
+}
+
+impl Default for TrainOptions {
+    fn default() -> Self {
+        TrainOptions {
+            hidden: 128,
+            epochs: 10,
+            batch_size: 1024,
+            learning_rate: 0.001,
+            optimizer: Optimizer::Adam,
+            validation: 0.1,
+            seed: 1,
+        }
+    }
+}
+
+/// Losses at the end of an epoch.
+#[derive(Debug, Clone, Copy)]
+pub struct EpochStats {
+    pub epoch: usize,
+    pub train_loss: f32,
+    pub validation_loss: Option<f32>,
+}
+
+const BETA1: f32 = 0.9;
+const BETA2: f32 = 0.999;
+const EPSILON: f32 = 1e-8;
+
+/// Network of `nnue::Network` in floating point, activations clipped to
+/// [0, 1], trained on the squared error of the expected result.
+///
+/// The weights are kept in one vector: the input weights, one row of
+/// `hidden` per input, then the hidden biases, the output weights and the
+/// output bias.
+pub struct Trainer {
+    hidden: usize,
+    params: Vec<f32>,
+    grads: Vec<f32>,
+    m: Vec<f32>,
+    v: Vec<f32>,
+    /// Input rows with a gradient in the current batch.
+    touched: Vec<bool>,
+    rows: Vec<usize>,
+    step: i32,
+    options: TrainOptions,
+}
+
+impl Trainer {
+    pub fn new(options: TrainOptions) -> Self {
+        let hidden = options.hidden;
+        let len = INPUTS * hidden + 3 * hidden + 1;
+        let mut rng = SmallRng::seed_from_u64(options.seed);
+        let mut params = vec![0.0; len];
+        for w in params[..INPUTS * hidden].iter_mut() {
+            *w = rng.gen_range(-0.1, 0.1);
+        }
+        for w in params[INPUTS * hidden..INPUTS * hidden + hidden].iter_mut() {
+            *w = rng.gen_range(0.0, 0.5);
+        }
+        let scale = 1.0 / (2.0 * hidden as f32).sqrt();
+        for w in params[INPUTS * hidden + hidden..len - 1].iter_mut() {
+            *w = rng.gen_range(-scale, scale);
+        }
+        let adam = options.optimizer == Optimizer::Adam;
+        Trainer {
+            hidden,
+            grads: vec![0.0; len],
+            m: if adam { vec![0.0; len] } else { Vec::new() },
+            v: if adam { vec![0.0; len] } else { Vec::new() },
+            params,
+            touched: vec![false; INPUTS],
+            rows: Vec::new(),
+            step: 0,
+            options,
+        }
+    }
+
+    fn bias_at(&self) -> usize {
+        INPUTS * self.hidden
+    }
+
+    fn out_at(&self) -> usize {
+        self.bias_at() + self.hidden
+    }
+
+    /// Hidden layers of both sides and the output in centipawns.
+    fn forward(&self, s: &TrainSample, acc: &mut Vec<f32>) -> f32 {
+        let h = self.hidden;
+        let bias_at = self.bias_at();
+        acc.clear();
+        for side in s.features.iter() {
+            acc.extend_from_slice(&self.params[bias_at..bias_at + h]);
+            let half = acc.len() - h;
+            for &f in side {
+                let row = &self.params[f as usize * h..(f as usize + 1) * h];
+                for (a, w) in acc[half..].iter_mut().zip(row) {
+                    *a += w;
+                }
+            }
+        }
+        let out_at = self.out_at();
+        let out: f32 = acc
+            .iter()
+            .zip(&self.params[out_at..out_at + 2 * h])
+            .map(|(a, w)| a.clamp(0.0, 1.0) * w)
+            .sum();
+        (out + self.params[out_at + 2 * h]) * OUTPUT_SCALE as f32
+    }
+
+    /// Squared error of `s`, accumulating its gradient.
+    fn backward(&mut self, s: &TrainSample, acc: &mut Vec<f32>) -> f32 {
+        let h = self.hidden;
+        let cp = self.forward(s, acc);
+        let p = sigmoid(cp);
+        let error = p - s.target;
+        // through the sigmoid, down to the output before scaling
+        let g = 2.0 * error * p * (1.0 - p) * std::f32::consts::LN_10 / 400.0 * OUTPUT_SCALE as f32;
+        let (bias_at, out_at) = (self.bias_at(), self.out_at());
+        self.grads[out_at + 2 * h] += g;
+        for (side, features) in s.features.iter().enumerate() {
+            for j in 0..h {
+                let a = acc[side * h + j];
+                let w = self.params[out_at + side * h + j];
+                self.grads[out_at + side * h + j] += g * a.clamp(0.0, 1.0);
+                // reuse the activation slot for the gradient of the hidden layer
+                acc[side * h + j] = if a > 0.0 && a < 1.0 { g * w } else { 0.0 };
+            }
+            let d = &acc[side * h..(side + 1) * h];
+            for (gb, &x) in self.grads[bias_at..bias_at + h].iter_mut().zip(d) {
+                *gb += x;
+            }
+            for &f in features {
+                let f = f as usize;
+                if !self.touched[f] {
+                    self.touched[f] = true;
+                    self.rows.push(f);
+                }
+                for (gw, &x) in self.grads[f * h..(f + 1) * h].iter_mut().zip(d) {
+                    *gw += x;
+                }
+            }
+        }
+        error * error
+    }
+
+    fn apply(&mut self, range: std::ops::Range<usize>, scale: f32) {
+        let lr = self.options.learning_rate;
+        match self.options.optimizer {
+            Optimizer::Sgd => {
+                for i in range {
+                    self.params[i] -= lr * self.grads[i] * scale;
+                    self.grads[i] = 0.0;
+                }
+            }
+            Optimizer::Adam => {
+                let t = self.step;
+                let correction1 = 1.0 - BETA1.powi(t);
+                let correction2 = 1.0 - BETA2.powi(t);
+                for i in range {
+                    let g = self.grads[i] * scale;
+                    self.m[i] = BETA1 * self.m[i] + (1.0 - BETA1) * g;
+                    self.v[i] = BETA2 * self.v[i] + (1.0 - BETA2) * g * g;
+                    let m = self.m[i] / correction1;
+                    let v = self.v[i] / correction2;
+                    self.params[i] -= lr * m / (v.sqrt() + EPSILON);
+                    self.grads[i] = 0.0;
+                }
+            }
+        }
+    }
+
+    /// Updates the weights from the gradient of a batch of `count` samples.
+    /// Only the input rows of the batch are touched.
+    fn update(&mut self, count: usize) {
+        let h = self.hidden;
+        let scale = 1.0 / count as f32;
+        self.step += 1;
+        let rows = std::mem::take(&mut self.rows);
+        for &f in rows.iter() {
+            self.touched[f] = false;
+            self.apply(f * h..(f + 1) * h, scale);
+        }
+        self.rows = rows;
+        self.rows.clear();
+        let (bias_at, len) = (self.bias_at(), self.params.len());
+        self.apply(bias_at..len, scale);
+        // the output weights must fit in an i8 once quantized
+        let limit = 127.0 / QB as f32;
+        let out_at = self.out_at();
+        for w in self.params[out_at..out_at + 2 * h].iter_mut() {
+            *w = w.clamp(-limit, limit);
+        }
+    }
+
+    /// Mean squared error over `samples`, without training.
+    pub fn loss(&self, samples: &[TrainSample]) -> f32 {
+        let mut acc = Vec::with_capacity(2 * self.hidden);
+        let total: f32 = samples
+            .iter()
+            .map(|s| (sigmoid(self.forward(s, &mut acc)) - s.target).powi(2))
+            .sum();
+        total / std::cmp::max(samples.len(), 1) as f32
+    }
+
+    /// Trains on `samples` for the configured epochs, a share of them held
+    /// out for validation. `on_epoch` gets the losses after each epoch.
+    pub fn train<F>(&mut self, mut samples: Vec<TrainSample>, mut on_epoch: F)
+    where
+        F: FnMut(&Trainer, EpochStats),
+    {
+        let mut rng = SmallRng::seed_from_u64(self.options.seed);
+        samples.shuffle(&mut rng);
+        let held_out = (samples.len() as f32 * self.options.validation) as usize;
+        let validation = samples.split_off(samples.len() - held_out);
+        let batch_size = std::cmp::max(self.options.batch_size, 1);
+        let mut acc = Vec::with_capacity(2 * self.hidden);
+        for epoch in 1..=self.options.epochs {
+            samples.shuffle(&mut rng);
+            let mut total = 0.0;
+            for batch in samples.chunks(batch_size) {
+                for s in batch {
+                    total += self.backward(s, &mut acc);
+                }
+                self.update(batch.len());
+            }
+            let stats = EpochStats {
+                epoch,
+                train_loss: total / std::cmp::max(samples.len(), 1) as f32,
+                validation_loss: if validation.is_empty() {
+                    None
+                } else {
+                    Some(self.loss(&validation))
+                },
+            };
+            on_epoch(self, stats);
+        }
+    }
+
+    /// Network with the weights rounded to the integers of `nnue::Network`.
+    pub fn quantize(&self) -> Network {
+        let h = self.hidden;
+        let (bias_at, out_at) = (self.bias_at(), self.out_at());
+        let to_i16 = |w: &f32| (w * QA as f32).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        let mut net = Network::zeros(h);
+        net.ft_weights = self.params[..bias_at].iter().map(to_i16).collect();
+        net.ft_bias = self.params[bias_at..out_at].iter().map(to_i16).collect();
+        net.out_weights = self.params[out_at..out_at + 2 * h]
+            .iter()
+            .map(|w| (w * QB as f32).round().clamp(-127.0, 127.0) as i8)
+            .collect();
+        net.out_bias = (self.params[out_at + 2 * h] * (QA * QB) as f32).round() as i32;
+        net
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nnue::tests::full_eval;
+
+    const POSITIONS: [&str; 6] = [
+        "4k3/8/8/8/8/8/PPP5/4K3 w - - 0 1 | 250 | 1-0",
+        "4k3/ppp5/8/8/8/8/8/4K3 w - - 0 1 | -250 | 0-1",
+        "4k3/8/8/8/8/8/8/3QK3 b - - 0 1 | 900 | 1-0",
+        "3qk3/8/8/8/8/8/8/4K3 w - - 0 1 | -900 | 0-1",
+        "4k3/p7/8/8/8/8/P7/4K3 w - - 0 1 | 0 | 1/2-1/2",
+        "4k3/8/8/8/8/8/8/3NK3 b - - 0 1 | 300 | 1/2-1/2",
+    ];
+
+    fn options() -> TrainOptions {
+        TrainOptions {
+            hidden: 8,
+            epochs: 30,
+            batch_size: 2,
+            learning_rate: 0.01,
+            validation: 0.0,
+            ..TrainOptions::default()
+        }
+    }
+
+    fn samples() -> Vec<TrainSample> {
+        POSITIONS.iter().map(|l| parse_sample(l, 0.5).unwrap()).collect()
+    }
+
+    #[test]
+    fn labeled_positions() {
+        let s = parse_sample(POSITIONS[0], 0.5).unwrap();
+        assert!((s.target - (0.5 * sigmoid(250.0) + 0.5)).abs() < 1e-6);
+        // three pawns and both kings
+        assert_eq!(5, s.features[0].len());
+        // targets are for the side to move
+        let s = parse_sample(POSITIONS[2], 1.0).unwrap();
+        assert!((s.target - (1.0 - sigmoid(900.0))).abs() < 1e-6);
+        assert!(parse_sample("4k3/8/8/8/8/8/8/4K3 w 1-0", 0.5).is_none());
+    }
+
+    #[test]
+    fn training_lowers_the_loss() {
+        for &optimizer in [Optimizer::Sgd, Optimizer::Adam].iter() {
+            let options = TrainOptions {
+                optimizer,
+                learning_rate: if optimizer == Optimizer::Sgd { 0.5 } else { 0.01 },
+                ..options()
+            };
+            let mut trainer = Trainer::new(options);
+            let before = trainer.loss(&samples());
+            let mut epochs = 0;
+            trainer.train(samples(), |_, stats| {
+                epochs = stats.epoch;
+                assert!(stats.validation_loss.is_none());
+            });
+            assert_eq!(30, epochs);
+            assert!(trainer.loss(&samples()) < before / 2.0, "{:?}", optimizer);
+        }
+    }
+
+    #[test]
+    fn quantized_network_matches() {
+        let mut trainer = Trainer::new(options());
+        trainer.train(samples(), |_, _| {});
+        // the raw output, without the endgame knowledge of the evaluator
+        let net = trainer.quantize();
+        let mut acc = Vec::new();
+        for line in POSITIONS.iter() {
+            let fields: Vec<_> = line.split(" - ").collect();
+            let (b, c) = parse_fen_color(&mut fields[0].chars()).unwrap();
+            let float = trainer.forward(&parse_sample(line, 0.5).unwrap(), &mut acc);
+            let score = full_eval(&net, &b, c);
+            assert!((score as f32 - float).abs() < 10.0, "{} {} {}", line, score, float);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::board::Piece;
+use crate::move_generator::{Case, Flags, GenMove};
+use crate::score::Score;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const DEFAULT_SIZE_MB: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TtEntry {
+    pub mov: Option<GenMove>,
+    /// Stored relative to the node, see `Score::to_tt`.
+    pub score: Score,
+    pub depth: i32,
+    pub bound: Bound,
+}
+
+impl TtEntry {
+    fn pack(&self) -> u64 {
+        let mov = self.mov.map_or(0u64, |m| {
+            let promotion = match m.promotion {
+                Some(Piece::Knight) => 1,
+                Some(Piece::Bishop) => 2,
+                Some(Piece::Rook) => 3,
+                Some(Piece::Queen) => 4,
+                _ => 0,
+            };
+            1 << 15 | promotion << 12 | (m.to.0 as u64) << 6 | m.from.0 as u64
+        });
+        let bound = match self.bound {
+            Bound::Exact => 1,
+            Bound::Lower => 2,
+            Bound::Upper => 3,
+        };
+        let depth = self.depth.clamp(-128, 127) as i8;
+        mov | (self.score.0 as i16 as u16 as u64) << 16 | (depth as u8 as u64) << 32 | bound << 40
+    }
+
+    fn unpack(data: u64) -> Option<TtEntry> {
+        let bound = match (data >> 40) & 0b11 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            3 => Bound::Upper,
+            _ => return None,
+        };
+        let mov = if data & (1 << 15) == 0 {
+            None
+        } else {
+            let promotion = match (data >> 12) & 0b111 {
+                1 => Some(Piece::Knight),
+                2 => Some(Piece::Bishop),
+                3 => Some(Piece::Rook),
+                4 => Some(Piece::Queen),
+                _ => None,
+            };
+            let from = Case((data & 0x3f) as u8);
+            let to = Case(((data >> 6) & 0x3f) as u8);
+            Some(GenMove::new(from, to, Flags::NONE).promotion(promotion))
+        };
+        Some(TtEntry {
+            mov,
+            score: Score((data >> 16) as u16 as i16 as i32),
+            depth: (data >> 32) as u8 as i8 as i32,
+            bound,
+        })
+    }
+}
+
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+/// Hash table of search results indexed by Zobrist key.
+///
+/// Slots are written without locking: the key is stored xor-ed with the data,
+/// so an entry torn by a concurrent write shows up as a key mismatch.
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        let count = std::cmp::max(size_mb * 1024 * 1024 / std::mem::size_of::<Slot>(), 1);
+        TranspositionTable {
+            slots: (0..count)
+                .map(|_| Slot {
+                    key: AtomicU64::new(0),
+                    data: AtomicU64::new(0),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn slot(&self, key: u64) -> &Slot {
+        &self.slots[((key as u128 * self.slots.len() as u128) >> 64) as usize]
+    }
+
+    pub fn probe(&self, key: u64) -> Option<TtEntry> {
+        let slot = self.slot(key);
+        let data = slot.data.load(Ordering::Relaxed);
+        if slot.key.load(Ordering::Relaxed) ^ data != key {
+            return None;
+        }
+        TtEntry::unpack(data)
+    }
+
+    /// Stores `entry`, unless the slot holds a deeper result for the same position.
+    pub fn store(&self, key: u64, mut entry: TtEntry) {
+        if let Some(old) = self.probe(key) {
+            if entry.bound != Bound::Exact && old.depth > entry.depth {
+                return;
+            }
+            if entry.mov.is_none() {
+                entry.mov = old.mov;
+            }
+        }
+        let slot = self.slot(key);
+        let data = entry.pack();
+        slot.key.store(key ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_probe() {
+        let tt = TranspositionTable::new(1);
+        let entry = TtEntry {
+            mov: Some("e7e8".parse::<GenMove>().unwrap().promotion(Some(Piece::Queen))),
+            score: Score::mated_in(3).to_tt(2),
+            depth: 5,
+            bound: Bound::Upper,
+        };
+        assert_eq!(None, tt.probe(0xdead_beef));
+        tt.store(0xdead_beef, entry);
+        assert_eq!(Some(entry), tt.probe(0xdead_beef));
+        assert_eq!(None, tt.probe(0xdead_bee0));
+
+        // shallower results don't replace deeper ones
+        tt.store(0xdead_beef, TtEntry { depth: 2, ..entry });
+        assert_eq!(Some(entry), tt.probe(0xdead_beef));
+
+        tt.clear();
+        assert_eq!(None, tt.probe(0xdead_beef));
+    }
+}
@@ -0,0 +1,300 @@
+use board::*;
+use evaluate::{self, EvalParams};
+use serde_json::Value;
+use std::path::Path;
+use std::thread;
+
+/// Quiet position with the result of the game it comes from, for White:
+/// 1 for a win, 0.5 for a draw and 0 for a loss.
+pub struct Sample {
+    board: Board,
+    result: f64,
+}
+
+/// Game result for White, from `1-0`, `1/2-1/2`, `0-1` or a number.
+pub fn parse_result(token: &str) -> Option<f64> {
+    match token.trim_matches(|c| c == '"' || c == '[' || c == ']' || c == ';') {
+        "1-0" | "1.0" | "1" => Some(1.0),
+        "1/2-1/2" | "0.5" => Some(0.5),
+        "0-1" | "0.0" | "0" => Some(0.0),
+        _ => None,
+    }
+}
+
+/// Parses `<FEN> <result>`, the result being the last token, as `1-0`,
+/// `1/2-1/2`, `0-1` or a number, possibly quoted or in brackets.
+fn parse_sample(line: &str) -> Option<Sample> {
+    let result = parse_result(line.split_whitespace().last()?)?;
+    let mut tokens = line.split_whitespace();
+    let placement = tokens.next()?;
+    if !placement.chars().all(|c| "/12345678pnbrqkPNBRQK".contains(c)) {
+        return None;
+    }
+    let side = tokens.next().unwrap_or("w");
+    let fen = format!("{} {}", placement, side);
+    let (board, _) = parse_fen_color(&mut fen.chars())?;
+    Some(Sample { board, result })
+}
+
+pub fn load_samples<P: AsRef<Path>>(path: P) -> Result<Vec<Sample>, String> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut samples = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_sample(line) {
+            Some(sample) => samples.push(sample),
+            None => warn!("{}:{}: invalid position {}", path.display(), i + 1, line),
+        }
+    }
+    Ok(samples)
+}
+
+/// Expected result for White of a position evaluated at `score`.
+fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+/// Sections of `EvalParams` read by each evaluator; the others are left
+/// alone.
+fn sections(evaluator: &str) -> &'static [&'static str] {
+    match evaluator {
+        "basic" => &["basic"],
+        _ => &["pst", "pawns", "king", "mobility", "positional"],
+    }
+}
+
+/// Evaluation parameters flattened to a vector, walking their serialized
+/// form, so that adding a weight anywhere makes it tunable.
+struct Parameters {
+    template: Value,
+    sections: &'static [&'static str],
+}
+
+fn collect(v: &Value, out: &mut Vec<i32>) {
+    match v {
+        Value::Number(n) => out.push(n.as_i64().unwrap_or(0) as i32),
+        Value::Array(a) => a.iter().for_each(|x| collect(x, out)),
+        Value::Object(o) => o.values().for_each(|x| collect(x, out)),
+        _ => {}
+    }
+}
+
+fn replace<I: Iterator<Item = i32>>(v: &mut Value, values: &mut I) {
+    match v {
+        Value::Number(_) => *v = Value::from(values.next().unwrap()),
+        Value::Array(a) => a.iter_mut().for_each(|x| replace(x, values)),
+        Value::Object(o) => o.values_mut().for_each(|x| replace(x, values)),
+        _ => {}
+    }
+}
+
+impl Parameters {
+    fn new(params: &EvalParams, evaluator: &str) -> Self {
+        Parameters {
+            template: serde_json::to_value(params).unwrap(),
+            sections: sections(evaluator),
+        }
+    }
+
+    fn values(&self) -> Vec<i32> {
+        let mut values = Vec::new();
+        for section in self.sections {
+            collect(&self.template[section], &mut values);
+        }
+        values
+    }
+
+    fn params(&self, values: &[i32]) -> EvalParams {
+        let mut v = self.template.clone();
+        let mut it = values.iter().cloned();
+        for section in self.sections {
+            replace(&mut v[section], &mut it);
+        }
+        serde_json::from_value(v).unwrap()
+    }
+}
+
+/// Texel tuning: minimizes the squared difference between game results and
+/// the evaluation mapped to an expected result.
+pub struct Tuner {
+    evaluator: String,
+    samples: Vec<Sample>,
+    threads: usize,
+    k: f64,
+}
+
+impl Tuner {
+    pub fn new(evaluator: &str, samples: Vec<Sample>, threads: usize) -> Self {
+        Tuner {
+            evaluator: evaluator.to_lowercase(),
+            samples,
+            threads: std::cmp::max(threads, 1),
+            k: 1.0,
+        }
+    }
+
+    /// Mean squared error of `params` over the samples, split between the
+    /// threads.
+    pub fn error(&self, params: &EvalParams) -> f64 {
+        let eval = evaluate::by_name(&self.evaluator, params).unwrap();
+        let chunk = self.samples.len().div_ceil(self.threads).max(1);
+        let k = self.k;
+        let total: f64 = thread::scope(|s| {
+            let handles: Vec<_> = self
+                .samples
+                .chunks(chunk)
+                .map(|samples| {
+                    let mut eval = eval.box_clone();
+                    s.spawn(move || {
+                        samples
+                            .iter()
+                            .map(|x| {
+                                let score = eval.evaluate(&x.board, Color::White) as f64;
+                                (x.result - sigmoid(score, k)).powi(2)
+                            })
+                            .sum::<f64>()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+        total / std::cmp::max(self.samples.len(), 1) as f64
+    }
+
+    /// Scaling of the sigmoid fitting the results best with `params`, by
+    /// narrowing down the search step. Results perfectly predicted by the
+    /// evaluation would push it up forever, hence the bounded iterations.
+    pub fn fit_k(&mut self, params: &EvalParams) -> f64 {
+        let mut step = 1.0;
+        for _ in 0..100 {
+            if step < 0.001 {
+                break;
+            }
+            let best = self.error(params);
+            let k = self.k;
+            let mut improved = false;
+            for &candidate in [k + step, k - step].iter().filter(|&&x| x > 0.0) {
+                self.k = candidate;
+                if self.error(params) < best {
+                    improved = true;
+                    break;
+                }
+            }
+            if !improved {
+                self.k = k;
+                step /= 2.0;
+            }
+        }
+        self.k
+    }
+
+    /// Local search over the parameters: each one is moved by `step` in both
+    /// directions, and the change kept when it lowers the error. Stops after
+    /// `passes` passes or once no parameter moves; `on_pass` gets the weights
+    /// after each pass.
+    pub fn tune<F>(&self, params: &EvalParams, passes: usize, step: i32, mut on_pass: F) -> EvalParams
+    where
+        F: FnMut(usize, f64, &EvalParams),
+    {
+        let parameters = Parameters::new(params, &self.evaluator);
+        let mut values = parameters.values();
+        let mut best = self.error(params);
+        for pass in 1..=passes {
+            let mut improved = false;
+            for i in 0..values.len() {
+                let original = values[i];
+                for &delta in [step, -step].iter() {
+                    values[i] = original + delta;
+                    let error = self.error(&parameters.params(&values));
+                    if error < best {
+                        best = error;
+                        improved = true;
+                        break;
+                    }
+                    values[i] = original;
+                }
+            }
+            on_pass(pass, best, &parameters.params(&values));
+            if !improved {
+                break;
+            }
+        }
+        parameters.params(&values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples() {
+        let s = parse_sample("4k3/8/8/8/8/8/PPP5/4K3 w - - 0 1 1-0").unwrap();
+        assert_eq!(1.0, s.result);
+        assert_eq!(3, s.board.get_pc_board(Piece::Pawn, Color::White).count_ones());
+        assert_eq!(0.5, parse_sample("4k3/8/8/8/8/8/8/4K3 b - - \"1/2-1/2\";").unwrap().result);
+        assert_eq!(0.0, parse_sample("4k3/8/8/8/8/8/8/4K3 w [0.0]").unwrap().result);
+        assert!(parse_sample("4k3/8/8/8/8/8/8/4K3 w").is_none());
+        assert!(parse_sample("not a position 1-0").is_none());
+    }
+
+    #[test]
+    fn parameters_round_trip() {
+        let params = EvalParams::default();
+        let parameters = Parameters::new(&params, "tapered");
+        let mut values = parameters.values();
+        assert!(values.len() > 1000);
+        assert_eq!(params, parameters.params(&values));
+        values[0] += 1;
+        assert_ne!(params, parameters.params(&values));
+        assert_eq!(params.basic, parameters.params(&values).basic);
+        assert_eq!(6, Parameters::new(&params, "basic").values().len());
+    }
+
+    #[test]
+    fn tuning_lowers_the_error() {
+        // an extra pawn wins every game: the pawn is undervalued
+        let samples: Vec<_> = [
+            "4k3/8/8/8/8/8/PP6/4K3 w 1-0",
+            "4k3/pp6/8/8/8/8/8/4K3 w 0-1",
+            "4k3/p7/8/8/8/8/PP6/4K3 w 1-0",
+            "4k3/pp6/8/8/8/8/P7/4K3 w 0-1",
+            "4k3/p7/8/8/8/8/P7/4K3 w 1/2-1/2",
+        ]
+        .iter()
+        .map(|l| parse_sample(l).unwrap())
+        .collect();
+        let tuner = Tuner::new("basic", samples, 2);
+        let params = EvalParams::default();
+        let before = tuner.error(&params);
+        let mut passes = 0;
+        let tuned = tuner.tune(&params, 3, 5, |_, _, _| passes += 1);
+        assert!(passes > 0);
+        assert!(tuner.error(&tuned) < before);
+        assert!(tuned.basic.material[0] > params.basic.material[0]);
+    }
+
+    #[test]
+    fn sigmoid_scaling() {
+        // an extra knight wins two games out of three
+        let samples: Vec<_> = [
+            "4k3/p7/8/8/8/8/P7/3NK3 w 1-0",
+            "4k3/p7/8/8/8/8/P7/3NK3 w 1-0",
+            "4k3/p7/8/8/8/8/P7/3NK3 w 1/2-1/2",
+        ]
+        .iter()
+        .map(|l| parse_sample(l).unwrap())
+        .collect();
+        let mut tuner = Tuner::new("basic", samples, 1);
+        let params = EvalParams::default();
+        let before = tuner.error(&params);
+        let k = tuner.fit_k(&params);
+        assert!(k > 1.0);
+        assert!(tuner.error(&params) < before);
+        // 30 units map to a 5/6 expected result
+        assert!((sigmoid(30.0, k) - 5.0 / 6.0).abs() < 0.01);
+    }
+}
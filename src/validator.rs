@@ -37,11 +37,7 @@ impl MoveType {
 
 impl Validator {
     fn delta_abs(a: u8, b: u8) -> u8 {
-        if a > b {
-            a - b
-        } else {
-            b - a
-        }
+        a.abs_diff(b)
     }
     fn cmp(a: u8, b: u8) -> i16 {
         if a > b {